@@ -1,8 +1,11 @@
 import httpx
 import time
 import logging
+import socket
 
 POLL_INTERVAL = 2
+# Seconds the server holds a request to /work until a job is available.
+WAIT_TIMEOUT = 60
 
 class JobClient(object):
     def __init__(self,
@@ -12,14 +15,22 @@ class JobClient(object):
         self.base_url = config.base_url
         self.logger = logger
         self.poll_interval = poll_interval
+        self.worker = socket.gethostname()
 
     # this function blocks until a job is available.
     def poll_next_job(self, typ):
         url = f"{self.base_url}/work/{typ}"
         while True:
-            res = httpx.post(url)
+            params = {"worker": self.worker, "wait": WAIT_TIMEOUT}
+            # The server sends whitespace every few seconds while waiting.
+            timeout = httpx.Timeout(10.0, read=30.0)
+            res = httpx.post(url, params=params, timeout=timeout)
             if res.status_code == 200:
                 res = res.json()
+                if res is None:
+                    continue
+                if "error" in res:
+                    raise Exception(res["error"])
                 return res
             elif res.status_code == 204:
                 #  logging.trace("No work to do, waiting and polling")
@@ -2,6 +2,8 @@ use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+use crate::task::TaskState;
+
 pub type JobId = u64;
 pub type JobTyp = String;
 
@@ -27,15 +29,74 @@ impl JobTypId {
     }
 }
 
+/// The state of the latest job of a type on a record.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema, PartialEq)]
+pub struct JobState {
+    #[serde(flatten)]
+    pub state: TaskState,
+    /// The settings the job was created with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub settings: Option<serde_json::Value>,
+}
+
+impl JobState {
+    pub fn new(state: TaskState, settings: Option<serde_json::Value>) -> Self {
+        Self { state, settings }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
 pub struct JobsLog {
     #[serde(default, skip_serializing_if = "SettingsMap::is_empty")]
     settings: SettingsMap,
+    /// The state of the latest job of each type.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    states: HashMap<JobTyp, JobState>,
+    /// The status of the latest job of each type as `type:status` (e.g. `asr:failed`), to
+    /// filter by in the index.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    status: Vec<String>,
 }
 
 impl JobsLog {
     pub fn is_empty(self) -> bool {
-        self.settings.is_empty()
+        self.settings.is_empty() && self.states.is_empty()
+    }
+
+    pub fn state(&self, typ: &str) -> Option<&JobState> {
+        self.states.get(typ)
+    }
+
+    /// Check if the latest job of a type failed.
+    pub fn failed(&self, typ: &str) -> bool {
+        self.state(typ)
+            .map_or(false, |state| state.state.status() == "failed")
+    }
+
+    /// Check if the latest job of a type completed.
+    pub fn completed(&self, typ: &str) -> bool {
+        self.state(typ)
+            .map_or(false, |state| state.state.status() == "completed")
+    }
+
+    pub fn states(&self) -> &HashMap<JobTyp, JobState> {
+        &self.states
+    }
+
+    pub fn status(&self) -> &[String] {
+        &self.status
+    }
+
+    /// Set the state of the latest job of a type.
+    pub fn set_state(&mut self, typ: &str, state: JobState) {
+        self.states.insert(typ.to_string(), state);
+        let mut status: Vec<String> = self
+            .states
+            .iter()
+            .map(|(typ, state)| format!("{}:{}", typ, state.state.status()))
+            .collect();
+        status.sort();
+        self.status = status;
     }
 
     pub fn setting(&self, typ: &str) -> Option<&serde_json::Value> {
@@ -60,3 +121,46 @@ impl JobsLog {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::{TaskFinishedState, TaskQueuedState};
+
+    #[test]
+    fn job_states() {
+        let mut log = JobsLog::default();
+        log.set_state("asr", JobState::new(queued_state("1"), None));
+        log.set_state("nlp", JobState::new(queued_state("2"), None));
+        assert_eq!(log.status(), &["asr:queued", "nlp:queued"]);
+
+        let failed = TaskState::Finished(TaskFinishedState {
+            task_id: "1".into(),
+            success: false,
+            error: Some("out of memory".into()),
+            start: None,
+            end: None,
+            took: 0.,
+            worker: Some("worker-1".into()),
+        });
+        let settings = serde_json::json!({ "model": "small" });
+        log.set_state("asr", JobState::new(failed, Some(settings)));
+        assert_eq!(log.status(), &["asr:failed", "nlp:queued"]);
+        assert!(log.failed("asr"));
+        assert!(!log.failed("nlp"));
+
+        let json = serde_json::to_value(&log).unwrap();
+        assert_eq!(json["states"]["asr"]["state"], "finished");
+        assert_eq!(json["states"]["asr"]["error"], "out of memory");
+        assert_eq!(json["states"]["asr"]["settings"]["model"], "small");
+        let decoded: JobsLog = serde_json::from_value(json).unwrap();
+        assert_eq!(decoded.state("asr"), log.state("asr"));
+    }
+
+    fn queued_state(task_id: &str) -> TaskState {
+        TaskState::Queued(TaskQueuedState {
+            task_id: task_id.into(),
+            queued: chrono::Utc::now(),
+        })
+    }
+}
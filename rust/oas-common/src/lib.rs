@@ -6,6 +6,7 @@ mod record_map;
 pub mod reference;
 pub mod resolver;
 pub mod ser;
+pub mod task;
 pub mod types;
 pub mod util;
 pub use jobs::*;
@@ -1,8 +1,14 @@
 use crate::Object;
-use serde::de::DeserializeOwned;
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use regex::Regex;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde::de::{self, DeserializeOwned};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::Value;
 use std::collections::HashMap;
+use std::fmt;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -13,6 +19,8 @@ pub enum MappingError {
     NotAnArray,
     #[error("Expected an object")]
     NotAnObject,
+    #[error("Invalid value: {0}")]
+    InvalidValue(String),
 }
 
 pub trait Mappable: Sized + DeserializeOwned {
@@ -126,7 +134,8 @@ pub struct FieldMapping {
     into_array: bool,
     #[serde(default)]
     into_single: bool,
-    // regex_match: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    transforms: Vec<Transform>,
     // json_path_match: Option<String>,
 }
 
@@ -136,12 +145,14 @@ impl FieldMapping {
     }
 
     pub fn apply(&self, value: Value) -> Result<Value, MappingError> {
+        let value = apply_transforms(&self.transforms, value)?;
         if self.into_array {
             Ok(Value::Array(vec![value]))
         } else if self.into_single {
             match value {
                 Value::Array(list) if !list.is_empty() => Ok(list.into_iter().next().unwrap()),
                 Value::Array(list) if list.is_empty() => Ok(Value::Null),
+                Value::Null => Ok(Value::Null),
                 _ => Err(MappingError::NotAnArray),
             }
         } else {
@@ -155,11 +166,280 @@ impl FieldMapping {
             target_key: source_key,
             into_array: self.into_single,
             into_single: self.into_array,
+            // Transforms can't be reversed.
+            transforms: vec![],
         };
         (target_key, reversed_mapping)
     }
 }
 
+/// A regular expression that is (de)serialized as a string.
+#[derive(Clone, Debug)]
+pub struct Pattern(Regex);
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        Regex::new(pattern).map(Self)
+    }
+
+    pub fn regex(&self) -> &Regex {
+        &self.0
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Serialize for Pattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        Self::new(&pattern).map_err(de::Error::custom)
+    }
+}
+
+impl JsonSchema for Pattern {
+    fn schema_name() -> String {
+        "Pattern".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+
+    fn is_referenceable() -> bool {
+        false
+    }
+}
+
+/// A transform of a mapped value.
+///
+/// Transforms of text values are applied to each element if the value is a list. A transform
+/// that yields `null` drops the value, which can be replaced with a `default` transform.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+#[serde(tag = "op", rename_all = "camelCase")]
+pub enum Transform {
+    /// Keep the value only if it matches the pattern.
+    When {
+        pattern: Pattern,
+    },
+    /// Extract the first match of the pattern (or one of its capture groups).
+    RegexExtract {
+        pattern: Pattern,
+        #[serde(default)]
+        group: usize,
+    },
+    /// Replace all matches of the pattern. The replacement can refer to capture groups (`$1`).
+    RegexReplace {
+        pattern: Pattern,
+        replacement: String,
+    },
+    /// Split a text into a list.
+    Split {
+        separator: String,
+    },
+    /// Join a list into a text.
+    Join {
+        separator: String,
+    },
+    /// Keep the first element of a list.
+    First,
+    /// Parse a date with one of the formats (in `strftime` syntax), or with the common date
+    /// formats if no formats are set. The result is an RFC 3339 date.
+    Date {
+        #[serde(default)]
+        formats: Vec<String>,
+    },
+    /// Parse a duration as seconds, `MM:SS` or `HH:MM:SS` into seconds.
+    Duration,
+    Lowercase,
+    Trim,
+    /// Use this value if the value is missing or empty.
+    Default {
+        value: Value,
+    },
+}
+
+impl Transform {
+    pub fn apply(&self, value: Value) -> Result<Value, MappingError> {
+        match self {
+            Transform::When { pattern } => map_text(value, |text| {
+                Ok(match pattern.regex().is_match(text) {
+                    true => Value::String(text.to_string()),
+                    false => Value::Null,
+                })
+            }),
+            Transform::RegexExtract { pattern, group } => map_text(value, |text| {
+                let matched = pattern
+                    .regex()
+                    .captures(text)
+                    .and_then(|captures| captures.get(*group))
+                    .map(|m| Value::String(m.as_str().to_string()));
+                Ok(matched.unwrap_or(Value::Null))
+            }),
+            Transform::RegexReplace {
+                pattern,
+                replacement,
+            } => map_text(value, |text| {
+                let replaced = pattern.regex().replace_all(text, replacement.as_str());
+                Ok(Value::String(replaced.to_string()))
+            }),
+            Transform::Split { separator } => {
+                let split = |text: &str| -> Vec<Value> {
+                    text.split(separator.as_str())
+                        .map(str::trim)
+                        .filter(|part| !part.is_empty())
+                        .map(|part| Value::String(part.to_string()))
+                        .collect()
+                };
+                Ok(match value {
+                    Value::String(text) => Value::Array(split(&text)),
+                    Value::Array(list) => Value::Array(
+                        list.into_iter()
+                            .flat_map(|value| match value {
+                                Value::String(text) => split(&text),
+                                value => vec![value],
+                            })
+                            .collect(),
+                    ),
+                    value => value,
+                })
+            }
+            Transform::Join { separator } => Ok(match value {
+                Value::Array(list) => {
+                    let parts: Vec<String> = list
+                        .into_iter()
+                        .filter_map(|value| match value {
+                            Value::String(text) => Some(text),
+                            Value::Null => None,
+                            value => Some(value.to_string()),
+                        })
+                        .collect();
+                    Value::String(parts.join(separator))
+                }
+                value => value,
+            }),
+            Transform::First => Ok(match value {
+                Value::Array(list) => list.into_iter().next().unwrap_or(Value::Null),
+                value => value,
+            }),
+            Transform::Date { formats } => map_text(value, |text| {
+                parse_date(text, formats)
+                    .map(|date| Value::String(date.to_rfc3339()))
+                    .ok_or_else(|| MappingError::InvalidValue(format!("Invalid date: {}", text)))
+            }),
+            Transform::Duration => match value {
+                Value::Number(_) => Ok(value),
+                value => map_text(value, |text| {
+                    parse_duration(text)
+                        .and_then(serde_json::Number::from_f64)
+                        .map(Value::Number)
+                        .ok_or_else(|| {
+                            MappingError::InvalidValue(format!("Invalid duration: {}", text))
+                        })
+                }),
+            },
+            Transform::Lowercase => map_text(value, |text| Ok(Value::String(text.to_lowercase()))),
+            Transform::Trim => map_text(value, |text| Ok(Value::String(text.trim().to_string()))),
+            Transform::Default { value: default } => Ok(match value {
+                Value::Null => default.clone(),
+                Value::String(text) if text.is_empty() => default.clone(),
+                Value::Array(list) if list.is_empty() => default.clone(),
+                value => value,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = serde_json::to_value(self).map_err(|_| fmt::Error)?;
+        write!(f, "{}", value)
+    }
+}
+
+/// Apply a list of transforms to a value.
+pub fn apply_transforms(transforms: &[Transform], value: Value) -> Result<Value, MappingError> {
+    transforms
+        .iter()
+        .try_fold(value, |value, transform| transform.apply(value))
+}
+
+/// Apply a function to a text value or to all text values of a list.
+///
+/// Other values are returned unchanged, and `null` results are removed from lists.
+fn map_text<F>(value: Value, f: F) -> Result<Value, MappingError>
+where
+    F: Fn(&str) -> Result<Value, MappingError>,
+{
+    match value {
+        Value::String(text) => f(&text),
+        Value::Array(list) => {
+            let mut result = vec![];
+            for value in list.into_iter() {
+                let value = match value {
+                    Value::String(text) => f(&text)?,
+                    value => value,
+                };
+                if !value.is_null() {
+                    result.push(value);
+                }
+            }
+            Ok(Value::Array(result))
+        }
+        value => Ok(value),
+    }
+}
+
+fn parse_date(text: &str, formats: &[String]) -> Option<DateTime<Utc>> {
+    let text = text.trim();
+    if formats.is_empty() {
+        return DateTime::parse_from_rfc2822(text)
+            .or_else(|_| DateTime::parse_from_rfc3339(text))
+            .ok()
+            .or_else(|| diligent_date_parser::parse_date(text))
+            .map(|date| date.with_timezone(&Utc));
+    }
+    formats.iter().find_map(|format| {
+        if let Ok(date) = DateTime::parse_from_str(text, format) {
+            Some(date.with_timezone(&Utc))
+        } else if let Ok(date) = NaiveDateTime::parse_from_str(text, format) {
+            Some(Utc.from_utc_datetime(&date))
+        } else if let Ok(date) = NaiveDate::parse_from_str(text, format) {
+            date.and_hms_opt(0, 0, 0)
+                .map(|date| Utc.from_utc_datetime(&date))
+        } else {
+            None
+        }
+    })
+}
+
+/// Parse a duration in seconds from a number of seconds or from `HH:MM:SS` or `MM:SS`.
+pub fn parse_duration(text: &str) -> Option<f64> {
+    let text = text.trim();
+    if let Ok(seconds) = text.parse::<f64>() {
+        return Some(seconds);
+    }
+    let parts: Vec<&str> = text.split(':').collect();
+    if parts.len() > 3 {
+        return None;
+    }
+    parts.iter().try_fold(0., |total, part| {
+        part.trim()
+            .parse::<f64>()
+            .ok()
+            .map(|part| total * 60. + part)
+    })
+}
+
 // pub struct FieldMappingBuilder {
 //     target_key: String,
 //     into_array: bool,
@@ -190,7 +470,7 @@ impl FieldMapping {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use serde_json::Value;
+    use serde_json::{json, Value};
     #[test]
     fn it_works() {
         let source = r#"
@@ -248,6 +528,112 @@ mod tests {
         assert!(reversed_target == expected);
     }
 
+    fn transform(json: &str) -> Transform {
+        serde_json::from_str(json).expect("failed to parse transform")
+    }
+
+    #[test]
+    fn regex_extract() {
+        let t = transform(r#"{"op": "regexExtract", "pattern": "Folge (\\d+)", "group": 1}"#);
+        assert_eq!(t.apply(json!("Podcast Folge 12")).unwrap(), json!("12"));
+        assert_eq!(t.apply(json!("Podcast")).unwrap(), Value::Null);
+        assert!(
+            serde_json::from_str::<Transform>(r#"{"op": "regexExtract", "pattern": "("}"#).is_err()
+        );
+    }
+
+    #[test]
+    fn regex_replace() {
+        let t = transform(r#"{"op": "regexReplace", "pattern": "\\s+", "replacement": " "}"#);
+        assert_eq!(t.apply(json!("a  b\n c")).unwrap(), json!("a b c"));
+    }
+
+    #[test]
+    fn split_and_join() {
+        let t = transform(r#"{"op": "split", "separator": ","}"#);
+        assert_eq!(t.apply(json!("a, b,,c")).unwrap(), json!(["a", "b", "c"]));
+        let t = transform(r#"{"op": "join", "separator": "; "}"#);
+        assert_eq!(t.apply(json!(["a", "b"])).unwrap(), json!("a; b"));
+        let t = transform(r#"{"op": "first"}"#);
+        assert_eq!(t.apply(json!(["a", "b"])).unwrap(), json!("a"));
+        assert_eq!(t.apply(json!("a")).unwrap(), json!("a"));
+        assert_eq!(t.apply(json!([])).unwrap(), Value::Null);
+    }
+
+    #[test]
+    fn date() {
+        let t = transform(r#"{"op": "date", "formats": ["%d.%m.%Y %H:%M", "%d.%m.%Y"]}"#);
+        assert_eq!(
+            t.apply(json!("24.12.2021 18:30")).unwrap(),
+            json!("2021-12-24T18:30:00+00:00")
+        );
+        assert_eq!(
+            t.apply(json!("24.12.2021")).unwrap(),
+            json!("2021-12-24T00:00:00+00:00")
+        );
+        assert!(t.apply(json!("yesterday")).is_err());
+        let t = transform(r#"{"op": "date"}"#);
+        assert_eq!(
+            t.apply(json!("Fri, 24 Dec 2021 18:30:00 +0100")).unwrap(),
+            json!("2021-12-24T17:30:00+00:00")
+        );
+    }
+
+    #[test]
+    fn duration() {
+        let t = transform(r#"{"op": "duration"}"#);
+        assert_eq!(t.apply(json!("01:02:03")).unwrap(), json!(3723.0));
+        assert_eq!(t.apply(json!("02:03")).unwrap(), json!(123.0));
+        assert_eq!(t.apply(json!("42.5")).unwrap(), json!(42.5));
+        assert_eq!(t.apply(json!(10)).unwrap(), json!(10));
+        assert!(t.apply(json!("long")).is_err());
+    }
+
+    #[test]
+    fn lowercase_and_trim() {
+        let t = transform(r#"{"op": "lowercase"}"#);
+        assert_eq!(t.apply(json!(["DE", "En"])).unwrap(), json!(["de", "en"]));
+        let t = transform(r#"{"op": "trim"}"#);
+        assert_eq!(t.apply(json!("  x ")).unwrap(), json!("x"));
+    }
+
+    #[test]
+    fn default_value() {
+        let t = transform(r#"{"op": "default", "value": "unknown"}"#);
+        assert_eq!(t.apply(Value::Null).unwrap(), json!("unknown"));
+        assert_eq!(t.apply(json!("")).unwrap(), json!("unknown"));
+        assert_eq!(t.apply(json!("known")).unwrap(), json!("known"));
+    }
+
+    #[test]
+    fn conditional() {
+        let transforms: Vec<Transform> = serde_json::from_str(
+            r#"[
+                {"op": "when", "pattern": "^cc-"},
+                {"op": "default", "value": "all rights reserved"}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            apply_transforms(&transforms, json!("cc-by")).unwrap(),
+            json!("cc-by")
+        );
+        assert_eq!(
+            apply_transforms(&transforms, json!("other")).unwrap(),
+            json!("all rights reserved")
+        );
+    }
+
+    #[test]
+    fn field_map_transforms() {
+        let field_map: FieldMap = serde_json::from_str(
+            r#"{"tags": {"targetKey": "genre", "transforms": [{"op": "split", "separator": ","}]}}"#,
+        )
+        .unwrap();
+        let target: Value = Value::from_json(r#"{"tags": "a,b"}"#, &field_map).unwrap();
+        assert_eq!(target, json!({"genre": ["a", "b"]}));
+    }
+
     fn reserialize(input: &str) -> String {
         let output: Value = serde_json::from_str(input).expect("failed to parse");
         serde_json::to_string(&output).expect("failed to serialize")
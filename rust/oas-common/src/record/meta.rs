@@ -1,3 +1,4 @@
+use crate::types::ItemKeys;
 use crate::{ElasticMapping, JobsLog};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
@@ -17,6 +18,12 @@ pub struct RecordMeta {
     pub(crate) id: String,
     #[serde(default)]
     pub(crate) jobs: JobsLog,
+    /// Hash of the value as it was last mapped from its source (e.g. a feed item).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) source_hash: Option<String>,
+    /// Values of the feed item the record was created from that may identify the item.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) item_keys: Option<ItemKeys>,
 }
 
 impl RecordMeta {
@@ -27,6 +34,22 @@ impl RecordMeta {
     pub fn jobs_mut(&mut self) -> &mut JobsLog {
         &mut self.jobs
     }
+
+    pub fn source_hash(&self) -> Option<&str> {
+        self.source_hash.as_deref()
+    }
+
+    pub fn set_source_hash(&mut self, hash: Option<String>) {
+        self.source_hash = hash;
+    }
+
+    pub fn item_keys(&self) -> Option<&ItemKeys> {
+        self.item_keys.as_ref()
+    }
+
+    pub fn set_item_keys(&mut self, keys: Option<ItemKeys>) {
+        self.item_keys = keys;
+    }
 }
 
 impl ElasticMapping for RecordMeta {
@@ -41,6 +64,17 @@ impl ElasticMapping for RecordMeta {
             "typ": {
                 "type": "keyword"
             },
+            "jobs": {
+                "properties": {
+                    "states": {
+                        "type": "object",
+                        "enabled": false
+                    },
+                    "status": {
+                        "type": "keyword"
+                    }
+                }
+            },
         })
     }
 }
@@ -64,6 +64,14 @@ where
         Self { meta, value }
     }
 
+    /// Move this record to a new id while keeping its value and metadata.
+    pub fn with_id(mut self, id: impl ToString) -> Self {
+        let id = id.to_string();
+        self.meta.guid = format!("{}_{}", self.meta.typ, id);
+        self.meta.id = id;
+        self
+    }
+
     /// Convert this record into an [UntypedRecord].
     ///
     /// This can be unwrapped by default as it only fails if the record value would not serialize
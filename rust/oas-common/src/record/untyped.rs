@@ -93,6 +93,16 @@ impl UntypedRecord {
         &mut self.meta
     }
 
+    /// Get a reference to the record value.
+    pub fn value(&self) -> &JsonObject {
+        &self.value
+    }
+
+    /// Get a mutable reference to the record value.
+    pub fn value_mut(&mut self) -> &mut JsonObject {
+        &mut self.value
+    }
+
     /// Merge this record's value with another JSON value.
     pub fn merge_json_value(
         &mut self,
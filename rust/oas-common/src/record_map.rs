@@ -1,4 +1,4 @@
-use crate::types::{Feed, Media, Post};
+use crate::types::{Feed, Media, Post, Show};
 use crate::{Record, TypedValue, UntypedRecord};
 use std::any::Any;
 use std::collections::HashMap;
@@ -21,6 +21,7 @@ impl RecordMap {
                 Media::NAME => self.insert_untyped::<Media>(record)?,
                 Post::NAME => self.insert_untyped::<Post>(record)?,
                 Feed::NAME => self.insert_untyped::<Feed>(record)?,
+                Show::NAME => self.insert_untyped::<Show>(record)?,
                 _ => {}
             }
         }
@@ -6,17 +6,42 @@ use crate::TypedValue;
 
 pub type TaskId = String;
 
-#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema, PartialEq)]
 #[serde(rename_all = "camelCase")]
 #[serde(tag = "state")]
 pub enum TaskState {
     None,
     Wanted,
     // WaitingFor(OtherTaskToFinish),
+    Queued(TaskQueuedState),
     Running(TaskRunningState),
     Finished(TaskFinishedState),
 }
 
+impl TaskState {
+    /// A short status string: `queued`, `running`, `completed` or `failed` (or `none` and
+    /// `wanted`).
+    pub fn status(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Wanted => "wanted",
+            Self::Queued(_) => "queued",
+            Self::Running(_) => "running",
+            Self::Finished(state) if state.success => "completed",
+            Self::Finished(_) => "failed",
+        }
+    }
+
+    pub fn task_id(&self) -> Option<&str> {
+        match self {
+            Self::None | Self::Wanted => None,
+            Self::Queued(state) => Some(&state.task_id),
+            Self::Running(state) => Some(&state.task_id),
+            Self::Finished(state) => Some(&state.task_id),
+        }
+    }
+}
+
 impl std::default::Default for TaskState {
     fn default() -> Self {
         Self::None
@@ -28,7 +53,7 @@ pub struct TaskFinishedModel {
     pub result: serde_json::Value,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct TaskFinishedState {
     pub task_id: String,
@@ -40,13 +65,24 @@ pub struct TaskFinishedState {
     // Time in seconds
     #[serde(default)]
     pub took: f32,
+    #[serde(default)]
+    pub worker: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskQueuedState {
+    pub task_id: String,
+    pub queued: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct TaskRunningState {
     pub task_id: String,
     pub start: DateTime<Utc>,
+    #[serde(default)]
+    pub worker: Option<String>,
 }
 
 pub trait TaskObject: TypedValue {
@@ -1,11 +1,17 @@
 use crate::jobs::SettingsMap;
 use crate::mapping::Mappable;
 use crate::record::{TypedValue, ValidationError};
+use crate::util::id_from_hashed_string;
+use chrono::{DateTime, Utc};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use url::Url;
 
 pub const DEFAULT_CHECK_INTERVAL: u64 = 3600;
+pub const DEFAULT_MIN_INTERVAL: u64 = 900;
+pub const DEFAULT_MAX_INTERVAL: u64 = 7 * 24 * 3600;
+/// Lower bound for adaptive check intervals (in seconds).
+pub const MIN_CHECK_INTERVAL: u64 = 60;
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
 #[serde(rename_all = "camelCase")]
@@ -16,6 +22,13 @@ pub struct Feed {
     pub media_jobs: SettingsMap,
     #[serde(default)]
     pub post_jobs: SettingsMap,
+    /// Previous URLs of the feed, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    /// New URL the feed announced (by a permanent redirect or `itunes:new-feed-url`)
+    /// that was not moved to yet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub moved_to: Option<String>,
 }
 
 impl Feed {
@@ -25,6 +38,17 @@ impl Feed {
             ..Default::default()
         }
     }
+
+    /// The URL that scopes item IDs. This is the original URL of the feed, so that IDs
+    /// stay stable when the feed moves.
+    pub fn identity_scope(&self) -> &str {
+        self.aliases.first().unwrap_or(&self.url)
+    }
+
+    /// Whether the feed is or was served at a URL.
+    pub fn has_url(&self, url: &str) -> bool {
+        self.url == url || self.aliases.iter().any(|alias| alias == url)
+    }
 }
 
 impl TypedValue for Feed {
@@ -32,6 +56,13 @@ impl TypedValue for Feed {
 
     fn validate(&self) -> Result<(), ValidationError> {
         let _url = Url::parse(&self.url)?;
+        if let Some(bounds) = self
+            .settings
+            .as_ref()
+            .and_then(|settings| settings.adaptive_interval.as_ref())
+        {
+            bounds.validate()?;
+        }
         Ok(())
     }
 }
@@ -46,6 +77,21 @@ pub struct FeedSettings {
     /// Try to crawl the feed backwards by increasing an offset query parameter
     #[serde(default)]
     pub crawl_backwards: bool,
+    /// Derive the check interval from the publishing frequency of the feed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adaptive_interval: Option<AdaptiveInterval>,
+    /// Do not fetch the feed until unpaused
+    #[serde(default)]
+    pub paused: bool,
+    /// How to derive stable IDs for the items of the feed
+    #[serde(default)]
+    pub identity: ItemIdentity,
+    /// Name of the mapping profile for the feed's items (default: selected by domain)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mapping: Option<String>,
+    /// Move the feed to its new URL automatically when it announces a move
+    #[serde(default)]
+    pub follow_moves: bool,
 }
 
 impl Default for FeedSettings {
@@ -53,6 +99,161 @@ impl Default for FeedSettings {
         Self {
             check_interval: DEFAULT_CHECK_INTERVAL,
             crawl_backwards: false,
+            adaptive_interval: None,
+            paused: false,
+            identity: ItemIdentity::default(),
+            mapping: None,
+            follow_moves: false,
+        }
+    }
+}
+
+/// Bounds for adaptive check intervals.
+///
+/// If set on a feed, the check interval is derived from the gaps between the publication dates
+/// of the feed's posts and kept within `min_interval` and `max_interval` (both in seconds).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AdaptiveInterval {
+    #[serde(default = "AdaptiveInterval::default_min")]
+    pub min_interval: u64,
+    #[serde(default = "AdaptiveInterval::default_max")]
+    pub max_interval: u64,
+}
+
+impl AdaptiveInterval {
+    fn default_min() -> u64 {
+        DEFAULT_MIN_INTERVAL
+    }
+    fn default_max() -> u64 {
+        DEFAULT_MAX_INTERVAL
+    }
+
+    /// Check that the bounds are ordered and not below [MIN_CHECK_INTERVAL].
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        if self.min_interval < MIN_CHECK_INTERVAL {
+            return Err(ValidationError::with_message(format!(
+                "The minimum check interval must be at least {} seconds",
+                MIN_CHECK_INTERVAL
+            )));
+        }
+        if self.min_interval > self.max_interval {
+            return Err(ValidationError::with_message(
+                "The minimum check interval must not be greater than the maximum".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for AdaptiveInterval {
+    fn default() -> Self {
+        Self {
+            min_interval: DEFAULT_MIN_INTERVAL,
+            max_interval: DEFAULT_MAX_INTERVAL,
+        }
+    }
+}
+
+/// Strategy to derive the ID of posts from feed items.
+///
+/// The first key in `keys` that is present on an item is hashed into the ID. If `scoped` is
+/// true, the (original) feed URL is hashed together with the key, so that items of different feeds never
+/// collide. The default (unscoped, GUID first) keeps the IDs of existing posts stable.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ItemIdentity {
+    #[serde(default)]
+    pub scoped: bool,
+    #[serde(default = "ItemIdentity::default_keys")]
+    pub keys: Vec<IdentityKey>,
+}
+
+impl ItemIdentity {
+    fn default_keys() -> Vec<IdentityKey> {
+        vec![
+            IdentityKey::Guid,
+            IdentityKey::Enclosure,
+            IdentityKey::Link,
+            IdentityKey::TitleDate,
+        ]
+    }
+
+    /// Feed-scoped identity with the default fallback keys.
+    pub fn scoped() -> Self {
+        Self {
+            scoped: true,
+            keys: Self::default_keys(),
         }
     }
 }
+
+impl Default for ItemIdentity {
+    fn default() -> Self {
+        Self {
+            scoped: false,
+            keys: Self::default_keys(),
+        }
+    }
+}
+
+/// A value of a feed item that can be used to identify the item.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum IdentityKey {
+    /// The item's `<guid>`
+    Guid,
+    /// The URL of the item's enclosure
+    Enclosure,
+    /// The item's `<link>`
+    Link,
+    /// The item's title together with its publication date
+    TitleDate,
+}
+
+/// The values of a feed item that may be used to identify it.
+///
+/// The keys are stored in the metadata of the posts that are created from feed items, so
+/// that the posts can be migrated to another [ItemIdentity].
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ItemKeys {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub guid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enclosure: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date: Option<DateTime<Utc>>,
+}
+
+impl ItemKeys {
+    fn get(&self, key: IdentityKey) -> Option<String> {
+        let value = match key {
+            IdentityKey::Guid => self.guid.clone(),
+            IdentityKey::Enclosure => self.enclosure.clone(),
+            IdentityKey::Link => self.link.clone(),
+            IdentityKey::TitleDate => match (&self.title, &self.date) {
+                (Some(title), Some(date)) => Some(format!("{}|{}", title, date.to_rfc3339())),
+                _ => None,
+            },
+        };
+        value.filter(|value| !value.trim().is_empty())
+    }
+
+    /// Derive the item id. `scope` is the URL of the feed the item belongs to.
+    ///
+    /// Returns `None` if none of the configured keys is present on the item.
+    pub fn id(&self, identity: &ItemIdentity, scope: &str) -> Option<String> {
+        let value = identity.keys.iter().find_map(|key| self.get(*key))?;
+        let id = if identity.scoped {
+            id_from_hashed_string(format!("{}|{}", scope, value))
+        } else {
+            id_from_hashed_string(value)
+        };
+        Some(id)
+    }
+}
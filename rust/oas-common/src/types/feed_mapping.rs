@@ -0,0 +1,45 @@
+use crate::mapping::Transform;
+use crate::record::{TypedValue, ValidationError};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// A named mapping profile for feed items. The record ID is the profile name.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FeedMapping {
+    /// Name of a profile whose fields are inherited. Fields of this profile take precedence.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+    /// Domain patterns of feeds that use this profile if their settings don't select a profile.
+    #[serde(default)]
+    pub domains: Vec<String>,
+    #[serde(default)]
+    pub fields: Vec<MappingField>,
+}
+
+/// Maps the value of a feed item field (e.g. `cba:teaser`) to a post field (e.g. `abstract`)
+/// or a media field (e.g. `media.duration`).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MappingField {
+    pub from: String,
+    pub to: String,
+    /// Transforms that are applied to the value in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transforms: Vec<Transform>,
+}
+
+impl TypedValue for FeedMapping {
+    const NAME: &'static str = "oas.FeedMapping";
+
+    fn validate(&self) -> Result<(), ValidationError> {
+        for field in self.fields.iter() {
+            if field.from.trim().is_empty() || field.to.trim().is_empty() {
+                return Err(ValidationError::with_message(
+                    "Mapping fields must not be empty".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
@@ -22,6 +22,9 @@ pub struct Media {
     pub duration: Option<f32>,
     pub transcript: Option<Transcript>,
     pub nlp: Option<Value>,
+    /// Chapter marks of this media (segments with type `chapter`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chapters: Vec<Segment>,
 
     #[serde(flatten)]
     pub other: serde_json::Map<String, Value>,
@@ -79,6 +82,10 @@ impl ElasticMapping for Media {
                 "type": "object",
                 "enabled": false
             },
+            "chapters": {
+                "type": "object",
+                "enabled": false
+            },
             "contentUrl":{
                 "type": "keyword"
             },
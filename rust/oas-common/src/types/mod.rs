@@ -1,8 +1,16 @@
 mod feed;
+mod feed_mapping;
 mod media;
+mod oai_source;
 mod post;
+mod show;
 
 pub use feed::Feed;
-pub use feed::FeedSettings;
-pub use media::{Media, Transcript, TranscriptPart};
+pub use feed::{
+    AdaptiveInterval, FeedSettings, IdentityKey, ItemIdentity, ItemKeys, MIN_CHECK_INTERVAL,
+};
+pub use feed_mapping::{FeedMapping, MappingField};
+pub use media::{Media, Segment, Transcript, TranscriptPart};
+pub use oai_source::OaiSource;
 pub use post::Post;
+pub use show::Show;
@@ -0,0 +1,74 @@
+use crate::jobs::SettingsMap;
+use crate::record::{TypedValue, ValidationError};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+pub const DEFAULT_METADATA_PREFIX: &str = "oai_dc";
+
+/// An OAI-PMH repository (e.g. a broadcast archive) whose records are harvested into posts
+/// and medias.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OaiSource {
+    /// Base URL of the OAI-PMH interface
+    pub url: String,
+    /// Metadata format to harvest
+    #[serde(default = "OaiSource::default_metadata_prefix")]
+    pub metadata_prefix: String,
+    /// Only harvest the records of this set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub set: Option<String>,
+    /// Name of the mapping profile for the records (default: `oai_dc`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mapping: Option<String>,
+    /// Date of the last complete harvest. The next harvest only requests records that
+    /// changed since.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub harvested_until: Option<String>,
+    #[serde(default)]
+    pub media_jobs: SettingsMap,
+    #[serde(default)]
+    pub post_jobs: SettingsMap,
+}
+
+impl OaiSource {
+    pub fn with_url(url: String) -> Self {
+        Self {
+            url,
+            metadata_prefix: Self::default_metadata_prefix(),
+            set: None,
+            mapping: None,
+            harvested_until: None,
+            media_jobs: Default::default(),
+            post_jobs: Default::default(),
+        }
+    }
+
+    fn default_metadata_prefix() -> String {
+        DEFAULT_METADATA_PREFIX.to_string()
+    }
+
+    /// The string the ID of the source is derived from. A repository can be harvested as
+    /// several sources with different sets.
+    pub fn identity(&self) -> String {
+        match &self.set {
+            Some(set) => format!("{}#{}", self.url, set),
+            None => self.url.clone(),
+        }
+    }
+}
+
+impl TypedValue for OaiSource {
+    const NAME: &'static str = "oas.OaiSource";
+
+    fn validate(&self) -> Result<(), ValidationError> {
+        let _url = Url::parse(&self.url)?;
+        if self.metadata_prefix.trim().is_empty() {
+            return Err(ValidationError::with_message(
+                "The metadata prefix must not be empty".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
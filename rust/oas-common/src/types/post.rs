@@ -1,4 +1,4 @@
-use super::{Feed, Media};
+use super::{Feed, Media, Show};
 use crate::mapping::Mappable;
 use crate::record::TypedValue;
 use crate::reference::{self, Reference};
@@ -32,6 +32,24 @@ pub struct Post {
 
     pub publisher: Option<String>,
 
+    /// URL of the artwork of the episode (or of its feed)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub episode_number: Option<u32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub season_number: Option<u32>,
+
+    /// Type of the episode, `full`, `trailer` or `bonus`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub episode_type: Option<String>,
+
+    /// Whether the episode contains explicit content
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explicit: Option<bool>,
+
     #[serde(default, deserialize_with = "ser::deserialize_multiple")]
     pub genre: Vec<String>,
 
@@ -44,6 +62,10 @@ pub struct Post {
     #[serde(default)]
     pub feeds: Vec<Reference<Feed>>,
 
+    /// The shows of the feeds of this post
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub shows: Vec<Reference<Show>>,
+
     pub transcript: Option<String>,
 
     #[serde(default)]
@@ -179,6 +201,25 @@ impl ElasticMapping for Post {
             },
             "feeds": {
                 "type":"keyword",
+            },
+            "shows": {
+                "type":"keyword",
+            },
+            "image": {
+                "type": "keyword",
+                "index": false
+            },
+            "episodeNumber": {
+                "type": "integer"
+            },
+            "seasonNumber": {
+                "type": "integer"
+            },
+            "episodeType": {
+                "type": "keyword"
+            },
+            "explicit": {
+                "type": "boolean"
             }
         })
     }
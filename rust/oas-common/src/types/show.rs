@@ -0,0 +1,93 @@
+use crate::mapping::Mappable;
+use crate::record::TypedValue;
+use crate::{ElasticMapping, Reference};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::Feed;
+
+/// A show (podcast or programme) with the channel metadata of a feed.
+///
+/// Shows are updated from the channel of their feed on every fetch and referenced by the
+/// posts of the feed. The ID of a show is derived from the original URL of its feed, so a
+/// show is kept when the feed moves.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Show {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// URL of the artwork of the show
+    pub image: Option<String>,
+    pub in_language: Option<String>,
+    pub author: Option<String>,
+    /// Website of the show
+    pub url: Option<String>,
+    #[serde(default)]
+    pub categories: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explicit: Option<bool>,
+    #[serde(default)]
+    pub feeds: Vec<Reference<Feed>>,
+}
+
+impl TypedValue for Show {
+    const NAME: &'static str = "oas.Show";
+}
+
+impl Mappable for Show {}
+
+impl ElasticMapping for Show {
+    fn elastic_mapping() -> serde_json::Value {
+        json!({
+            "title": {
+                "type": "text",
+                "analyzer": "german",
+                "fields": {
+                    "keyword": {
+                        "type": "keyword",
+                        "ignore_above": 256
+                    }
+                }
+            },
+            "description": {
+                "type": "text",
+                "analyzer": "german",
+            },
+            "image": {
+                "type": "keyword",
+                "index": false
+            },
+            "inLanguage": {
+                "type": "keyword"
+            },
+            "author": {
+                "type": "text",
+                "fields": {
+                    "keyword": {
+                        "type": "keyword",
+                        "ignore_above": 256
+                    }
+                }
+            },
+            "url": {
+                "type": "keyword"
+            },
+            "categories": {
+                "type": "text",
+                "fields": {
+                    "keyword": {
+                        "type": "keyword",
+                        "ignore_above": 256
+                    }
+                }
+            },
+            "explicit": {
+                "type": "boolean"
+            },
+            "feeds": {
+                "type": "keyword"
+            }
+        })
+    }
+}
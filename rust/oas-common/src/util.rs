@@ -1,3 +1,4 @@
+use serde_json::Value;
 use sha2::Digest;
 use uuid::Uuid;
 
@@ -43,3 +44,21 @@ pub fn id_from_uuid() -> String {
     let encoded = base32::encode(base32::Alphabet::Crockford, &uuid.as_bytes()[0..16]);
     encoded.to_lowercase()
 }
+
+/// Sort the keys of all objects in a JSON value, to hash it independent of key order.
+pub fn sorted_json(value: Value) -> Value {
+    match value {
+        Value::Object(object) => {
+            let mut entries: Vec<_> = object.into_iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, sorted_json(value)))
+                    .collect(),
+            )
+        }
+        Value::Array(values) => Value::Array(values.into_iter().map(sorted_json).collect()),
+        value => value,
+    }
+}
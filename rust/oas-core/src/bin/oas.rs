@@ -1,8 +1,8 @@
 use anyhow::Context;
 use clap::Parser;
 use futures::stream::StreamExt;
-use oas_common::types::Media;
-use oas_common::Record;
+use oas_common::types::{FeedMapping, ItemIdentity, Media, OaiSource};
+use oas_common::{Record, TypedValue};
 use oas_core::rss::manager::FeedManagerOpts;
 use oas_core::server::{run_server, ServerOpts};
 use oas_core::types::Post;
@@ -29,10 +29,18 @@ struct Args {
     #[clap(long, env = "COUCHDB_URL")]
     pub couchdb_url: Option<String>,
 
-    /// Redis URL
+    /// Ocypod URL
     #[clap(long, env = "OCYPOD_URL")]
     pub ocypod_url: Option<String>,
 
+    /// Job queue backend: "ocypod" (default) or "couchdb" (does not need Ocypod and Redis)
+    #[clap(long, env = "JOB_QUEUE")]
+    pub job_queue: Option<String>,
+
+    /// Path to jobs config file (queue settings and pipelines)
+    #[clap(long, env = "JOBS_CONFIG_FILE")]
+    pub jobs_config_file: Option<String>,
+
     /// Bind HTTP server to host
     #[clap(long, env = "HTTP_HOST")]
     pub http_host: Option<String>,
@@ -41,10 +49,34 @@ struct Args {
     #[clap(long, env = "HTTP_PORT")]
     pub http_port: Option<u16>,
 
-    /// Path to mapping file
+    /// Path to mapping file (seeds the mapping profiles if the database has none)
     #[clap(long, env = "MAPPING_FILE")]
     pub mapping_file: Option<String>,
 
+    /// Path to crawl rules file
+    #[clap(long, env = "CRAWL_RULES_FILE")]
+    pub crawl_rules_file: Option<String>,
+
+    /// Max number of feeds to fetch concurrently
+    #[clap(long, env = "FEED_CONCURRENCY")]
+    pub feed_concurrency: Option<usize>,
+
+    /// Min delay between requests to the same host (in seconds)
+    #[clap(long, env = "FEED_HOST_DELAY")]
+    pub feed_host_delay: Option<u64>,
+
+    /// Timeout for feed requests (in seconds)
+    #[clap(long, env = "FEED_TIMEOUT")]
+    pub feed_timeout: Option<u64>,
+
+    /// User agent for feed requests
+    #[clap(long, env = "FEED_USER_AGENT")]
+    pub feed_user_agent: Option<String>,
+
+    /// Public URL of the API for WebSub callbacks (WebSub is disabled if not set)
+    #[clap(long, env = "WEBSUB_CALLBACK_URL")]
+    pub websub_callback_url: Option<String>,
+
     /// Enable developer mode defaults
     #[clap(long)]
     pub dev: bool,
@@ -63,6 +95,10 @@ enum Command {
     Search(SearchOpts),
     /// Fetch a RSS feed
     Feed(FeedCommands),
+    /// Manage mapping profiles for feeds
+    Mapping(MappingCommands),
+    /// Harvest OAI-PMH repositories
+    Oai(OaiCommands),
     /// Job commands
     Job(jobs::bin::JobOpts),
     /// Run the HTTP API server
@@ -80,6 +116,62 @@ struct FeedCommands {
     command: FeedCommand,
 }
 
+#[derive(Parser, Debug)]
+struct MappingCommands {
+    /// Subcommand
+    #[clap(subcommand)]
+    command: MappingCommand,
+}
+
+#[derive(Parser, Debug)]
+struct OaiCommands {
+    /// Subcommand
+    #[clap(subcommand)]
+    command: OaiCommand,
+}
+
+#[derive(Parser, Debug)]
+struct HarvestSourceOpts {
+    /// Source ID or URL
+    id_or_url: String,
+    #[clap(flatten)]
+    opts: rss::oai::HarvestOpts,
+}
+
+#[derive(Parser, Debug)]
+enum OaiCommand {
+    /// Add an OAI-PMH source
+    Add(rss::oai::AddSourceOpts),
+    /// List all OAI-PMH sources
+    List,
+    /// Harvest an OAI-PMH source
+    Harvest(HarvestSourceOpts),
+}
+
+#[derive(Parser, Debug)]
+struct MappingNameOpts {
+    /// Name of the mapping profile
+    name: String,
+}
+
+#[derive(Parser, Debug)]
+struct MappingFileOpts {
+    /// TOML file with mapping profiles (same format as mapping.toml)
+    file: std::path::PathBuf,
+}
+
+#[derive(Parser, Debug)]
+enum MappingCommand {
+    /// List all mapping profiles
+    List,
+    /// Print a mapping profile as JSON
+    Show(MappingNameOpts),
+    /// Save the mapping profiles from a file, replacing profiles with the same names
+    Import(MappingFileOpts),
+    /// Delete a mapping profile
+    Delete(MappingNameOpts),
+}
+
 #[derive(Parser, Debug)]
 struct RefetchOpts {
     /// Feed ID or URL
@@ -96,6 +188,14 @@ enum FeedCommand {
     Watch(FeedManagerOpts),
     /// Refetch a feed and update all records
     Refetch(RefetchOpts),
+    /// Migrate the post IDs of a feed to a new identity strategy
+    MigrateIds(rss::identity::MigrateIdsOpts),
+    /// Move a feed to a new URL, keeping its posts
+    Move(rss::moves::MoveOpts),
+    /// Import feeds from an OPML file
+    Import(rss::opml::ImportOpts),
+    /// Export all feeds as OPML
+    Export(rss::opml::ExportOpts),
 }
 
 #[derive(Parser, Debug)]
@@ -178,14 +278,30 @@ fn state_from_args(args: &Args) -> anyhow::Result<State> {
     let index_manager = index::IndexManager::with_url(args.elasticsearch_url.as_deref())?;
     let feed_manager_opts = FeedManagerOpts {
         mapping_file: args.mapping_file.clone(),
+        crawl_rules_file: args.crawl_rules_file.clone(),
+        feed_concurrency: args.feed_concurrency,
+        feed_host_delay: args.feed_host_delay,
+        feed_timeout: args.feed_timeout,
+        feed_user_agent: args.feed_user_agent.clone(),
+        websub_callback_url: args.websub_callback_url.clone(),
     };
     let feed_manager = rss::FeedManager::new(feed_manager_opts);
 
-    let ocypod_url = args
-        .ocypod_url
-        .as_deref()
-        .unwrap_or(jobs::DEFAULT_OCYPOD_URL);
-    let job_manager = jobs::JobManager::new(db_manager.record_db().clone(), ocypod_url);
+    let job_manager = match args.job_queue.as_deref() {
+        None | Some("ocypod") => {
+            let ocypod_url = args
+                .ocypod_url
+                .as_deref()
+                .unwrap_or(jobs::DEFAULT_OCYPOD_URL);
+            jobs::JobManager::new(&db_manager, ocypod_url)
+        }
+        Some("couchdb") => {
+            let queue = jobs::CouchQueue::new(db_manager.jobs_db().clone());
+            jobs::JobManager::with_queue(&db_manager, queue)
+        }
+        Some(other) => anyhow::bail!("Invalid job queue backend: {}", other),
+    };
+    let job_manager = job_manager.with_config_file(args.jobs_config_file.as_ref());
 
     let state = State::new(db_manager, db, index_manager, feed_manager, job_manager);
     Ok(state)
@@ -206,6 +322,8 @@ async fn main() -> anyhow::Result<()> {
         Command::Index(opts) => run_index(state, opts).await,
         Command::Search(opts) => run_search(state, opts).await,
         Command::Feed(opts) => run_feed(state, opts.command).await,
+        Command::Mapping(opts) => run_mapping(state, opts.command).await,
+        Command::Oai(opts) => run_oai(state, opts.command).await,
         Command::Job(opts) => jobs::bin::main(state, opts).await,
         Command::Server(opts) => run_server(state, opts).await,
         Command::Run => run_all(state, args).await,
@@ -365,14 +483,100 @@ async fn run_search(state: State, opts: SearchOpts) -> anyhow::Result<()> {
     Ok(())
 }
 
+async fn run_mapping(state: State, command: MappingCommand) -> anyhow::Result<()> {
+    state.db.init().await?;
+    match command {
+        MappingCommand::List => {
+            let records: Vec<Record<FeedMapping>> = state.db.get_all_records().await?;
+            for record in records {
+                let extends = record
+                    .value
+                    .extends
+                    .as_ref()
+                    .map(|name| format!(" extends {}", name))
+                    .unwrap_or_default();
+                println!(
+                    "{}{} ({} fields, domains: {})",
+                    record.id(),
+                    extends,
+                    record.value.fields.len(),
+                    record.value.domains.join(", ")
+                );
+            }
+        }
+        MappingCommand::Show(opts) => {
+            let record: Record<FeedMapping> =
+                state.db.get_record(&FeedMapping::guid(&opts.name)).await?;
+            println!("{}", serde_json::to_string_pretty(&record.value)?);
+        }
+        MappingCommand::Import(opts) => {
+            let contents = tokio::fs::read_to_string(&opts.file).await?;
+            let checks = rss::mapping::import_mappings(&state.db, &contents).await?;
+            for (name, check) in checks.iter() {
+                for warning in check.warnings.iter() {
+                    log::warn!("Mapping {}: {}", name, warning);
+                }
+            }
+            log::info!("Saved {} mapping profiles", checks.len());
+        }
+        MappingCommand::Delete(opts) => {
+            rss::mapping::delete_profile(&state.db, &opts.name).await?;
+            log::info!("Deleted mapping profile {}", opts.name);
+        }
+    }
+    Ok(())
+}
+
+async fn run_oai(state: State, command: OaiCommand) -> anyhow::Result<()> {
+    state.db.init().await?;
+    match command {
+        OaiCommand::Add(opts) => {
+            let mut source = OaiSource::with_url(opts.url);
+            source.set = opts.set;
+            source.mapping = opts.mapping;
+            if let Some(prefix) = opts.metadata_prefix {
+                source.metadata_prefix = prefix;
+            }
+            let record = rss::oai::add_source(&state.db, source).await?;
+            log::info!("OAI source {}: {}", record.id(), record.value.url);
+        }
+        OaiCommand::List => {
+            let records: Vec<Record<OaiSource>> = state.db.get_all_records().await?;
+            for record in records {
+                println!(
+                    "{} {} (set: {}, harvested until: {})",
+                    record.id(),
+                    record.value.url,
+                    record.value.set.as_deref().unwrap_or("-"),
+                    record.value.harvested_until.as_deref().unwrap_or("-")
+                );
+            }
+        }
+        OaiCommand::Harvest(opts) => {
+            state
+                .feed_manager
+                .harvest(&state.db, &opts.id_or_url, &opts.opts)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
 async fn run_feed(state: State, command: FeedCommand) -> anyhow::Result<()> {
     state.db.init().await?;
     match command {
+        FeedCommand::Fetch(opts) if opts.dry_run => {
+            let preview = state
+                .feed_manager
+                .preview(&state.db, opts.url.as_str(), None)
+                .await?;
+            println!("{}", serde_json::to_string_pretty(&preview)?);
+        }
         FeedCommand::Fetch(opts) => {
-            rss::ops::fetch_and_save(&state.db, &opts).await?;
+            state.feed_manager.fetch(&state.db, &opts).await?;
         }
         FeedCommand::Crawl(opts) => {
-            rss::ops::crawl_and_save(&state.db, &opts).await?;
+            state.feed_manager.crawl(&state.db, &opts).await?;
         }
         FeedCommand::Watch(_opts) => {
             state.feed_manager.run_watch(state.db).await?;
@@ -383,6 +587,79 @@ async fn run_feed(state: State, command: FeedCommand) -> anyhow::Result<()> {
                 .refetch(&state.db, &opts.id_or_url)
                 .await?;
         }
+        FeedCommand::Move(opts) => {
+            let report =
+                rss::manager::move_feed(&state.db, &opts.id_or_url, opts.url.as_deref()).await?;
+            log::info!(
+                "Moved feed {} to {} ({} posts, {} medias)",
+                report.from,
+                report.to,
+                report.posts,
+                report.medias
+            );
+        }
+        FeedCommand::MigrateIds(opts) => {
+            let feed = rss::manager::get_feed(&state.db, &opts.id_or_url).await?;
+            let identity = if opts.scoped {
+                ItemIdentity::scoped()
+            } else {
+                ItemIdentity::default()
+            };
+            let report =
+                rss::identity::migrate_ids(&state.db, feed, identity, opts.dry_run).await?;
+            for (old_guid, new_guid) in report.moved.iter() {
+                log::debug!("{} -> {}", old_guid, new_guid);
+            }
+            for guid in report.skipped.iter() {
+                log::warn!("No identity for post {}, not migrated", guid);
+            }
+            for (guid, err) in report.failed.iter() {
+                log::error!("Failed to migrate post {}: {}", guid, err);
+            }
+            log::info!(
+                "{} {} posts ({} unchanged, {} skipped, {} failed)",
+                if opts.dry_run { "Would move" } else { "Moved" },
+                report.moved.len(),
+                report.unchanged,
+                report.skipped.len(),
+                report.failed.len()
+            );
+            if !report.failed.is_empty() {
+                anyhow::bail!("Migration incomplete, the feed keeps its old identity strategy");
+            }
+        }
+        FeedCommand::Import(opts) => {
+            let xml = tokio::fs::read_to_string(&opts.file).await?;
+            let report = rss::opml::import(&state.db, &xml, opts.dry_run).await?;
+            for url in report.created.iter() {
+                log::debug!("new feed: {}", url);
+            }
+            for err in report.invalid.iter() {
+                log::warn!("Invalid outline: {}", err);
+            }
+            for err in report.failed.iter() {
+                log::error!("Failed to save feed: {}", err);
+            }
+            log::info!(
+                "{} {} feeds ({} existing, {} invalid, {} failed)",
+                if opts.dry_run {
+                    "Would import"
+                } else {
+                    "Imported"
+                },
+                report.created.len(),
+                report.existing.len(),
+                report.invalid.len(),
+                report.failed.len()
+            );
+        }
+        FeedCommand::Export(opts) => {
+            let xml = rss::opml::export(&state.db).await?;
+            match opts.output {
+                Some(path) => tokio::fs::write(path, xml).await?,
+                None => println!("{}", xml),
+            }
+        }
     };
     Ok(())
 }
@@ -6,6 +6,7 @@ use crate::util::{wait_for_ready, RetryOpts};
 
 pub const RECORD_DB_NAME: &str = "records";
 pub const META_DB_NAME: &str = "meta";
+pub const JOBS_DB_NAME: &str = "jobs";
 pub const SEPERATOR: &str = "$";
 
 #[derive(Debug, Clone)]
@@ -14,6 +15,7 @@ pub struct CouchManager {
     client: reqwest::Client,
     record_db: CouchDB,
     meta_db: CouchDB,
+    jobs_db: CouchDB,
 }
 
 pub fn db_name(prefix: &str, name: &str) -> String {
@@ -35,15 +37,19 @@ impl CouchManager {
         let client = reqwest::Client::new();
         let mut record_config = config.clone();
         let mut meta_config = config.clone();
+        let mut jobs_config = config.clone();
         record_config.database = db_name(&config.database, RECORD_DB_NAME);
         meta_config.database = db_name(&config.database, META_DB_NAME);
+        jobs_config.database = db_name(&config.database, JOBS_DB_NAME);
         let meta_db = CouchDB::with_config_and_client(meta_config, client.clone());
         let record_db = CouchDB::with_config_and_client(record_config, client.clone());
+        let jobs_db = CouchDB::with_config_and_client(jobs_config, client.clone());
         Ok(Self {
             config: Arc::new(config),
             client,
             record_db,
             meta_db,
+            jobs_db,
         })
     }
 
@@ -80,7 +86,12 @@ impl CouchManager {
             log::warn!("Failed to ensure system CouchDB: {}", err);
         }
 
-        let res = futures::future::join_all([self.record_db().init(), self.meta_db().init()]).await;
+        let res = futures::future::join_all([
+            self.record_db().init(),
+            self.meta_db().init(),
+            self.jobs_db().init(),
+        ])
+        .await;
         for res in res {
             res?
         }
@@ -91,6 +102,7 @@ impl CouchManager {
         let res = futures::future::join_all(vec![
             self.record_db().destroy_and_init(),
             self.meta_db().destroy_and_init(),
+            self.jobs_db().destroy_and_init(),
         ])
         .await;
         for res in res {
@@ -107,6 +119,11 @@ impl CouchManager {
         &self.meta_db
     }
 
+    /// The database of the built-in job queue (see [CouchQueue](crate::jobs::CouchQueue)).
+    pub fn jobs_db(&self) -> &CouchDB {
+        &self.jobs_db
+    }
+
     pub async fn durable_changes(&self, id: impl ToString, opts: ChangesOpts) -> DurableChanges {
         let id = id.to_string();
         let changes =
@@ -33,6 +33,8 @@ use self::table::Table;
 
 pub const DEFAULT_DATABASE: &str = "oas";
 pub const DEFAULT_HOST: &str = "http://localhost:5984";
+/// Number of docs that are requested per page in [CouchDB::find_records].
+const FIND_PAGE_SIZE: usize = 1000;
 // pub const DEFAULT_PORT: u16 = 5984;
 
 /// CouchDB config.
@@ -238,6 +240,68 @@ impl CouchDB {
         }
     }
 
+    /// Find docs with a Mango selector.
+    ///
+    /// Returns a page of at most `limit` docs. Pass the bookmark of a page to get the next page.
+    /// See https://docs.couchdb.org/en/stable/api/database/find.html for details.
+    pub async fn find(
+        &self,
+        selector: &Value,
+        limit: usize,
+        bookmark: Option<&str>,
+    ) -> Result<FindResult> {
+        let mut body = json!({ "selector": selector, "limit": limit });
+        if let Some(bookmark) = bookmark {
+            body["bookmark"] = json!(bookmark);
+        }
+        self.find_with_body(body).await
+    }
+
+    /// Find docs with a Mango selector, sorted by a list of fields.
+    ///
+    /// The sort fields have to be covered by an index (see [create_index](Self::create_index)).
+    pub async fn find_sorted(
+        &self,
+        selector: &Value,
+        sort: &[&str],
+        limit: usize,
+    ) -> Result<FindResult> {
+        let sort: Vec<Value> = sort
+            .iter()
+            .map(|field| {
+                let mut order = serde_json::Map::new();
+                order.insert(field.to_string(), json!("asc"));
+                Value::Object(order)
+            })
+            .collect();
+        let body = json!({ "selector": selector, "sort": sort, "limit": limit });
+        self.find_with_body(body).await
+    }
+
+    async fn find_with_body(&self, body: Value) -> Result<FindResult> {
+        let req = self.request(Method::POST, "_find").json(&body);
+        let res: FindResult = self.send(req).await?;
+        if let Some(warning) = &res.warning {
+            log::debug!("[{}] find: {}", self.config.database, warning);
+        }
+        Ok(res)
+    }
+
+    /// Create a Mango index on a list of fields, unless it exists already.
+    ///
+    /// The index is stored in a design doc with the name of the index.
+    pub async fn create_index(&self, name: &str, fields: &[&str]) -> Result<()> {
+        let body = json!({
+            "index": { "fields": fields },
+            "ddoc": name,
+            "name": name,
+            "type": "json"
+        });
+        let req = self.request(Method::POST, "_index").json(&body);
+        let _res: Value = self.send(req).await?;
+        Ok(())
+    }
+
     /// Get a doc from the id by its id.
     pub async fn get_doc(&self, id: &str) -> Result<Doc> {
         let req = self.request(Method::GET, id);
@@ -290,6 +354,22 @@ impl CouchDB {
         res
     }
 
+    /// Put a doc into the database unless it was changed in the meantime.
+    ///
+    /// Unlike [put_doc](Self::put_doc), this does not look up the latest revision: A doc
+    /// without a revision is only created if it does not exist yet, and a doc with a revision
+    /// is only saved if the revision is current. Returns `None` on conflicts.
+    pub async fn try_put_doc(&self, doc: Doc) -> Result<Option<PutResponse>> {
+        // Bulk puts report conflicts per doc.
+        let mut results = self.put_bulk(vec![doc]).await?;
+        match results.pop() {
+            Some(PutResult::Ok(res)) => Ok(Some(res)),
+            Some(PutResult::Err(err)) if err.error == "conflict" => Ok(None),
+            Some(PutResult::Err(err)) => Err(CouchError::Other(err.to_string())),
+            None => Err(CouchError::Other("Empty response from CouchDB".into())),
+        }
+    }
+
     /// Put a list of docs into the database in a single bulk operation.
     pub async fn put_bulk(&self, docs: Vec<Doc>) -> Result<Vec<PutResult>> {
         let ids: Vec<_> = docs.iter().map(|d| d.id()).collect();
@@ -464,6 +544,33 @@ impl CouchDB {
         Ok(records)
     }
 
+    /// Get all records with the type that match a Mango selector.
+    ///
+    /// The pages of results are loaded one after the other.
+    pub async fn find_records<T: TypedValue>(&self, selector: &Value) -> Result<Vec<Record<T>>> {
+        let prefix = format!("{}_", T::NAME);
+        let mut selector = selector.clone();
+        selector["_id"] = json!({ "$gt": prefix, "$lt": format!("{}\u{ffff}", prefix) });
+        let mut records = vec![];
+        let mut bookmark = None;
+        loop {
+            let page = self
+                .find(&selector, FIND_PAGE_SIZE, bookmark.as_deref())
+                .await?;
+            let len = page.docs.len();
+            records.extend(
+                page.docs
+                    .into_iter()
+                    .filter_map(|doc| doc.into_typed_record::<T>().ok()),
+            );
+            if len < FIND_PAGE_SIZE || page.bookmark.is_none() {
+                break;
+            }
+            bookmark = page.bookmark;
+        }
+        Ok(records)
+    }
+
     /// Get a single record by its type and id.
     ///
     /// ```ignore
@@ -182,6 +182,15 @@ pub struct FinishedEvent {
     pub pending: Option<u64>, // not available on CouchDB 1.0
 }
 
+/// A page of docs found with a Mango selector.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FindResult {
+    pub docs: Vec<Doc>,
+    /// Bookmark to pass to get the next page.
+    pub bookmark: Option<String>,
+    pub warning: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct GetChangesResult {
     pub results: Vec<ChangeEvent>,
@@ -241,6 +241,45 @@ for (nested_doc in nested_docs) {
         Ok(records)
     }
 
+    /// Find the GUIDs of the records that match a query, one page at a time.
+    ///
+    /// Results are sorted by GUID. Pass the last GUID of a page as `after` to get the next page.
+    pub async fn find_guids(
+        &self,
+        query: Value,
+        size: usize,
+        after: Option<&str>,
+    ) -> Result<Vec<String>, Error> {
+        let mut body = json!({
+            "query": query,
+            "size": size,
+            "sort": [{ "$meta.guid": "asc" }],
+            "_source": ["$meta.guid"]
+        });
+        if let Some(after) = after {
+            body["search_after"] = json!([after]);
+        }
+        let response = self
+            .client
+            .search(SearchParts::Index(&[&self.index]))
+            .body(body)
+            .send()
+            .await?
+            .error_for_status_code()?;
+
+        let json: Value = response.json().await?;
+        let guids = json["hits"]["hits"]
+            .as_array()
+            .map(|hits| {
+                hits.iter()
+                    .filter_map(|hit| hit["_source"]["$meta"]["guid"].as_str())
+                    .map(|guid| guid.to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(guids)
+    }
+
     /// Simple string query on the index.
     pub async fn find_records_with_text_query(
         &self,
@@ -1,8 +1,9 @@
 //! Index manager
 //!
-//! The index manager maintains a list of Elasticsearch indexes. It also maintains an "oas.meta"
-//! index which stores meta information about the indexing state, most importantly the latest
-//! CouchDB seq that was indexed.
+//! The index manager maintains a list of Elasticsearch indexes: the data index of posts (with
+//! their medias) and the index of shows. It also maintains an "oas.meta" index which stores
+//! meta information about the indexing state, most importantly the latest CouchDB seq that
+//! was indexed.
 
 use crate::{couch::CouchDB, util::RetryOpts};
 use anyhow::Context;
@@ -14,7 +15,7 @@ use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use std::time;
 
-use super::{elastic, Config, Index, PostIndex};
+use super::{elastic, Config, Index, PostIndex, ShowIndex};
 
 /// Prefix used for all indexes created by OAS.
 pub const DEFAULT_PREFIX: &str = "oas";
@@ -22,6 +23,8 @@ pub const DEFAULT_PREFIX: &str = "oas";
 pub const META_INDEX_NAME: &str = "meta";
 /// Name of the data index.
 pub const DATA_INDEX_NAME: &str = "data";
+/// Name of the show index.
+pub const SHOW_INDEX_NAME: &str = "shows";
 /// Doc ID for the index state.
 pub const DOC_ID_INDEX_STATE: &str = "IndexMeta.data";
 
@@ -31,6 +34,7 @@ pub struct IndexManager {
     config: Config,
     client: Arc<Elasticsearch>,
     post_index: Arc<PostIndex>,
+    show_index: Arc<ShowIndex>,
     meta_index: Arc<MetaIndex>,
 }
 
@@ -122,10 +126,14 @@ impl IndexManager {
         let post_index_name = format!("{}.{}", prefix, DATA_INDEX_NAME);
         let post_index = PostIndex::new(client.clone(), post_index_name);
 
+        let show_index_name = format!("{}.{}", prefix, SHOW_INDEX_NAME);
+        let show_index = ShowIndex::new(client.clone(), show_index_name);
+
         Ok(Self {
             config,
             client,
             post_index: Arc::new(post_index),
+            show_index: Arc::new(show_index),
             meta_index: Arc::new(meta_index),
         })
     }
@@ -174,6 +182,7 @@ impl IndexManager {
         self.wait_for_ready().await?;
         self.meta_index.index.ensure_index(opts.delete_meta).await?;
         self.post_index.index.ensure_index(opts.delete_data).await?;
+        self.show_index.index.ensure_index(opts.delete_data).await?;
         Ok(())
     }
 
@@ -185,6 +194,10 @@ impl IndexManager {
         &self.post_index
     }
 
+    pub fn show_index(&self) -> &Arc<ShowIndex> {
+        &self.show_index
+    }
+
     // fn meta_index(&self) -> &Arc<MetaIndex> {
     //     &self.meta_index
     // }
@@ -219,6 +232,10 @@ impl IndexManager {
                 .index_changes(db, &records[..])
                 .await
                 .context("Failed to index changes")?;
+            self.show_index
+                .index_changes(&records[..])
+                .await
+                .context("Failed to index shows")?;
             self.meta_index
                 .set_latest_indexed_seq(latest_seq)
                 .await
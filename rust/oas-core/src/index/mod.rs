@@ -3,9 +3,11 @@ mod elastic;
 mod error;
 mod manager;
 mod post_index;
+mod show_index;
 
 pub use config::Config;
 pub use elastic::Index;
 pub use error::IndexError;
 pub use manager::{IndexManager, InitOpts};
 pub use post_index::PostIndex;
+pub use show_index::ShowIndex;
@@ -0,0 +1,51 @@
+use anyhow::Context;
+use elasticsearch::Elasticsearch;
+use oas_common::types::Show;
+use oas_common::{ElasticMapping, Record, RecordMap, UntypedRecord};
+use std::sync::Arc;
+
+use super::Index;
+
+/// The index of shows, to search for and browse shows apart from their posts.
+#[derive(Debug, Clone)]
+pub struct ShowIndex {
+    pub(super) index: Arc<Index>,
+}
+
+impl ShowIndex {
+    pub fn new(client: Arc<Elasticsearch>, name: String) -> Self {
+        let index = Index::new(client, name, Record::<Show>::elastic_mapping());
+        Self {
+            index: Arc::new(index),
+        }
+    }
+
+    pub fn index(&self) -> &Arc<Index> {
+        &self.index
+    }
+
+    pub fn client(&self) -> &Elasticsearch {
+        self.index().client()
+    }
+
+    pub fn name(&self) -> &str {
+        self.index().name()
+    }
+
+    /// Index the shows in a batch of changed records.
+    pub async fn index_changes(&self, changes: &[UntypedRecord]) -> anyhow::Result<()> {
+        let mut sorted =
+            RecordMap::from_untyped(changes.to_vec()).context("Failed to upcast records")?;
+        let shows = sorted.into_vec::<Show>();
+        if shows.is_empty() {
+            return Ok(());
+        }
+        let res = self.index.put_typed_records(&shows).await?;
+        let stats = res.stats();
+        if res.errors {
+            log::error!("Index failed for {} shows", stats.errors);
+        }
+        log::debug!("indexed {} shows", shows.len());
+        Ok(())
+    }
+}
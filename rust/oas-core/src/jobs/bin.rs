@@ -1,7 +1,10 @@
+use chrono::{DateTime, Utc};
 use clap::Parser;
 use oas_common::types::{Media, Post};
 use oas_common::{Record, TypedValue};
+use serde_json::json;
 
+use super::bulk::{self, BulkCreateRequest, Selection};
 use crate::couch::CouchDB;
 use crate::State;
 
@@ -15,6 +18,8 @@ pub struct JobOpts {
 #[derive(Parser, Debug)]
 pub enum JobCommand {
     Create(CreateOpts),
+    /// Create jobs for many posts or medias
+    Bulk(BulkOpts),
 }
 
 #[derive(Parser, Debug)]
@@ -54,6 +59,72 @@ pub struct AsrOpts {
     missing: bool,
 }
 
+#[derive(Parser, Debug)]
+pub struct BulkOpts {
+    /// Job type (e.g. asr or nlp). ASR jobs are created for the medias of the selected posts.
+    typ: String,
+    /// Only posts of this feed (feed ID)
+    #[clap(long)]
+    feed: Option<String>,
+    /// Only posts published at or after this date (e.g. 2021-01-01T00:00:00Z)
+    #[clap(long)]
+    since: Option<DateTime<Utc>>,
+    /// Only posts published at or before this date (e.g. 2021-12-31T23:59:59Z)
+    #[clap(long)]
+    until: Option<DateTime<Utc>>,
+    /// Only posts that match this search query (query string syntax)
+    #[clap(long)]
+    query: Option<String>,
+    /// Only posts that match this CouchDB Mango selector (JSON)
+    #[clap(long)]
+    selector: Option<String>,
+    /// Job settings (JSON). Defaults to the job settings of each record.
+    #[clap(long)]
+    opts: Option<String>,
+    /// Also create jobs for records that already have results
+    #[clap(long)]
+    force: bool,
+    /// Only count the jobs that would be created
+    #[clap(long)]
+    dry_run: bool,
+    /// Number of posts to load at once
+    #[clap(long, default_value = "100")]
+    batch_size: usize,
+    /// Max number of jobs to create per second
+    #[clap(long)]
+    rate: Option<f32>,
+    /// Max number of jobs to create
+    #[clap(long)]
+    limit: Option<usize>,
+}
+
+impl BulkOpts {
+    fn into_request(self) -> anyhow::Result<BulkCreateRequest> {
+        let parse = |json: Option<String>| -> anyhow::Result<Option<serde_json::Value>> {
+            Ok(match json {
+                Some(json) => Some(serde_json::from_str(&json)?),
+                None => None,
+            })
+        };
+        let selection = Selection {
+            feed: self.feed,
+            since: self.since,
+            until: self.until,
+            query: self.query,
+            selector: parse(self.selector)?,
+        };
+        Ok(BulkCreateRequest {
+            opts: parse(self.opts)?,
+            force: self.force,
+            dry_run: self.dry_run,
+            batch_size: self.batch_size,
+            rate: self.rate,
+            limit: self.limit,
+            ..BulkCreateRequest::new(self.typ, selection)
+        })
+    }
+}
+
 pub async fn main(state: State, opts: JobOpts) -> anyhow::Result<()> {
     state.db.init().await?;
     match opts.command {
@@ -61,9 +132,16 @@ pub async fn main(state: State, opts: JobOpts) -> anyhow::Result<()> {
             CreateJob::Nlp(opts) => run_nlp(state, opts).await,
             CreateJob::Asr(opts) => run_asr(state, opts).await,
         },
+        JobCommand::Bulk(opts) => run_bulk(state, opts.into_request()?).await,
     }
 }
 
+async fn run_bulk(state: State, req: BulkCreateRequest) -> anyhow::Result<()> {
+    let result = bulk::create_jobs(&state, req).await?;
+    println!("{}", serde_json::to_string_pretty(&result)?);
+    Ok(())
+}
+
 async fn run_nlp(state: State, opts: NlpOpts) -> anyhow::Result<()> {
     let post = state.db.table::<Post>().get(&opts.post).await?;
     let job = crate::jobs::typs::nlp_job(&post, None);
@@ -105,13 +183,12 @@ pub async fn load_medias_with_opts(
                 .filter_map(|r| r.into_record())
                 .collect()
         }
-        AsrOpts { missing: true, .. } => db
-            .table::<Media>()
-            .get_all()
-            .await?
-            .into_iter()
-            .filter(|r| r.value.transcript.is_none())
-            .collect(),
+        AsrOpts { missing: true, .. } => {
+            let selector = json!({
+                "$or": [{ "transcript": { "$exists": false } }, { "transcript": null }]
+            });
+            db.find_records::<Media>(&selector).await?
+        }
         AsrOpts { latest: true, .. } => db.table::<Media>().get_all().await?,
         _ => {
             anyhow::bail!("Invalid or ambigous options")
@@ -0,0 +1,362 @@
+//! Bulk job creation.
+//!
+//! Jobs are created for the posts selected by feed, publication date, a search query or a
+//! CouchDB selector. ASR jobs are created for the medias of the selected posts, all other jobs
+//! for the posts themselves. Posts are loaded in batches, and records that already have
+//! results or a pending job are skipped.
+
+use chrono::{DateTime, Utc};
+use oas_common::types::{Feed, Media, Post};
+use oas_common::TypedValue;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::time::Duration;
+
+use super::pipelines::Subject;
+use super::{typs, JobFilter, JobStatus};
+use crate::State;
+
+const DEFAULT_BATCH_SIZE: usize = 100;
+
+fn default_batch_size() -> usize {
+    DEFAULT_BATCH_SIZE
+}
+
+/// The posts to create jobs for. All set filters have to match.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Selection {
+    /// Only posts of a feed (feed ID).
+    pub feed: Option<String>,
+    /// Only posts published at or after a date.
+    pub since: Option<DateTime<Utc>>,
+    /// Only posts published at or before a date.
+    pub until: Option<DateTime<Utc>>,
+    /// Only posts that match a search query (query string syntax) in the post index.
+    pub query: Option<String>,
+    /// Only posts that match a CouchDB Mango selector.
+    pub selector: Option<Value>,
+}
+
+impl Selection {
+    fn feed_guid(&self) -> Option<String> {
+        self.feed
+            .as_ref()
+            .map(|feed| match feed.starts_with(&Feed::guid("")) {
+                true => feed.clone(),
+                false => Feed::guid(feed),
+            })
+    }
+
+    /// The Mango selector for the posts in CouchDB.
+    fn couch_selector(&self) -> Value {
+        let prefix = Post::guid("");
+        let mut filters = vec![json!({
+            "_id": { "$gt": prefix, "$lt": format!("{}\u{fff0}", prefix) }
+        })];
+        if let Some(feed) = self.feed_guid() {
+            filters.push(json!({ "feeds": { "$elemMatch": { "$eq": feed } } }));
+        }
+        if let Some(since) = &self.since {
+            filters.push(json!({ "datePublished": { "$gte": since } }));
+        }
+        if let Some(until) = &self.until {
+            filters.push(json!({ "datePublished": { "$lte": until } }));
+        }
+        if let Some(selector) = &self.selector {
+            filters.push(selector.clone());
+        }
+        json!({ "$and": filters })
+    }
+
+    /// The query for the posts in the post index.
+    fn index_query(&self, query: &str) -> Value {
+        let mut filters = vec![];
+        if let Some(feed) = self.feed_guid() {
+            filters.push(json!({ "term": { "feeds": feed } }));
+        }
+        if self.since.is_some() || self.until.is_some() {
+            let mut range = serde_json::Map::new();
+            if let Some(since) = &self.since {
+                range.insert("gte".into(), json!(since));
+            }
+            if let Some(until) = &self.until {
+                range.insert("lte".into(), json!(until));
+            }
+            filters.push(json!({ "range": { "datePublished": range } }));
+        }
+        json!({
+            "bool": {
+                "must": [{ "query_string": { "query": query } }],
+                "filter": filters
+            }
+        })
+    }
+}
+
+/// A request to create jobs in bulk.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkCreateRequest {
+    /// The job type.
+    pub typ: String,
+    #[serde(flatten)]
+    pub selection: Selection,
+    /// The job settings. Defaults to the job settings of each record.
+    pub opts: Option<Value>,
+    /// Also create jobs for records that already have results.
+    #[serde(default)]
+    pub force: bool,
+    /// Only count the jobs that would be created.
+    #[serde(default)]
+    pub dry_run: bool,
+    /// The number of posts to load at once.
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    /// The maximal number of jobs to create per second.
+    pub rate: Option<f32>,
+    /// The maximal number of jobs to create.
+    pub limit: Option<usize>,
+}
+
+impl BulkCreateRequest {
+    pub fn new(typ: impl ToString, selection: Selection) -> Self {
+        Self {
+            typ: typ.to_string(),
+            selection,
+            opts: None,
+            force: false,
+            dry_run: false,
+            batch_size: DEFAULT_BATCH_SIZE,
+            rate: None,
+            limit: None,
+        }
+    }
+}
+
+/// The result of creating jobs in bulk.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkCreateResult {
+    pub dry_run: bool,
+    /// The number of selected records.
+    pub matched: usize,
+    /// The number of records skipped because they already have results.
+    pub skipped_with_results: usize,
+    /// The number of records skipped because a job is pending for them.
+    pub skipped_pending: usize,
+    /// The number of jobs created (or that would be created on a dry run).
+    pub created: usize,
+    /// The number of jobs that failed to be created.
+    pub failed: usize,
+}
+
+/// Pages of selected posts, from CouchDB or from the post index.
+enum Pages {
+    Couch {
+        selector: Value,
+        bookmark: Option<String>,
+    },
+    Index {
+        query: Value,
+        after: Option<String>,
+    },
+    Done,
+}
+
+impl Pages {
+    fn new(selection: &Selection) -> anyhow::Result<Self> {
+        match (&selection.query, &selection.selector) {
+            (Some(_), Some(_)) => anyhow::bail!("Use either a search query or a selector"),
+            (Some(query), None) => Ok(Self::Index {
+                query: selection.index_query(query),
+                after: None,
+            }),
+            (None, _) => Ok(Self::Couch {
+                selector: selection.couch_selector(),
+                bookmark: None,
+            }),
+        }
+    }
+
+    async fn next(&mut self, state: &State, size: usize) -> anyhow::Result<Option<Vec<Subject>>> {
+        let guids = match self {
+            Self::Done => return Ok(None),
+            Self::Couch { selector, bookmark } => {
+                let res = state.db.find(selector, size, bookmark.as_deref()).await?;
+                *bookmark = res.bookmark;
+                res.docs
+                    .into_iter()
+                    .map(|doc| doc.id().to_string())
+                    .collect::<Vec<_>>()
+            }
+            Self::Index { query, after } => {
+                let index = state.index_manager.post_index().index();
+                let guids = index
+                    .find_guids(query.clone(), size, after.as_deref())
+                    .await?;
+                *after = guids.last().cloned();
+                guids
+            }
+        };
+        if guids.len() < size {
+            *self = Self::Done;
+        }
+        if guids.is_empty() {
+            return Ok(None);
+        }
+        let guids: Vec<&str> = guids.iter().map(|guid| guid.as_str()).collect();
+        let posts = state.db.get_many_records::<Post>(&guids).await?;
+        Ok(Some(posts.into_iter().map(Subject::Post).collect()))
+    }
+}
+
+/// Load the records to create jobs of a type for from a batch of posts.
+async fn subjects(
+    state: &State,
+    typ: &str,
+    posts: Vec<Subject>,
+    seen: &mut HashSet<String>,
+) -> anyhow::Result<Vec<Subject>> {
+    if typ != typs::ASR {
+        return Ok(posts);
+    }
+    // Medias may belong to several posts.
+    let mut guids = vec![];
+    for post in posts.iter() {
+        if let Subject::Post(post) = post {
+            for media in post.value.media.iter() {
+                if seen.insert(media.guid().to_string()) {
+                    guids.push(media.guid().to_string());
+                }
+            }
+        }
+    }
+    if guids.is_empty() {
+        return Ok(vec![]);
+    }
+    let guids: Vec<&str> = guids.iter().map(|guid| guid.as_str()).collect();
+    let medias = state.db.get_many_records::<Media>(&guids).await?;
+    Ok(medias.into_iter().map(Subject::Media).collect())
+}
+
+/// Create jobs for all selected records.
+pub async fn create_jobs(
+    state: &State,
+    req: BulkCreateRequest,
+) -> anyhow::Result<BulkCreateResult> {
+    if req.batch_size == 0 {
+        anyhow::bail!("Batch size must be greater than 0");
+    }
+    let mut interval = match req.rate {
+        Some(rate) if rate > 0. => Some(tokio::time::interval(Duration::from_secs_f32(1. / rate))),
+        Some(_) => anyhow::bail!("Rate must be greater than 0"),
+        None => None,
+    };
+
+    let filter = JobFilter::all()
+        .with_queue(&req.typ)
+        .with_status(JobStatus::Queued)
+        .with_status(JobStatus::Running);
+    let pending: HashSet<String> = state
+        .jobs
+        .fetch_jobs(Some(filter))
+        .await?
+        .into_iter()
+        .flat_map(|job| job.tags)
+        .collect();
+
+    let mut result = BulkCreateResult {
+        dry_run: req.dry_run,
+        ..Default::default()
+    };
+    let mut pages = Pages::new(&req.selection)?;
+    let mut seen = HashSet::new();
+    while let Some(posts) = pages.next(state, req.batch_size).await? {
+        for subject in subjects(state, &req.typ, posts, &mut seen).await? {
+            if req.limit.map_or(false, |limit| result.created >= limit) {
+                return Ok(result);
+            }
+            result.matched += 1;
+            if !req.force && subject.has_results(&req.typ) {
+                result.skipped_with_results += 1;
+                continue;
+            }
+            if pending.contains(subject.guid()) {
+                result.skipped_pending += 1;
+                continue;
+            }
+            if req.dry_run {
+                result.created += 1;
+                continue;
+            }
+            if let Some(interval) = interval.as_mut() {
+                interval.tick().await;
+            }
+            let job = subject.job_request(&req.typ, req.opts.clone());
+            match state.jobs.create_job(job).await {
+                Ok(_) => result.created += 1,
+                Err(err) => {
+                    log::warn!(
+                        "Failed to create {} job for {}: {}",
+                        req.typ,
+                        subject.guid(),
+                        err
+                    );
+                    result.failed += 1;
+                }
+            }
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selectors() {
+        let selection: Selection = serde_json::from_value(json!({
+            "feed": "abc",
+            "since": "2021-01-01T00:00:00Z",
+            "selector": { "inLanguage": "de" }
+        }))
+        .unwrap();
+        let selector = selection.couch_selector();
+        let filters = selector["$and"].as_array().unwrap();
+        assert_eq!(filters.len(), 4);
+        assert_eq!(filters[0]["_id"]["$gt"], "oas.Post_");
+        assert_eq!(filters[1]["feeds"]["$elemMatch"]["$eq"], "oas.Feed_abc");
+        assert_eq!(filters[2]["datePublished"]["$gte"], "2021-01-01T00:00:00Z");
+        assert_eq!(filters[3]["inLanguage"], "de");
+
+        let query = selection.index_query("climate");
+        let filters = query["bool"]["filter"].as_array().unwrap();
+        assert_eq!(filters[0]["term"]["feeds"], "oas.Feed_abc");
+        assert_eq!(
+            filters[1]["range"]["datePublished"]["gte"],
+            "2021-01-01T00:00:00Z"
+        );
+        assert!(filters[1]["range"]["datePublished"].get("lte").is_none());
+
+        let both: Selection =
+            serde_json::from_value(json!({ "query": "climate", "selector": {} })).unwrap();
+        assert!(Pages::new(&both).is_err());
+    }
+
+    #[test]
+    fn requests() {
+        let req: BulkCreateRequest = serde_json::from_value(json!({
+            "typ": "asr",
+            "feed": "abc",
+            "dryRun": true
+        }))
+        .unwrap();
+        assert!(req.dry_run);
+        assert!(!req.force);
+        assert_eq!(req.batch_size, DEFAULT_BATCH_SIZE);
+        assert_eq!(req.selection.feed.as_deref(), Some("abc"));
+    }
+}
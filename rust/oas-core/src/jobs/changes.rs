@@ -1,3 +1,12 @@
+//! Automatic job creation from record changes.
+//!
+//! NLP jobs are created for posts without NLP results, and ASR jobs for medias without a
+//! transcript, if the job type is enabled in the record's job settings. Records whose latest
+//! job of a type failed (`$meta.jobs.status` contains e.g. `asr:failed`) are not picked up
+//! again: Such jobs have to be recreated manually, e.g. with a bulk job request. The same
+//! holds for records whose latest job completed without setting a result (e.g. ASR of a
+//! media without speech), as storing the job state changes the record again.
+
 use anyhow::Context;
 use oas_common::types::{Media, Post};
 use oas_common::{Record, RecordMap, Resolver, UntypedRecord};
@@ -49,9 +58,15 @@ async fn on_post_change(state: &State, record: Record<Post>) -> anyhow::Result<(
     // Check for post-level jobs.
     // Logic is: Create an NLP job if record.value.nlp is serde_json::Value::Null
     // and no NLP job is pending for this record.
+    // Failed jobs and jobs that completed without a result are not recreated: Storing the
+    // job state changes the record again.
     let typ = job_typs::NLP;
     if let Some(_opts) = record.meta().jobs().setting(typ) {
-        if record.value.nlp.is_null() && !has_pending(&state.jobs, typ, record.guid()).await {
+        if record.value.nlp.is_null()
+            && !record.meta().jobs().failed(typ)
+            && !record.meta().jobs().completed(typ)
+            && !has_pending(&state.jobs, typ, record.guid()).await
+        {
             let job = job_typs::nlp_job(&record, None);
             state.jobs.create_job(job).await?;
         }
@@ -61,9 +76,14 @@ async fn on_post_change(state: &State, record: Record<Post>) -> anyhow::Result<(
 }
 
 async fn on_media_change(state: &State, record: Record<Media>) -> anyhow::Result<()> {
+    // Medias with a publisher-provided transcript (see `rss::podcast`) already have a
+    // transcript set and thus skip ASR.
     let typ = job_typs::ASR;
     if let Some(_opts) = record.meta().jobs().setting(typ) {
-        if record.value.transcript.is_none() && !has_pending(&state.jobs, typ, record.guid()).await
+        if record.value.transcript.is_none()
+            && !record.meta().jobs().failed(typ)
+            && !record.meta().jobs().completed(typ)
+            && !has_pending(&state.jobs, typ, record.guid()).await
         {
             let job = job_typs::asr_job(&record, None);
             state.jobs.create_job(job).await?;
@@ -72,7 +92,10 @@ async fn on_media_change(state: &State, record: Record<Media>) -> anyhow::Result
     Ok(())
 }
 
-async fn has_pending(jobs: &JobManager, typ: &str, guid: &str) -> bool {
+/// Check if a job of a type is pending for a record.
+///
+/// This only saves requests: [JobManager::create_job] does not enqueue jobs that are pending.
+pub(super) async fn has_pending(jobs: &JobManager, typ: &str, guid: &str) -> bool {
     match jobs.pending_jobs(guid, typ).await {
         Err(_) => false,
         Ok(list) => !list.is_empty(),
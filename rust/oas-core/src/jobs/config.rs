@@ -0,0 +1,191 @@
+//! Configuration of jobs: pipelines and queue settings.
+//!
+//! The config is loaded from a TOML file (see `config/jobs.toml` for the defaults).
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time;
+use tokio::fs::read_to_string;
+
+use super::ocypod::Duration;
+use super::pipelines::{PipelineStep, Pipelines};
+use super::Queue;
+use crate::rss::mapping::config_file_path;
+
+const DEFAULT_JOBS_CONFIG: &str = include_str!("../../../../config/jobs.toml");
+const JOBS_CONFIG_FILE: &str = "jobs.toml";
+
+/// Name of the queue settings that apply to all queues.
+pub const DEFAULT_QUEUE: &str = "default";
+
+impl Default for Queue {
+    fn default() -> Self {
+        Self {
+            timeout: time::Duration::from_secs(3600).into(),
+            heartbeat_timeout: time::Duration::from_secs(3600).into(),
+            expires_after: time::Duration::from_secs(86400).into(),
+            retries: 5,
+            retry_delays: vec![],
+        }
+    }
+}
+
+/// Settings for a queue. Settings that are not set are left unchanged.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueSettings {
+    /// Time after which a running job times out (e.g. "1h 30m").
+    pub timeout: Option<Duration>,
+    /// Time after which a running job without heartbeats times out.
+    pub heartbeat_timeout: Option<Duration>,
+    /// Time after which an ended job is deleted.
+    pub expires_after: Option<Duration>,
+    /// How often a failed or timed out job is retried.
+    pub retries: Option<u64>,
+    /// Delays before the retries (the last delay applies to all further retries).
+    pub retry_delays: Option<Vec<Duration>>,
+}
+
+impl QueueSettings {
+    pub fn apply(&self, queue: &mut Queue) {
+        if let Some(timeout) = &self.timeout {
+            queue.timeout = timeout.clone();
+        }
+        if let Some(heartbeat_timeout) = &self.heartbeat_timeout {
+            queue.heartbeat_timeout = heartbeat_timeout.clone();
+        }
+        if let Some(expires_after) = &self.expires_after {
+            queue.expires_after = expires_after.clone();
+        }
+        if let Some(retries) = self.retries {
+            queue.retries = retries;
+        }
+        if let Some(retry_delays) = &self.retry_delays {
+            queue.retry_delays = retry_delays.clone();
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct JobsConfigFile {
+    #[serde(default)]
+    pipeline: Vec<PipelineStep>,
+    #[serde(default)]
+    queue: HashMap<String, QueueSettings>,
+}
+
+/// The jobs config.
+#[derive(Debug, Default, Clone)]
+pub struct JobsConfig {
+    pub pipelines: Pipelines,
+    pub queues: HashMap<String, QueueSettings>,
+}
+
+impl JobsConfig {
+    pub fn from_toml(contents: &str) -> anyhow::Result<Self> {
+        let file: JobsConfigFile = toml::from_str(contents)?;
+        Ok(Self {
+            pipelines: Pipelines::new(file.pipeline),
+            queues: file.queue,
+        })
+    }
+
+    /// The config included at compile time.
+    pub fn with_defaults() -> Self {
+        Self::from_toml(DEFAULT_JOBS_CONFIG).expect("Invalid default jobs config")
+    }
+
+    /// Load the config from a file, from the default config path or the
+    /// defaults included at compile time.
+    pub async fn load(path: Option<PathBuf>) -> anyhow::Result<Self> {
+        let path = match path {
+            Some(path) => Some(path),
+            None => config_file_path(JOBS_CONFIG_FILE).await,
+        };
+        match path {
+            Some(path) => {
+                let contents = read_to_string(&path)
+                    .await
+                    .with_context(|| format!("File not found: {}", path.display()))?;
+                Self::from_toml(&contents)
+            }
+            None => Ok(Self::with_defaults()),
+        }
+    }
+
+    /// Get the configured settings for a queue.
+    ///
+    /// The settings of the queue are applied over the `default` settings.
+    pub fn queue(&self, name: &str) -> Queue {
+        let mut queue = Queue::default();
+        for name in [DEFAULT_QUEUE, name] {
+            if let Some(settings) = self.queues.get(name) {
+                settings.apply(&mut queue);
+            }
+        }
+        queue
+    }
+
+    /// The names of all queues with settings of their own.
+    pub fn configured_queues(&self) -> impl Iterator<Item = &str> {
+        self.queues
+            .keys()
+            .map(|name| name.as_str())
+            .filter(|name| *name != DEFAULT_QUEUE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hours(hours: u64) -> Duration {
+        time::Duration::from_secs(hours * 3600).into()
+    }
+
+    #[test]
+    fn queue_settings() {
+        let config = JobsConfig::from_toml(
+            r#"
+            [queue.default]
+            retries = 2
+            retryDelays = ["1m", "10m"]
+
+            [queue.asr]
+            timeout = "6h"
+            heartbeatTimeout = "30m"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.configured_queues().collect::<Vec<_>>(), vec!["asr"]);
+
+        let asr = config.queue("asr");
+        assert_eq!(asr.timeout, hours(6));
+        assert_eq!(
+            asr.heartbeat_timeout,
+            time::Duration::from_secs(1800).into()
+        );
+        assert_eq!(asr.retries, 2);
+        assert_eq!(asr.retry_delays.len(), 2);
+
+        let nlp = config.queue("nlp");
+        assert_eq!(nlp.timeout, hours(1));
+        assert_eq!(nlp.expires_after, hours(24));
+        assert_eq!(nlp.retries, 2);
+
+        let update: QueueSettings = serde_json::from_str(r#"{ "retries": 0 }"#).unwrap();
+        let mut queue = nlp.clone();
+        update.apply(&mut queue);
+        assert_eq!(queue.retries, 0);
+        assert_eq!(queue.timeout, nlp.timeout);
+    }
+
+    #[test]
+    fn default_config() {
+        let config = JobsConfig::with_defaults();
+        assert_eq!(config.queue("nlp"), Queue::default());
+        assert!(config.queue("asr").timeout.0 > Queue::default().timeout.0);
+    }
+}
@@ -0,0 +1,709 @@
+//! A job queue persisted in CouchDB.
+//!
+//! This queue follows the semantics of Ocypod: Jobs that are running for longer than their
+//! timeout, or that did not send a heartbeat within their heartbeat timeout, time out. Failed
+//! and timed out jobs are queued again until their retries are used up, and ended jobs are
+//! deleted once they expired.
+//!
+//! There is no background task: Timeouts and expiry are checked for all jobs in a sweep that
+//! runs at most every [SWEEP_INTERVAL] when jobs are taken or listed, and for the jobs that
+//! are returned. Queued jobs are found with a Mango index on their queue and status, so taking
+//! a job does not load the other jobs of the database.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use super::ocypod::Duration;
+use super::{
+    JobCreate, JobFilter, JobId, JobInfo, JobInput, JobNotFound, JobOutput, JobQueue, JobStatus,
+    Queue,
+};
+use crate::couch::{CouchDB, CouchError, Doc, DocMeta, PutResult};
+
+const JOB_PREFIX: &str = "job_";
+const QUEUE_PREFIX: &str = "queue_";
+const COUNTER_ID: &str = "counter";
+const MAX_CONFLICTS: usize = 10;
+/// Name of the Mango index on the queue, status and ID of jobs.
+const JOBS_INDEX: &str = "jobs-by-queue-status";
+/// Number of ready jobs that are loaded when taking a job.
+const TAKE_CANDIDATES: usize = 16;
+/// Page size when loading jobs with a selector.
+const FIND_PAGE_SIZE: usize = 1000;
+/// Minimal time between two checks of all jobs for timeouts and expiry.
+const SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// A job queue that stores jobs and queue settings in a CouchDB database.
+#[derive(Debug, Clone)]
+pub struct CouchQueue {
+    db: CouchDB,
+    last_sweep: Arc<Mutex<Option<Instant>>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Counter {
+    next: JobId,
+}
+
+/// A job as stored in the database.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct JobDoc {
+    #[serde(skip)]
+    rev: Option<String>,
+    #[serde(flatten)]
+    info: JobInfo,
+    started_at: Option<DateTime<Utc>>,
+    retry_at: Option<DateTime<Utc>>,
+}
+
+/// The result of checking a job for timeouts and expiry.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Check {
+    Unchanged,
+    Changed,
+    Expired,
+}
+
+impl CouchQueue {
+    pub fn new(db: CouchDB) -> Self {
+        Self {
+            db,
+            last_sweep: Default::default(),
+        }
+    }
+
+    async fn next_id(&self) -> anyhow::Result<JobId> {
+        for _ in 0..MAX_CONFLICTS {
+            let (rev, next) = match self.db.get_doc(COUNTER_ID).await {
+                Ok(doc) => {
+                    let rev = doc.rev().map(|rev| rev.to_string());
+                    let counter: Counter = doc.into_typed()?;
+                    (rev, counter.next)
+                }
+                Err(err) if is_not_found(&err) => (None, 1),
+                Err(err) => return Err(err.into()),
+            };
+            let meta = DocMeta::new(COUNTER_ID.to_string(), rev);
+            let doc = Doc::from_typed(meta, Counter { next: next + 1 })?;
+            if self.put_exclusive(doc).await?.is_some() {
+                return Ok(next);
+            }
+        }
+        Err(anyhow::anyhow!(
+            "Failed to allocate a job ID: Too many conflicts"
+        ))
+    }
+
+    async fn load_job(&self, job_id: JobId) -> anyhow::Result<JobDoc> {
+        match self.db.get_doc(&job_doc_id(job_id)).await {
+            Ok(doc) => JobDoc::from_doc(doc),
+            Err(err) if is_not_found(&err) => Err(JobNotFound(job_id).into()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Load the jobs that match a Mango selector, sorted by ID.
+    async fn find_jobs(&self, selector: Value) -> anyhow::Result<Vec<JobDoc>> {
+        let mut selector = selector;
+        selector["_id"] = job_id_range();
+        let mut jobs = vec![];
+        let mut bookmark = None;
+        loop {
+            let page = self
+                .db
+                .find(&selector, FIND_PAGE_SIZE, bookmark.as_deref())
+                .await?;
+            let len = page.docs.len();
+            for doc in page.docs.into_iter() {
+                let id = doc.id().to_string();
+                match JobDoc::from_doc(doc) {
+                    Ok(job) => jobs.push(job),
+                    Err(err) => log::warn!("Skip invalid job {}: {}", id, err),
+                }
+            }
+            if len < FIND_PAGE_SIZE || page.bookmark.is_none() {
+                break;
+            }
+            bookmark = page.bookmark;
+        }
+        jobs.sort_by_key(|job| job.info.id);
+        Ok(jobs)
+    }
+
+    /// Load the oldest jobs of a queue that may be taken now.
+    async fn ready_jobs(&self, queue: &str, now: DateTime<Utc>) -> anyhow::Result<Vec<JobDoc>> {
+        let selector = json!({
+            "queue": queue,
+            "status": JobStatus::Queued,
+            "id": { "$gt": null },
+            "$or": [
+                { "retry_at": null },
+                { "retry_at": { "$lte": now } }
+            ]
+        });
+        let res = self
+            .db
+            .find_sorted(&selector, &["queue", "status", "id"], TAKE_CANDIDATES)
+            .await?;
+        let jobs = res
+            .docs
+            .into_iter()
+            .filter_map(|doc| JobDoc::from_doc(doc).ok())
+            .filter(|job| job.is_ready(now))
+            .collect();
+        Ok(jobs)
+    }
+
+    /// Check running and ended jobs for timeouts and expiry, unless this was done less than
+    /// [SWEEP_INTERVAL] ago.
+    async fn sweep(&self) {
+        {
+            let mut last_sweep = self.last_sweep.lock().unwrap();
+            if matches!(*last_sweep, Some(at) if at.elapsed() < SWEEP_INTERVAL) {
+                return;
+            }
+            *last_sweep = Some(Instant::now());
+        }
+        let selector = json!({
+            "$or": [
+                { "status": JobStatus::Running },
+                { "ended": true }
+            ]
+        });
+        let res = match self.find_jobs(selector).await {
+            Ok(jobs) => self.check_jobs(jobs, Utc::now()).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = res {
+            log::warn!("Failed to check jobs for timeouts: {}", err);
+        }
+    }
+
+    /// Check jobs for timeouts and expiry and save the changes.
+    ///
+    /// Returns the jobs that did not expire, with the changes applied.
+    async fn check_jobs(
+        &self,
+        jobs: Vec<JobDoc>,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<JobDoc>> {
+        let mut unchanged = vec![];
+        let mut changed = vec![];
+        let mut docs = vec![];
+        for mut job in jobs.into_iter() {
+            match job.check(now) {
+                Check::Unchanged => unchanged.push(job),
+                Check::Changed => {
+                    docs.push(job.to_doc()?);
+                    changed.push(job);
+                }
+                Check::Expired => {
+                    let mut doc = Doc::new(job.doc_meta(), Default::default());
+                    doc.doc
+                        .insert("_deleted".to_string(), serde_json::Value::Bool(true));
+                    docs.push(doc);
+                }
+            }
+        }
+        if docs.is_empty() {
+            return Ok(unchanged);
+        }
+
+        let results = self.db.put_bulk(docs).await?;
+        for result in results.into_iter() {
+            if let PutResult::Ok(res) = result {
+                if let Some(job) = changed
+                    .iter_mut()
+                    .find(|job| job_doc_id(job.info.id) == res.id)
+                {
+                    job.rev = Some(res.rev);
+                }
+            }
+        }
+        // Changes that failed to save conflicted with other updates. These jobs keep their
+        // old revision, so that they cannot be taken by this instance.
+        let mut jobs: Vec<JobDoc> = unchanged.into_iter().chain(changed.into_iter()).collect();
+        jobs.sort_by_key(|job| job.info.id);
+        Ok(jobs)
+    }
+
+    /// Put a doc unless its revision is outdated.
+    ///
+    /// Returns the new revision, or `None` if the doc was changed in the meantime.
+    async fn put_exclusive(&self, doc: Doc) -> anyhow::Result<Option<String>> {
+        let res = self.db.try_put_doc(doc).await?;
+        Ok(res.map(|res| res.rev))
+    }
+
+    /// Load a job, apply a change and save it, retrying on conflicts.
+    async fn update_with<F>(&self, job_id: JobId, mut change: F) -> anyhow::Result<()>
+    where
+        F: FnMut(&mut JobDoc, DateTime<Utc>) -> anyhow::Result<()> + Send,
+    {
+        for _ in 0..MAX_CONFLICTS {
+            let mut job = self.load_job(job_id).await?;
+            change(&mut job, Utc::now())?;
+            if self.put_exclusive(job.to_doc()?).await?.is_some() {
+                return Ok(());
+            }
+        }
+        Err(anyhow::anyhow!(
+            "Failed to update job {}: Too many conflicts",
+            job_id
+        ))
+    }
+}
+
+#[async_trait::async_trait]
+impl JobQueue for CouchQueue {
+    async fn init(&self) -> anyhow::Result<()> {
+        self.db
+            .create_index(JOBS_INDEX, &["queue", "status", "id"])
+            .await?;
+        Ok(())
+    }
+
+    async fn get_queues(&self) -> anyhow::Result<Vec<String>> {
+        let docs = self.db.get_all_with_prefix(QUEUE_PREFIX).await?;
+        let queues = docs
+            .rows
+            .iter()
+            .filter_map(|row| row.id.strip_prefix(QUEUE_PREFIX))
+            .map(|name| name.to_string())
+            .collect();
+        Ok(queues)
+    }
+
+    async fn get_queue(&self, name: &str) -> anyhow::Result<Option<Queue>> {
+        match self.db.get_doc(&queue_doc_id(name)).await {
+            Ok(doc) => Ok(Some(doc.into_typed()?)),
+            Err(err) if is_not_found(&err) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn put_queue(&self, name: &str, queue: Queue) -> anyhow::Result<()> {
+        let doc = Doc::from_typed(DocMeta::with_id(queue_doc_id(name)), queue)?;
+        self.db.put_doc(doc).await?;
+        Ok(())
+    }
+
+    async fn put_job(&self, queue: &str, job: JobCreate) -> anyhow::Result<JobId> {
+        let settings = self
+            .get_queue(queue)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Queue not found: {}", queue))?;
+        let id = self.next_id().await?;
+        let job = JobDoc::new(id, queue, job, settings, Utc::now());
+        if self.put_exclusive(job.to_doc()?).await?.is_none() {
+            return Err(anyhow::anyhow!("Job {} already exists", id));
+        }
+        Ok(id)
+    }
+
+    async fn take_job(&self, queue: &str) -> anyhow::Result<Option<JobInput>> {
+        self.sweep().await;
+        let now = Utc::now();
+        let jobs = self.ready_jobs(queue, now).await?;
+        for mut job in jobs.into_iter() {
+            job.start(now);
+            // If another worker took the job in the meantime, try the next one.
+            if self.put_exclusive(job.to_doc()?).await?.is_some() {
+                return Ok(Some(JobInput {
+                    id: job.info.id,
+                    input: job.info.input,
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn update_job(
+        &self,
+        job_id: JobId,
+        status: Option<JobStatus>,
+        output: Option<JobOutput>,
+    ) -> anyhow::Result<()> {
+        self.update_with(job_id, |job, now| {
+            if let Some(output) = &output {
+                match job.info.output.as_mut() {
+                    Some(current) => current.merge(output),
+                    None => job.info.output = Some(output.clone()),
+                }
+                // Progress reports of running jobs count as heartbeats.
+                if job.info.status == JobStatus::Running {
+                    job.info.last_heartbeat = Some(now);
+                }
+            }
+            if let Some(status) = &status {
+                job.set_status(status.clone(), now)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn requeue_job(&self, job_id: JobId) -> anyhow::Result<()> {
+        self.update_with(job_id, |job, _now| {
+            if job.info.status != JobStatus::Running {
+                return Err(anyhow::anyhow!("Job {} is not running", job_id));
+            }
+            job.requeue();
+            Ok(())
+        })
+        .await
+    }
+
+    async fn heartbeat(&self, job_id: JobId) -> anyhow::Result<()> {
+        self.update_with(job_id, |job, now| {
+            if job.info.status != JobStatus::Running {
+                return Err(anyhow::anyhow!("Job {} is not running", job_id));
+            }
+            job.info.last_heartbeat = Some(now);
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_job(&self, job_id: JobId) -> anyhow::Result<JobInfo> {
+        let job = self.load_job(job_id).await?;
+        let mut jobs = self.check_jobs(vec![job], Utc::now()).await?;
+        match jobs.pop() {
+            Some(job) => Ok(job.info),
+            None => Err(JobNotFound(job_id).into()),
+        }
+    }
+
+    async fn delete_job(&self, job_id: JobId) -> anyhow::Result<()> {
+        self.db.delete_doc(&job_doc_id(job_id)).await?;
+        Ok(())
+    }
+
+    async fn fetch_filtered(&self, filter: JobFilter) -> anyhow::Result<Vec<JobInfo>> {
+        self.sweep().await;
+        let jobs = self.find_jobs(filter_selector(&filter)).await?;
+        let jobs = self.check_jobs(jobs, Utc::now()).await?;
+        let jobs = jobs
+            .into_iter()
+            .map(|job| job.info)
+            .filter(|job| filter.matches(job))
+            .collect();
+        Ok(jobs)
+    }
+}
+
+impl JobDoc {
+    fn new(id: JobId, queue: &str, job: JobCreate, settings: Queue, now: DateTime<Utc>) -> Self {
+        let retries = job
+            .retries
+            .unwrap_or_else(|| u32::try_from(settings.retries).unwrap_or(u32::MAX));
+        let retry_delays = if job.retry_delays.is_empty() {
+            settings.retry_delays
+        } else {
+            job.retry_delays
+        };
+        let info = JobInfo {
+            id,
+            queue: queue.to_string(),
+            status: JobStatus::Queued,
+            tags: job.tags,
+            created_at: Some(now),
+            ended_at: None,
+            last_heartbeat: None,
+            input: job.input,
+            output: None,
+            timeout: job.timeout.unwrap_or(settings.timeout),
+            heartbeat_timeout: job.heartbeat_timeout.unwrap_or(settings.heartbeat_timeout),
+            expires_after: job.expires_after.unwrap_or(settings.expires_after),
+            retries,
+            retries_attempted: 0,
+            retry_delays: Some(retry_delays),
+            ended: false,
+        };
+        Self {
+            rev: None,
+            info,
+            started_at: None,
+            retry_at: None,
+        }
+    }
+
+    fn from_doc(doc: Doc) -> anyhow::Result<Self> {
+        let rev = doc.rev().map(|rev| rev.to_string());
+        let mut job: JobDoc = doc.into_typed()?;
+        job.rev = rev;
+        Ok(job)
+    }
+
+    fn doc_meta(&self) -> DocMeta {
+        DocMeta::new(job_doc_id(self.info.id), self.rev.clone())
+    }
+
+    fn to_doc(&self) -> anyhow::Result<Doc> {
+        Doc::from_typed(self.doc_meta(), self)
+    }
+
+    /// Whether the job is queued and may be taken now.
+    fn is_ready(&self, now: DateTime<Utc>) -> bool {
+        self.info.status == JobStatus::Queued && self.retry_at.map_or(true, |at| at <= now)
+    }
+
+    fn start(&mut self, now: DateTime<Utc>) {
+        self.info.status = JobStatus::Running;
+        self.info.last_heartbeat = None;
+        self.started_at = Some(now);
+        self.retry_at = None;
+    }
+
+    /// Queue the job again without counting a retry.
+    fn requeue(&mut self) {
+        self.info.status = JobStatus::Queued;
+        self.info.last_heartbeat = None;
+        self.started_at = None;
+        self.retry_at = None;
+    }
+
+    /// Set the status of the job as requested by a worker or admin.
+    fn set_status(&mut self, status: JobStatus, now: DateTime<Utc>) -> anyhow::Result<()> {
+        if self.info.ended {
+            return Err(anyhow::anyhow!("Job {} has already ended", self.info.id));
+        }
+        match status {
+            JobStatus::Completed | JobStatus::Cancelled => self.end(status, now),
+            JobStatus::Failed => self.fail(status, now),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Job status cannot be set to {:?} manually",
+                    status
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the job for timeouts and expiry.
+    fn check(&mut self, now: DateTime<Utc>) -> Check {
+        if self.info.ended {
+            match self.info.ended_at {
+                Some(ended_at) if elapsed(ended_at, &self.info.expires_after, now) => {
+                    Check::Expired
+                }
+                _ => Check::Unchanged,
+            }
+        } else if self.info.status == JobStatus::Running {
+            let started_at = self.started_at.unwrap_or(now);
+            let last_heartbeat = self.info.last_heartbeat.unwrap_or(started_at);
+            if elapsed(started_at, &self.info.timeout, now)
+                || elapsed(last_heartbeat, &self.info.heartbeat_timeout, now)
+            {
+                self.fail(JobStatus::TimedOut, now);
+                Check::Changed
+            } else {
+                Check::Unchanged
+            }
+        } else {
+            Check::Unchanged
+        }
+    }
+
+    /// Queue the job again if it has retries left, otherwise end it with a status.
+    fn fail(&mut self, status: JobStatus, now: DateTime<Utc>) {
+        if self.info.retries_attempted < self.info.retries {
+            let delays = self.info.retry_delays.as_deref().unwrap_or_default();
+            let delay = delays
+                .get(self.info.retries_attempted as usize)
+                .or_else(|| delays.last())
+                .and_then(|delay| chrono::Duration::from_std(delay.0).ok());
+            self.info.retries_attempted += 1;
+            self.info.status = JobStatus::Queued;
+            self.info.last_heartbeat = None;
+            self.started_at = None;
+            self.retry_at = delay.and_then(|delay| now.checked_add_signed(delay));
+        } else {
+            self.end(status, now);
+        }
+    }
+
+    fn end(&mut self, status: JobStatus, now: DateTime<Utc>) {
+        self.info.status = status;
+        self.info.ended = true;
+        self.info.ended_at = Some(now);
+        self.retry_at = None;
+    }
+}
+
+/// Whether a duration has elapsed since a point in time. Durations too large to represent
+/// never elapse.
+fn elapsed(since: DateTime<Utc>, duration: &Duration, now: DateTime<Utc>) -> bool {
+    chrono::Duration::from_std(duration.0)
+        .ok()
+        .and_then(|duration| since.checked_add_signed(duration))
+        .map_or(false, |until| until <= now)
+}
+
+fn job_doc_id(id: JobId) -> String {
+    // Zero-padded so that the jobs are sorted by ID.
+    format!("{}{:020}", JOB_PREFIX, id)
+}
+
+/// A Mango condition on `_id` that matches all jobs.
+fn job_id_range() -> Value {
+    json!({ "$gt": JOB_PREFIX, "$lt": format!("{}\u{ffff}", JOB_PREFIX) })
+}
+
+/// A Mango selector for the jobs that match a filter.
+fn filter_selector(filter: &JobFilter) -> Value {
+    let mut selector = json!({});
+    if !filter.queue.is_empty() {
+        selector["queue"] = json!({ "$in": filter.queue });
+    }
+    if !filter.status.is_empty() {
+        selector["status"] = json!({ "$in": filter.status });
+    }
+    if !filter.tag.is_empty() {
+        selector["tags"] = json!({ "$elemMatch": { "$in": filter.tag } });
+    }
+    selector
+}
+
+fn queue_doc_id(name: &str) -> String {
+    format!("{}{}", QUEUE_PREFIX, name)
+}
+
+fn is_not_found(err: &CouchError) -> bool {
+    matches!(err, CouchError::NotFound) || err.status_code() == Some(404)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time;
+
+    fn minutes(minutes: u64) -> Duration {
+        time::Duration::from_secs(minutes * 60).into()
+    }
+
+    fn queue() -> Queue {
+        Queue {
+            timeout: minutes(60),
+            heartbeat_timeout: minutes(5),
+            expires_after: minutes(60 * 24),
+            retries: 1,
+            retry_delays: vec![minutes(10)],
+        }
+    }
+
+    fn job(now: DateTime<Utc>) -> JobDoc {
+        let create = JobCreate::with_tags(serde_json::json!({}), vec!["oas.Media_1".into()]);
+        JobDoc::new(1, "asr", create, queue(), now)
+    }
+
+    #[test]
+    fn job_settings_from_queue() {
+        let now = Utc::now();
+        let mut create = JobCreate::with_defaults(serde_json::json!({ "media_id": "1" }));
+        create.retries = Some(3);
+        let job = JobDoc::new(7, "asr", create, queue(), now);
+        assert_eq!(job.info.status, JobStatus::Queued);
+        assert_eq!(job.info.retries, 3);
+        assert_eq!(job.info.heartbeat_timeout, minutes(5));
+        assert_eq!(job.info.retry_delays, Some(vec![minutes(10)]));
+        assert!(job.is_ready(now));
+        assert_eq!(job_doc_id(job.info.id), "job_00000000000000000007");
+
+        let doc = job.to_doc().unwrap();
+        let decoded = JobDoc::from_doc(doc).unwrap();
+        assert_eq!(decoded, job);
+    }
+
+    #[test]
+    fn heartbeat_timeout_and_retry() {
+        let now = Utc::now();
+        let mut job = job(now);
+        job.start(now);
+        assert_eq!(
+            job.check(now + chrono::Duration::minutes(4)),
+            Check::Unchanged
+        );
+        job.info.last_heartbeat = Some(now + chrono::Duration::minutes(4));
+        assert_eq!(
+            job.check(now + chrono::Duration::minutes(8)),
+            Check::Unchanged
+        );
+
+        // Missing heartbeats time out the job, which is retried after the retry delay.
+        let later = now + chrono::Duration::minutes(10);
+        assert_eq!(job.check(later), Check::Changed);
+        assert_eq!(job.info.status, JobStatus::Queued);
+        assert_eq!(job.info.retries_attempted, 1);
+        assert!(!job.is_ready(later));
+        assert!(job.is_ready(later + chrono::Duration::minutes(10)));
+
+        // Without retries left, the job ends and expires later on.
+        job.start(later);
+        assert_eq!(
+            job.check(later + chrono::Duration::minutes(61)),
+            Check::Changed
+        );
+        assert_eq!(job.info.status, JobStatus::TimedOut);
+        assert!(job.info.ended);
+        assert_eq!(
+            job.check(later + chrono::Duration::hours(2)),
+            Check::Unchanged
+        );
+        assert_eq!(job.check(later + chrono::Duration::days(2)), Check::Expired);
+    }
+
+    #[test]
+    fn selector_from_filter() {
+        let filter = JobFilter::all()
+            .with_queue("asr")
+            .with_status(JobStatus::Queued)
+            .with_tag("oas.Media_1".into());
+        let selector = filter_selector(&filter);
+        assert_eq!(
+            selector,
+            json!({
+                "queue": { "$in": ["asr"] },
+                "status": { "$in": ["queued"] },
+                "tags": { "$elemMatch": { "$in": ["oas.Media_1"] } }
+            })
+        );
+        assert_eq!(filter_selector(&JobFilter::all()), json!({}));
+    }
+
+    #[test]
+    fn failed_jobs_are_retried() {
+        let now = Utc::now();
+        let mut job = job(now);
+        job.start(now);
+        job.set_status(JobStatus::Failed, now).unwrap();
+        assert_eq!(job.info.status, JobStatus::Queued);
+        assert!(!job.info.ended);
+
+        job.start(now);
+        job.set_status(JobStatus::Failed, now).unwrap();
+        assert_eq!(job.info.status, JobStatus::Failed);
+        assert!(job.info.ended);
+        assert!(job.set_status(JobStatus::Completed, now).is_err());
+
+        let mut job = self::job(now);
+        job.start(now);
+        assert!(job.set_status(JobStatus::Running, now).is_err());
+    }
+
+    #[test]
+    fn requeued_jobs_keep_their_retries() {
+        let now = Utc::now();
+        let mut job = job(now);
+        job.start(now);
+        job.requeue();
+        assert_eq!(job.info.status, JobStatus::Queued);
+        assert_eq!(job.info.retries_attempted, 0);
+        assert!(job.is_ready(now));
+    }
+}
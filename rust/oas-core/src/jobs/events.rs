@@ -0,0 +1,158 @@
+//! Live job events.
+//!
+//! The [JobManager](super::JobManager) publishes an event whenever a job is created, taken by
+//! a worker, reports progress, completes or fails. Only changes made through this server are
+//! published: Jobs that time out or expire in the queue do not emit events.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio::sync::broadcast;
+
+use super::{JobId, JobInfo, JobStatus};
+
+/// Number of events kept for slow subscribers.
+const CAPACITY: usize = 1024;
+
+/// A change of the status or progress of a job.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct JobEvent {
+    pub id: JobId,
+    pub queue: String,
+    pub status: JobStatus,
+    pub subjects: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<HashMap<String, String>>,
+    pub ended: bool,
+}
+
+impl From<JobInfo> for JobEvent {
+    fn from(job: JobInfo) -> Self {
+        let (progress, error, meta) = match job.output {
+            Some(output) => (output.progress, output.error, output.meta),
+            None => (None, None, None),
+        };
+        Self {
+            id: job.id,
+            queue: job.queue,
+            status: job.status,
+            subjects: job.tags,
+            progress,
+            error,
+            meta,
+            ended: job.ended,
+        }
+    }
+}
+
+/// Filter for job events. Events match if they match all non-empty lists.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct JobEventFilter {
+    /// Job IDs.
+    #[serde(default)]
+    pub job: Vec<JobId>,
+    /// Queues (job types).
+    #[serde(default)]
+    pub queue: Vec<String>,
+    /// Subject GUIDs.
+    #[serde(default)]
+    pub subject: Vec<String>,
+}
+
+impl JobEventFilter {
+    pub fn matches(&self, event: &JobEvent) -> bool {
+        (self.job.is_empty() || self.job.contains(&event.id))
+            && (self.queue.is_empty() || self.queue.contains(&event.queue))
+            && (self.subject.is_empty()
+                || event
+                    .subjects
+                    .iter()
+                    .any(|subject| self.subject.contains(subject)))
+    }
+}
+
+/// Broadcast channel for job events.
+#[derive(Debug, Clone)]
+pub struct JobEvents {
+    sender: broadcast::Sender<JobEvent>,
+}
+
+impl Default for JobEvents {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        Self { sender }
+    }
+}
+
+impl JobEvents {
+    /// Publish an event to all current subscribers.
+    pub fn publish(&self, event: JobEvent) {
+        // Sending only fails if there are no subscribers.
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<JobEvent> {
+        self.sender.subscribe()
+    }
+
+    pub fn has_subscribers(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(id: JobId, queue: &str, subject: &str) -> JobEvent {
+        JobEvent {
+            id,
+            queue: queue.into(),
+            status: JobStatus::Running,
+            subjects: vec![subject.into()],
+            progress: Some(50.),
+            error: None,
+            meta: None,
+            ended: false,
+        }
+    }
+
+    #[test]
+    fn filters() {
+        let asr = event(1, "asr", "oas.Media_1");
+        let nlp = event(2, "nlp", "oas.Post_1");
+        assert!(JobEventFilter::default().matches(&asr));
+
+        let filter = JobEventFilter {
+            queue: vec!["asr".into()],
+            ..Default::default()
+        };
+        assert!(filter.matches(&asr));
+        assert!(!filter.matches(&nlp));
+
+        let filter = JobEventFilter {
+            job: vec![2],
+            subject: vec!["oas.Post_1".into(), "oas.Post_2".into()],
+            ..Default::default()
+        };
+        assert!(!filter.matches(&asr));
+        assert!(filter.matches(&nlp));
+    }
+
+    #[test]
+    fn publish() {
+        let events = JobEvents::default();
+        assert!(!events.has_subscribers());
+        events.publish(event(1, "asr", "oas.Media_1"));
+
+        let mut receiver = events.subscribe();
+        assert!(events.has_subscribers());
+        events.publish(event(2, "asr", "oas.Media_1"));
+        assert_eq!(receiver.try_recv().unwrap().id, 2);
+        assert!(receiver.try_recv().is_err());
+    }
+}
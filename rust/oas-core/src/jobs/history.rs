@@ -0,0 +1,208 @@
+//! Job states on records.
+//!
+//! The state of the latest job of each type (queued, running, completed or failed) is stored
+//! in the `$meta.jobs` of the subjects of the job. The states are returned with the records
+//! from the API, and `$meta.jobs.status` (e.g. `asr:failed`) is indexed as a keyword.
+
+use chrono::Utc;
+use oas_common::jobs::JobState;
+use oas_common::task::{TaskFinishedState, TaskQueuedState, TaskRunningState, TaskState};
+
+use super::JobId;
+use crate::couch::{CouchDB, Doc};
+
+/// Number of attempts to write a job state on a record that is changed concurrently.
+const MAX_CONFLICTS: usize = 10;
+
+/// The settings of a job, from the job arguments.
+pub(super) fn settings(args: &serde_json::Value) -> Option<serde_json::Value> {
+    args.get("opts").filter(|opts| !opts.is_null()).cloned()
+}
+
+pub(super) fn queued(job_id: JobId) -> TaskState {
+    TaskState::Queued(TaskQueuedState {
+        task_id: job_id.to_string(),
+        queued: Utc::now(),
+    })
+}
+
+/// The state of a job that was just created.
+///
+/// The state is written after the job was pushed onto the queue, so a worker may have taken
+/// the job in the meantime. A later state of the same job is kept.
+pub(super) fn created(
+    job_id: JobId,
+    previous: Option<&JobState>,
+    settings: Option<serde_json::Value>,
+) -> JobState {
+    let task_id = job_id.to_string();
+    match previous {
+        Some(state)
+            if state.state.task_id() == Some(task_id.as_str())
+                && !matches!(state.state, TaskState::Queued(_)) =>
+        {
+            state.clone()
+        }
+        _ => JobState::new(queued(job_id), settings),
+    }
+}
+
+pub(super) fn running(job_id: JobId, worker: Option<String>) -> TaskState {
+    TaskState::Running(TaskRunningState {
+        task_id: job_id.to_string(),
+        start: Utc::now(),
+        worker,
+    })
+}
+
+/// The state of a finished job.
+///
+/// Start time and worker are taken from the running state of the same job, if any.
+pub(super) fn finished(
+    job_id: JobId,
+    previous: Option<&JobState>,
+    error: Option<String>,
+    duration: Option<f32>,
+) -> TaskState {
+    let task_id = job_id.to_string();
+    let end = Utc::now();
+    let (start, worker) = match previous.map(|state| &state.state) {
+        Some(TaskState::Running(state)) if state.task_id == task_id => {
+            (Some(state.start), state.worker.clone())
+        }
+        _ => (None, None),
+    };
+    let took = duration
+        .or_else(|| start.map(|start| (end - start).num_milliseconds() as f32 / 1000.))
+        .unwrap_or_default();
+    TaskState::Finished(TaskFinishedState {
+        task_id,
+        success: error.is_none(),
+        error,
+        start,
+        end: Some(end),
+        took,
+        worker,
+    })
+}
+
+/// Convert the error of a failed job to a string.
+pub(super) fn error_string(error: &serde_json::Value) -> String {
+    match error {
+        serde_json::Value::String(error) => error.clone(),
+        serde_json::Value::Null => "Unknown error".to_string(),
+        error => error.to_string(),
+    }
+}
+
+/// Store a job state on the subjects of a job.
+///
+/// The new state is derived from the current state of the job type on each record.
+/// Settings are kept from the current state if the new state has none, and records whose state
+/// does not change are not written. Records are written
+/// with the rev they were loaded with, and loaded again on conflicts, so that concurrent
+/// changes to the records are not overwritten.
+pub(super) async fn set_state<F>(
+    db: &CouchDB,
+    subjects: &[String],
+    typ: &str,
+    update: F,
+) -> anyhow::Result<()>
+where
+    F: Fn(Option<&JobState>) -> JobState,
+{
+    for guid in subjects {
+        set_record_state(db, guid, typ, &update).await?;
+    }
+    Ok(())
+}
+
+async fn set_record_state<F>(db: &CouchDB, guid: &str, typ: &str, update: &F) -> anyhow::Result<()>
+where
+    F: Fn(Option<&JobState>) -> JobState,
+{
+    for _ in 0..MAX_CONFLICTS {
+        let doc = match db.get_doc(guid).await {
+            Ok(doc) => doc,
+            Err(err) if err.status_code() == Some(404) => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        let meta = doc.meta.clone();
+        let mut record = doc.into_untyped_record()?;
+        let jobs = record.meta_mut().jobs_mut();
+        let previous = jobs.state(typ);
+        let mut state = update(previous);
+        if state.settings.is_none() {
+            state.settings = previous.and_then(|state| state.settings.clone());
+        }
+        if previous == Some(&state) {
+            return Ok(());
+        }
+        jobs.set_state(typ, state);
+        let doc = Doc::new(meta, Doc::from_untyped_record(record).doc);
+        if db.try_put_doc(doc).await?.is_some() {
+            return Ok(());
+        }
+    }
+    anyhow::bail!(
+        "Failed to set {} job state on {}: Too many conflicts",
+        typ,
+        guid
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn finished_states() {
+        let running = JobState::new(running(1, Some("worker-1".into())), None);
+        let state = finished(1, Some(&running), None, Some(3.5));
+        match state {
+            TaskState::Finished(state) => {
+                assert!(state.success);
+                assert!(state.start.is_some());
+                assert_eq!(state.took, 3.5);
+                assert_eq!(state.worker.as_deref(), Some("worker-1"));
+            }
+            _ => panic!("expected finished state"),
+        }
+
+        let error = error_string(&json!({ "message": "out of memory" }));
+        let state = finished(2, Some(&running), Some(error), None);
+        assert_eq!(state.status(), "failed");
+        match state {
+            TaskState::Finished(state) => {
+                assert!(state.start.is_none());
+                assert_eq!(state.worker, None);
+                assert_eq!(
+                    state.error.as_deref(),
+                    Some(r#"{"message":"out of memory"}"#)
+                );
+            }
+            _ => panic!("expected finished state"),
+        }
+
+        assert_eq!(
+            settings(&json!({ "opts": { "model": "small" } })),
+            Some(json!({ "model": "small" }))
+        );
+        assert_eq!(settings(&json!({ "opts": null })), None);
+    }
+
+    #[test]
+    fn created_state_keeps_later_states() {
+        let settings = Some(json!({ "model": "small" }));
+        let state = created(1, None, settings.clone());
+        assert_eq!(state.state.status(), "queued");
+        assert_eq!(state.settings, settings);
+
+        let running = JobState::new(running(1, None), None);
+        assert_eq!(created(1, Some(&running), settings.clone()), running);
+
+        let previous = JobState::new(finished(0, None, None, None), None);
+        assert_eq!(created(1, Some(&previous), None).state.status(), "queued");
+    }
+}
@@ -0,0 +1,257 @@
+//! Locks that make job creation idempotent.
+//!
+//! Before a job is created, a lock document keyed by the job type, the subjects and the job
+//! arguments is created in CouchDB. As long as the job of a lock is pending, creating the same
+//! job again returns the pending job instead of enqueuing a duplicate. Locks are deleted when
+//! their job ends. Locks of jobs that ended otherwise (e.g. timed out in the queue) are taken
+//! over by the next job with the same key, and are deleted on [cleanup](JobLocks::cleanup).
+
+use chrono::{DateTime, Utc};
+use oas_common::util::{id_from_hashed_string, sorted_json};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::Duration;
+
+use super::{JobCreateRequest, JobId, JobInfo, JobNotFound, JobQueue};
+use crate::couch::{CouchDB, Doc, DocMeta};
+
+const LOCK_PREFIX: &str = "lock_";
+const MAX_CONFLICTS: usize = 10;
+/// Time after which a lock without a job is considered stale (e.g. because its creator
+/// crashed before the job was created).
+const CREATE_TIMEOUT_SECS: i64 = 60;
+/// Interval in which a lock is checked while another creator creates its job.
+const CREATE_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// The idempotency key of a job request.
+///
+/// Derived from the job type, the subjects and the arguments (including the job settings).
+pub fn idempotency_key(job: &JobCreateRequest) -> String {
+    let mut subjects = job.subjects.clone();
+    subjects.sort();
+    let args = sorted_json(job.args.clone());
+    id_from_hashed_string(format!("{}\n{}\n{}", job.typ, subjects.join(","), args))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct JobLock {
+    typ: String,
+    subjects: Vec<String>,
+    job_id: Option<JobId>,
+    created_at: DateTime<Utc>,
+}
+
+/// The idempotency key of an existing job.
+pub fn job_idempotency_key(job: &JobInfo) -> String {
+    idempotency_key(&JobCreateRequest {
+        typ: job.queue.clone(),
+        args: job.input.clone(),
+        subjects: job.tags.clone(),
+    })
+}
+
+/// The result of acquiring a lock.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Lock {
+    /// The lock was acquired at a revision; the job may be created.
+    Acquired(String),
+    /// A job with the same key is pending.
+    Pending(JobId),
+}
+
+/// Lock documents for job creation, stored in the jobs database.
+#[derive(Debug, Clone)]
+pub struct JobLocks {
+    db: CouchDB,
+}
+
+impl JobLocks {
+    pub fn new(db: CouchDB) -> Self {
+        Self { db }
+    }
+
+    /// Acquire the lock for a job request, unless a job with the same key is pending.
+    ///
+    /// If another creator holds the lock, this waits until its job is created.
+    pub async fn acquire(
+        &self,
+        queue: &dyn JobQueue,
+        key: &str,
+        job: &JobCreateRequest,
+    ) -> anyhow::Result<Lock> {
+        let id = lock_doc_id(key);
+        let mut conflicts = 0;
+        while conflicts < MAX_CONFLICTS {
+            let rev = match self.db.get_doc(&id).await {
+                Ok(doc) => {
+                    let rev = doc.rev().map(|rev| rev.to_string());
+                    let lock: JobLock = doc.into_typed()?;
+                    match lock.job_id {
+                        Some(job_id) if is_pending(queue, job_id).await? => {
+                            return Ok(Lock::Pending(job_id));
+                        }
+                        None if !lock.is_stale() => {
+                            log::debug!(
+                                "Job {} for {:?} is being created, wait",
+                                job.typ,
+                                job.subjects
+                            );
+                            tokio::time::sleep(CREATE_POLL_INTERVAL).await;
+                            continue;
+                        }
+                        // The job ended or its lock is stale: Take over the lock.
+                        _ => {}
+                    }
+                    rev
+                }
+                Err(err) if err.status_code() == Some(404) => None,
+                Err(err) => return Err(err.into()),
+            };
+            let lock = JobLock {
+                typ: job.typ.clone(),
+                subjects: job.subjects.clone(),
+                job_id: None,
+                created_at: Utc::now(),
+            };
+            let doc = Doc::from_typed(DocMeta::new(id.clone(), rev), lock)?;
+            // On conflicts, another job with the same key was created in the meantime.
+            if let Some(res) = self.db.try_put_doc(doc).await? {
+                return Ok(Lock::Acquired(res.rev));
+            }
+            conflicts += 1;
+        }
+        Err(anyhow::anyhow!(
+            "Failed to acquire job lock {}: Too many conflicts",
+            key
+        ))
+    }
+
+    /// Store the ID of the created job on an acquired lock.
+    pub async fn set_job(
+        &self,
+        key: &str,
+        rev: String,
+        job: &JobCreateRequest,
+        job_id: JobId,
+    ) -> anyhow::Result<()> {
+        let lock = JobLock {
+            typ: job.typ.clone(),
+            subjects: job.subjects.clone(),
+            job_id: Some(job_id),
+            created_at: Utc::now(),
+        };
+        let doc = Doc::from_typed(DocMeta::new(lock_doc_id(key), Some(rev)), lock)?;
+        if self.db.try_put_doc(doc).await?.is_none() {
+            log::warn!("Job lock {} for job {} was taken over", key, job_id);
+        }
+        Ok(())
+    }
+
+    /// Release an acquired lock without a job.
+    pub async fn release(&self, key: &str) -> anyhow::Result<()> {
+        self.db.delete_doc(&lock_doc_id(key)).await?;
+        Ok(())
+    }
+
+    /// Delete the lock of an ended job, unless it was taken over by another job.
+    pub async fn release_job(&self, key: &str, job_id: JobId) -> anyhow::Result<()> {
+        let doc = match self.db.get_doc(&lock_doc_id(key)).await {
+            Ok(doc) => doc,
+            Err(err) if err.status_code() == Some(404) => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        let meta = doc.meta.clone();
+        let lock: JobLock = doc.into_typed()?;
+        if lock.job_id == Some(job_id) {
+            self.delete(meta).await?;
+        }
+        Ok(())
+    }
+
+    /// Delete the locks of all jobs that have ended. Returns the number of deleted locks.
+    pub async fn cleanup(&self, queue: &dyn JobQueue) -> anyhow::Result<usize> {
+        let docs = self.db.get_all_with_prefix(LOCK_PREFIX).await?;
+        let mut deleted = 0;
+        for row in docs.rows {
+            let meta = row.doc.meta.clone();
+            let lock: JobLock = match row.doc.into_typed() {
+                Ok(lock) => lock,
+                Err(err) => {
+                    log::warn!("Invalid job lock {}: {}", row.id, err);
+                    continue;
+                }
+            };
+            let ended = match lock.job_id {
+                Some(job_id) => !is_pending(queue, job_id).await?,
+                None => lock.is_stale(),
+            };
+            if ended && self.delete(meta).await? {
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Delete a lock at a revision. Returns false if the lock was changed in the meantime.
+    async fn delete(&self, meta: DocMeta) -> anyhow::Result<bool> {
+        let doc = Doc::from_typed(meta, json!({ "_deleted": true }))?;
+        Ok(self.db.try_put_doc(doc).await?.is_some())
+    }
+}
+
+impl JobLock {
+    fn is_stale(&self) -> bool {
+        let age = Utc::now() - self.created_at;
+        age.num_seconds() >= CREATE_TIMEOUT_SECS
+    }
+}
+
+/// Check if a job is pending. Jobs that cannot be found anymore have ended.
+async fn is_pending(queue: &dyn JobQueue, job_id: JobId) -> anyhow::Result<bool> {
+    match queue.get_job(job_id).await {
+        Ok(job) => Ok(job.status.pending()),
+        Err(err) if err.is::<JobNotFound>() => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+fn lock_doc_id(key: &str) -> String {
+    format!("{}{}", LOCK_PREFIX, key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn job(args: serde_json::Value, subjects: &[&str]) -> JobCreateRequest {
+        JobCreateRequest {
+            typ: "asr".into(),
+            args,
+            subjects: subjects.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn keys() {
+        let a = job(
+            json!({ "media_id": "1", "opts": { "a": 1, "b": 2 } }),
+            &["oas.Media_1"],
+        );
+        let b = job(
+            json!({ "opts": { "b": 2, "a": 1 }, "media_id": "1" }),
+            &["oas.Media_1"],
+        );
+        assert_eq!(idempotency_key(&a), idempotency_key(&b));
+
+        let other_settings = job(
+            json!({ "media_id": "1", "opts": { "a": 2 } }),
+            &["oas.Media_1"],
+        );
+        assert_ne!(idempotency_key(&a), idempotency_key(&other_settings));
+
+        let mut other_typ = a.clone();
+        other_typ.typ = "nlp".into();
+        assert_ne!(idempotency_key(&a), idempotency_key(&other_typ));
+    }
+}
@@ -1,66 +1,259 @@
 use json_patch::Patch;
+use oas_common::jobs::JobState;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, time::Duration};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::RwLock;
 
-use crate::couch::CouchDB;
+use crate::couch::{CouchDB, CouchManager};
 
 pub mod bin;
+pub mod bulk;
 pub mod changes;
+mod config;
+mod couch_queue;
+pub mod events;
+mod history;
+pub mod locks;
 mod ocypod;
+pub mod pipelines;
+mod queue;
 pub mod typs;
 
+pub use bulk::{BulkCreateRequest, BulkCreateResult, Selection};
+pub use config::{JobsConfig, QueueSettings, DEFAULT_QUEUE};
+pub use couch_queue::CouchQueue;
+pub use events::{JobEvent, JobEventFilter, JobEvents};
+use locks::{JobLocks, Lock};
 pub use ocypod::{
-    JobFilter, JobId, JobInfo, JobInput, JobOutput, JobStatus, OcypodClient, DEFAULT_OCYPOD_URL,
+    JobCreate, JobFilter, JobId, JobInfo, JobInput, JobOutput, JobStatus, OcypodClient, Queue,
+    DEFAULT_OCYPOD_URL,
 };
+pub use pipelines::Pipelines;
+pub use queue::{JobNotFound, JobQueue};
 
 #[derive(Debug, Clone)]
 pub struct JobManager {
-    client: OcypodClient,
+    client: Arc<dyn JobQueue>,
     db: CouchDB,
+    locks: JobLocks,
+    config: Arc<RwLock<JobsConfig>>,
+    config_file: Option<PathBuf>,
+    events: JobEvents,
 }
 
 impl JobManager {
-    pub fn new(db: CouchDB, base_url: impl ToString) -> Self {
+    /// Create a job manager with an Ocypod job queue.
+    pub fn new(db_manager: &CouchManager, base_url: impl ToString) -> Self {
         let client = OcypodClient::new(base_url.to_string());
-        Self { db, client }
+        Self::with_queue(db_manager, client)
     }
 
+    /// Create a job manager with any job queue backend.
+    ///
+    /// Jobs operate on the record database, and job locks are stored in the jobs database.
+    pub fn with_queue(db_manager: &CouchManager, queue: impl JobQueue + 'static) -> Self {
+        Self {
+            db: db_manager.record_db().clone(),
+            locks: JobLocks::new(db_manager.jobs_db().clone()),
+            client: Arc::new(queue),
+            config: Arc::new(RwLock::new(JobsConfig::with_defaults())),
+            config_file: None,
+            events: JobEvents::default(),
+        }
+    }
+
+    /// Load the jobs config from a file on [init](Self::init).
+    pub fn with_config_file(mut self, path: Option<impl Into<PathBuf>>) -> Self {
+        self.config_file = path.map(|path| path.into());
+        self
+    }
+
+    /// Load the jobs config and apply the configured queue settings.
+    pub async fn init(&self) -> anyhow::Result<()> {
+        let config = JobsConfig::load(self.config_file.clone()).await?;
+        // Failing to reach the job queue should not prevent startup.
+        if let Err(err) = self.client.init().await {
+            log::warn!("Failed to initialize the job queue: {}", err);
+        }
+        for name in config.configured_queues() {
+            if let Err(err) = self.client.put_queue(name, config.queue(name)).await {
+                log::warn!("Failed to apply settings for queue {}: {}", name, err);
+            }
+        }
+        *self.config.write().await = config;
+        match self.locks.cleanup(self.client.as_ref()).await {
+            Ok(deleted) => log::debug!("Deleted {} locks of ended jobs", deleted),
+            Err(err) => log::warn!("Failed to clean up job locks: {}", err),
+        }
+        Ok(())
+    }
+
+    /// Get the names and settings of all queues.
+    pub async fn queues(&self) -> anyhow::Result<Vec<QueueInfo>> {
+        let names = self.client.get_queues().await?;
+        let mut queues = vec![];
+        for name in names {
+            if let Some(settings) = self.client.get_queue(&name).await? {
+                queues.push(QueueInfo { name, settings });
+            }
+        }
+        Ok(queues)
+    }
+
+    /// Get the settings of a queue.
+    pub async fn queue(&self, name: &str) -> anyhow::Result<Option<Queue>> {
+        self.client.get_queue(name).await
+    }
+
+    /// Update the settings of a queue, creating the queue if it does not exist yet.
+    pub async fn update_queue(&self, name: &str, settings: QueueSettings) -> anyhow::Result<Queue> {
+        let mut queue = match self.client.get_queue(name).await? {
+            Some(queue) => queue,
+            None => self.config.read().await.queue(name),
+        };
+        settings.apply(&mut queue);
+        self.client.put_queue(name, queue.clone()).await?;
+        Ok(queue)
+    }
+
+    /// Create a job, unless the same job is already pending.
+    ///
+    /// Jobs are the same if they have the same type, subjects and arguments (see
+    /// [idempotency_key](locks::idempotency_key)). If the same job is pending, its ID is
+    /// returned instead.
     pub async fn create_job(&self, job: JobCreateRequest) -> anyhow::Result<ocypod::JobId> {
-        let typ = job.typ;
+        let key = locks::idempotency_key(&job);
+        let rev = match self.locks.acquire(self.client.as_ref(), &key, &job).await? {
+            Lock::Pending(job_id) => {
+                log::debug!(
+                    "Job {} ({}) for {:?} is pending, skip",
+                    job_id,
+                    job.typ,
+                    job.subjects
+                );
+                return Ok(job_id);
+            }
+            Lock::Acquired(rev) => rev,
+        };
+
+        let job_id = match self.put_job(&job).await {
+            Ok(job_id) => job_id,
+            Err(err) => {
+                if let Err(err) = self.locks.release(&key).await {
+                    log::warn!("Failed to release job lock {}: {}", key, err);
+                }
+                return Err(err);
+            }
+        };
+        // The job exists now: Without its ID on the lock, the lock is taken over by the next
+        // job with the same key.
+        if let Err(err) = self.locks.set_job(&key, rev, &job, job_id).await {
+            log::warn!("Failed to set job {} on lock {}: {}", job_id, key, err);
+        }
+        let settings = history::settings(&job.args);
+        self.set_state(&job.subjects, &job.typ, |previous| {
+            history::created(job_id, previous, settings.clone())
+        })
+        .await;
+        self.publish(job_id).await;
+        Ok(job_id)
+    }
+
+    /// Live events for changes of jobs.
+    pub fn events(&self) -> &JobEvents {
+        &self.events
+    }
+
+    /// Publish the current state of a job, if there are subscribers to job events.
+    async fn publish(&self, job_id: JobId) {
+        if !self.events.has_subscribers() {
+            return;
+        }
+        match self.client.get_job(job_id).await {
+            Ok(job) => self.events.publish(job.into()),
+            Err(err) => log::debug!("Failed to publish event for job {}: {}", job_id, err),
+        }
+    }
+
+    /// Store the state of a job on its subjects.
+    ///
+    /// Errors are logged: The job itself is not affected if storing its state fails.
+    async fn set_state<F>(&self, subjects: &[String], typ: &str, update: F)
+    where
+        F: Fn(Option<&JobState>) -> JobState,
+    {
+        if let Err(err) = history::set_state(&self.db, subjects, typ, update).await {
+            log::warn!(
+                "Failed to store {} job state on {:?}: {}",
+                typ,
+                subjects,
+                err
+            );
+        }
+    }
+
+    async fn put_job(&self, job: &JobCreateRequest) -> anyhow::Result<ocypod::JobId> {
+        let typ = &job.typ;
         let queues = self.client.get_queues().await?;
-        if !queues.contains(&typ) {
-            // TODO: Make queue settings adjustable.
-            let queue = ocypod::Queue {
-                timeout: Duration::from_secs(3600).into(),
-                heartbeat_timeout: Duration::from_secs(3600).into(),
-                expires_after: Duration::from_secs(86400).into(),
-                retries: 5,
-                retry_delays: vec![],
-            };
-            self.client.put_queue(&typ, queue).await?;
+        if !queues.contains(typ) {
+            let queue = self.config.read().await.queue(typ);
+            self.client.put_queue(typ, queue).await?;
         }
 
         // Push the job onto the job queue.
-        let job = ocypod::JobCreate::with_tags(job.args, job.subjects);
-        let job_id = self.client.put_job(&typ, job).await?;
+        let create = ocypod::JobCreate::with_tags(job.args.clone(), job.subjects.clone());
+        let job_id = self.client.put_job(typ, create).await?;
 
         Ok(job_id)
     }
 
     pub async fn delete_job(&self, job: JobId) -> anyhow::Result<()> {
+        let info = self.client.get_job(job).await;
         self.client.delete_job(job).await?;
+        if let Ok(info) = info {
+            self.release_lock(&info).await;
+        }
         Ok(())
     }
+
+    /// Delete the lock of an ended job.
+    async fn release_lock(&self, job: &JobInfo) {
+        let key = locks::job_idempotency_key(job);
+        if let Err(err) = self.locks.release_job(&key, job.id).await {
+            log::warn!("Failed to release job lock {}: {}", key, err);
+        }
+    }
     pub async fn take_job_timeout(
         &self,
         typ: &str,
+        worker: Option<&str>,
         timeout: JobTakeTimeout,
     ) -> anyhow::Result<Option<JobRequest>> {
+        self.take_job_until(typ, worker, timeout, || false).await
+    }
+
+    /// Wait for a job like [take_job_timeout](Self::take_job_timeout), but stop waiting once
+    /// `cancelled` returns true. A job that is being taken is always returned.
+    pub async fn take_job_until<F>(
+        &self,
+        typ: &str,
+        worker: Option<&str>,
+        timeout: JobTakeTimeout,
+        cancelled: F,
+    ) -> anyhow::Result<Option<JobRequest>>
+    where
+        F: Fn() -> bool,
+    {
         let start = std::time::Instant::now();
         let poll_interval = std::time::Duration::from_secs(1);
         let mut interval = tokio::time::interval(poll_interval);
         loop {
-            let job = self.take_job(typ).await?;
+            if cancelled() {
+                return Ok(None);
+            }
+            let job = self.take_job(typ, worker).await?;
             match job {
                 Some(job) => return Ok(Some(job)),
                 None => {
@@ -75,7 +268,12 @@ impl JobManager {
         }
     }
 
-    pub async fn take_job(&self, typ: &str) -> anyhow::Result<Option<JobRequest>> {
+    /// Take the next job of a type, optionally for a named worker.
+    pub async fn take_job(
+        &self,
+        typ: &str,
+        worker: Option<&str>,
+    ) -> anyhow::Result<Option<JobRequest>> {
         let input = self.client.take_job(typ).await?;
         if let Some(input) = input {
             let job = self.client.get_job(input.id).await?;
@@ -85,6 +283,13 @@ impl JobManager {
                 args: input.input,
                 subjects: job.tags,
             };
+            let state = history::running(job_request.id, worker.map(|w| w.to_string()));
+            let settings = history::settings(&job_request.args);
+            self.set_state(&job_request.subjects, &job_request.typ, |_| {
+                JobState::new(state.clone(), settings.clone())
+            })
+            .await;
+            self.publish(job_request.id).await;
             Ok(Some(job_request))
         } else {
             Ok(None)
@@ -117,11 +322,19 @@ impl JobManager {
             meta: req.meta,
             duration: None,
         };
-        self.client.update_job(job_id, None, Some(output)).await
+        self.client.update_job(job_id, None, Some(output)).await?;
+        self.publish(job_id).await;
+        Ok(())
+    }
+
+    pub async fn heartbeat(&self, job_id: JobId) -> anyhow::Result<()> {
+        self.client.heartbeat(job_id).await
     }
 
     pub async fn set_failed(&self, job_id: JobId, req: JobFailedRequest) -> anyhow::Result<()> {
         let status = JobStatus::Failed;
+        let error = history::error_string(&req.error);
+        let duration = req.duration;
         let output = JobOutput {
             error: Some(req.error),
             progress: Some(100.0),
@@ -130,7 +343,51 @@ impl JobManager {
         };
         self.client
             .update_job(job_id, Some(status), Some(output))
-            .await
+            .await?;
+
+        // Failed jobs may be queued again for a retry.
+        let job = self.client.get_job(job_id).await?;
+        let retry = job.status.pending();
+        if !retry {
+            self.release_lock(&job).await;
+        }
+        self.set_state(&job.tags, &job.queue, |previous| {
+            let state = match retry {
+                true => history::queued(job_id),
+                false => history::finished(job_id, previous, Some(error.clone()), duration),
+            };
+            JobState::new(state, None)
+        })
+        .await;
+        self.publish(job_id).await;
+        Ok(())
+    }
+
+    /// Put a job that was taken but never handed to a worker back in its queue.
+    ///
+    /// Backends that cannot requeue jobs fail the job instead, which uses up a retry.
+    pub async fn requeue_job(&self, job_id: JobId) -> anyhow::Result<()> {
+        self.client.requeue_job(job_id).await?;
+        let job = self.client.get_job(job_id).await?;
+        let retry = job.status.pending();
+        if !retry {
+            self.release_lock(&job).await;
+        }
+        self.set_state(&job.tags, &job.queue, |previous| {
+            let state = match retry {
+                true => history::queued(job_id),
+                false => history::finished(
+                    job_id,
+                    previous,
+                    Some("The worker disconnected".to_string()),
+                    None,
+                ),
+            };
+            JobState::new(state, None)
+        })
+        .await;
+        self.publish(job_id).await;
+        Ok(())
     }
 
     pub async fn set_completed(
@@ -157,19 +414,24 @@ impl JobManager {
         let job = self.client.get_job(job_id).await?;
         self.on_complete(&job).await?;
 
+        let duration = output.duration;
         self.client
             .update_job(job_id, Some(JobStatus::Completed), Some(output))
             .await?;
+        self.release_lock(&job).await;
+
+        self.set_state(&job.tags, &job.queue, |previous| {
+            JobState::new(history::finished(job_id, previous, None, duration), None)
+        })
+        .await;
+        self.publish(job_id).await;
 
         Ok(())
     }
 
     async fn on_complete(&self, job: &JobInfo) -> anyhow::Result<()> {
-        let typ = &job.queue;
-        if typ.as_str() == typs::ASR {
-            typs::on_asr_complete(&self.db, self, job).await?;
-        }
-        Ok(())
+        let pipelines = self.config.read().await.pipelines.clone();
+        pipelines::on_complete(&self.db, self, &pipelines, job).await
     }
 }
 
@@ -180,6 +442,13 @@ pub enum JobTakeTimeout {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+pub struct QueueInfo {
+    pub name: String,
+    #[serde(flatten)]
+    pub settings: Queue,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JobCreateRequest {
     pub typ: String,
     pub args: serde_json::Value,
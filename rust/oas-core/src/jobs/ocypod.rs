@@ -7,6 +7,8 @@ use std::collections::HashMap;
 use std::fmt;
 use std::time;
 
+use super::{JobNotFound, JobQueue};
+
 pub type JobId = u64;
 pub const DEFAULT_OCYPOD_URL: &str = "http://localhost:8023";
 
@@ -30,6 +32,17 @@ impl OcypodClient {
         Ok(())
     }
 
+    pub async fn get_queue(&self, name: &str) -> anyhow::Result<Option<Queue>> {
+        let url = format!("{}/queue/{}", self.base_url, name);
+        let res = self.client.get(&url).send().await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        check_response(&res)?;
+        let queue: Queue = res.json().await?;
+        Ok(Some(queue))
+    }
+
     pub async fn put_job(&self, queue: &str, job: JobCreate) -> anyhow::Result<JobId> {
         let url = format!("{}/queue/{}/job", self.base_url, queue);
         let res = self.client.post(&url).json(&job).send().await?;
@@ -76,9 +89,19 @@ impl OcypodClient {
         Ok(())
     }
 
+    pub async fn heartbeat(&self, job_id: JobId) -> anyhow::Result<()> {
+        let url = format!("{}/job/{}/heartbeat", self.base_url, job_id);
+        let res = self.client.put(&url).send().await?;
+        check_response(&res)?;
+        Ok(())
+    }
+
     pub async fn get_job(&self, job_id: JobId) -> anyhow::Result<JobInfo> {
         let url = format!("{}/job/{}", self.base_url, job_id);
         let res = self.client.get(&url).send().await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Err(JobNotFound(job_id).into());
+        }
         check_response(&res)?;
         let job: JobInfo = res.json().await?;
         Ok(job)
@@ -196,6 +219,54 @@ impl OcypodClient {
     }
 }
 
+#[async_trait::async_trait]
+impl JobQueue for OcypodClient {
+    async fn get_queues(&self) -> anyhow::Result<Vec<String>> {
+        OcypodClient::get_queues(self).await
+    }
+
+    async fn get_queue(&self, name: &str) -> anyhow::Result<Option<Queue>> {
+        OcypodClient::get_queue(self, name).await
+    }
+
+    async fn put_queue(&self, name: &str, queue: Queue) -> anyhow::Result<()> {
+        OcypodClient::put_queue(self, name, queue).await
+    }
+
+    async fn put_job(&self, queue: &str, job: JobCreate) -> anyhow::Result<JobId> {
+        OcypodClient::put_job(self, queue, job).await
+    }
+
+    async fn take_job(&self, queue: &str) -> anyhow::Result<Option<JobInput>> {
+        OcypodClient::take_job(self, queue).await
+    }
+
+    async fn update_job(
+        &self,
+        job_id: JobId,
+        status: Option<JobStatus>,
+        output: Option<JobOutput>,
+    ) -> anyhow::Result<()> {
+        OcypodClient::update_job(self, job_id, status, output).await
+    }
+
+    async fn heartbeat(&self, job_id: JobId) -> anyhow::Result<()> {
+        OcypodClient::heartbeat(self, job_id).await
+    }
+
+    async fn get_job(&self, job_id: JobId) -> anyhow::Result<JobInfo> {
+        OcypodClient::get_job(self, job_id).await
+    }
+
+    async fn delete_job(&self, job_id: JobId) -> anyhow::Result<()> {
+        OcypodClient::delete_job(self, job_id).await
+    }
+
+    async fn fetch_filtered(&self, filter: JobFilter) -> anyhow::Result<Vec<JobInfo>> {
+        OcypodClient::fetch_filtered(self, filter).await
+    }
+}
+
 fn check_response(res: &reqwest::Response) -> anyhow::Result<()> {
     if res.status().is_success() {
         Ok(())
@@ -415,8 +486,8 @@ impl From<time::Duration> for Duration {
 
 impl<'de> Deserialize<'de> for Duration {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
-        let s: &str = Deserialize::deserialize(deserializer)?;
-        humantime::parse_duration(s)
+        let s: String = Deserialize::deserialize(deserializer)?;
+        humantime::parse_duration(&s)
             .map(Duration)
             .map_err(D::Error::custom)
     }
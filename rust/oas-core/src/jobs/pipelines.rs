@@ -0,0 +1,290 @@
+//! Job pipelines.
+//!
+//! When a job completes, follow-up jobs are created as declared in the pipelines of the jobs
+//! config (see [JobsConfig](super::JobsConfig)).
+
+use oas_common::types::{Media, Post};
+use oas_common::util::split_guid;
+use oas_common::{Record, TypedValue};
+use serde::{Deserialize, Serialize};
+
+use super::changes::has_pending;
+use super::{typs, JobInfo, JobManager};
+use crate::couch::CouchDB;
+
+/// The records to create follow-up jobs for, starting from the record of the completed job.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum Target {
+    /// The record of the completed job.
+    Same,
+    /// The posts of a media (or the post itself).
+    Posts,
+    /// The medias of a post (or the media itself).
+    Media,
+}
+
+impl Default for Target {
+    fn default() -> Self {
+        Self::Same
+    }
+}
+
+/// Conditions on a target record that all have to match to create a follow-up job.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Conditions {
+    /// Fields that are not set (dot-separated paths).
+    #[serde(default)]
+    pub missing: Vec<String>,
+    /// Fields that are set (dot-separated paths).
+    #[serde(default)]
+    pub present: Vec<String>,
+    /// Whether the record has to enable the job type in its job settings.
+    #[serde(default)]
+    pub setting: bool,
+}
+
+impl Conditions {
+    /// Check if a record matches these conditions for a job type.
+    pub fn matches<T: TypedValue>(&self, record: &Record<T>, typ: &str) -> bool {
+        if self.setting && record.meta().jobs().setting(typ).is_none() {
+            return false;
+        }
+        if self.missing.is_empty() && self.present.is_empty() {
+            return true;
+        }
+        let value = match serde_json::to_value(&record.value) {
+            Ok(value) => value,
+            Err(_) => return false,
+        };
+        let is_set = |path: &String| {
+            let pointer = format!("/{}", path.replace('.', "/"));
+            !matches!(
+                value.pointer(&pointer),
+                None | Some(serde_json::Value::Null)
+            )
+        };
+        self.missing.iter().all(|path| !is_set(path)) && self.present.iter().all(is_set)
+    }
+}
+
+/// A step in a pipeline: When a job of type `on` completes, create jobs of type `job`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PipelineStep {
+    pub on: String,
+    pub job: String,
+    #[serde(default)]
+    pub target: Target,
+    #[serde(default)]
+    pub when: Conditions,
+}
+
+/// The list of pipeline steps.
+#[derive(Debug, Default, Clone)]
+pub struct Pipelines {
+    steps: Vec<PipelineStep>,
+}
+
+impl Pipelines {
+    pub fn new(steps: Vec<PipelineStep>) -> Self {
+        Self { steps }
+    }
+
+    /// Get the steps that run after a job of a type completed.
+    pub fn steps_for<'a>(&'a self, typ: &'a str) -> impl Iterator<Item = &'a PipelineStep> + 'a {
+        self.steps.iter().filter(move |step| step.on == typ)
+    }
+
+    pub fn steps(&self) -> &[PipelineStep] {
+        &self.steps
+    }
+}
+
+/// A record that jobs are created for.
+#[derive(Debug, Clone)]
+pub(super) enum Subject {
+    Media(Record<Media>),
+    Post(Record<Post>),
+}
+
+impl Subject {
+    /// Load the record of a job from the subjects that are stored as tags on the job.
+    async fn load(db: &CouchDB, job: &JobInfo) -> anyhow::Result<Option<Self>> {
+        for tag in job.tags.iter() {
+            match split_guid(tag) {
+                Some((typ, id)) if typ == Media::NAME => {
+                    return Ok(Some(Self::Media(db.table::<Media>().get(&id).await?)));
+                }
+                Some((typ, id)) if typ == Post::NAME => {
+                    return Ok(Some(Self::Post(db.table::<Post>().get(&id).await?)));
+                }
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    async fn targets(self, db: &CouchDB, target: Target) -> anyhow::Result<Vec<Self>> {
+        let targets = match (target, self) {
+            (Target::Same, subject)
+            | (Target::Posts, subject @ Self::Post(_))
+            | (Target::Media, subject @ Self::Media(_)) => vec![subject],
+            (Target::Posts, Self::Media(media)) => {
+                let mut posts = vec![];
+                for post_ref in media.value.posts.iter() {
+                    if let Ok(post) = db.table::<Post>().get(post_ref.id()).await {
+                        posts.push(Self::Post(post));
+                    }
+                }
+                posts
+            }
+            (Target::Media, Self::Post(post)) => {
+                let mut medias = vec![];
+                for media_ref in post.value.media.iter() {
+                    if let Ok(media) = db.table::<Media>().get(media_ref.id()).await {
+                        medias.push(Self::Media(media));
+                    }
+                }
+                medias
+            }
+        };
+        Ok(targets)
+    }
+
+    pub(super) fn guid(&self) -> &str {
+        match self {
+            Self::Media(record) => record.guid(),
+            Self::Post(record) => record.guid(),
+        }
+    }
+
+    fn matches(&self, conditions: &Conditions, typ: &str) -> bool {
+        match self {
+            Self::Media(record) => conditions.matches(record, typ),
+            Self::Post(record) => conditions.matches(record, typ),
+        }
+    }
+
+    pub(super) fn job_request(
+        &self,
+        typ: &str,
+        opts: Option<serde_json::Value>,
+    ) -> super::JobCreateRequest {
+        match self {
+            Self::Media(record) => typs::record_job(typ, record, opts),
+            Self::Post(record) => typs::record_job(typ, record, opts),
+        }
+    }
+
+    /// Check if the record already has the results of a job type.
+    ///
+    /// This is the case if the latest job of the type completed, or if the record has a
+    /// transcript (ASR jobs on medias) or NLP results (NLP jobs on posts).
+    pub(super) fn has_results(&self, typ: &str) -> bool {
+        match self {
+            Self::Media(record) => {
+                (typ == typs::ASR && record.value.transcript.is_some())
+                    || record.meta().jobs().completed(typ)
+            }
+            Self::Post(record) => {
+                (typ == typs::NLP && !record.value.nlp.is_null())
+                    || record.meta().jobs().completed(typ)
+            }
+        }
+    }
+}
+
+/// Create the follow-up jobs for a completed job.
+pub async fn on_complete(
+    db: &CouchDB,
+    jobs: &JobManager,
+    pipelines: &Pipelines,
+    job: &JobInfo,
+) -> anyhow::Result<()> {
+    let steps: Vec<&PipelineStep> = pipelines.steps_for(&job.queue).collect();
+    if steps.is_empty() {
+        return Ok(());
+    }
+    let subject = match Subject::load(db, job).await? {
+        Some(subject) => subject,
+        None => return Ok(()),
+    };
+    for step in steps {
+        let targets = subject.clone().targets(db, step.target).await?;
+        for target in targets {
+            if !target.matches(&step.when, &step.job)
+                || has_pending(jobs, &step.job, target.guid()).await
+            {
+                continue;
+            }
+            let req = target.job_request(&step.job, None);
+            let job_id = jobs.create_job(req).await?;
+            log::debug!(
+                "Job {} ({}) created after job {} ({})",
+                job_id,
+                step.job,
+                job.id,
+                job.queue
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobs::JobsConfig;
+    use serde_json::json;
+
+    #[test]
+    fn default_pipelines() {
+        let pipelines = JobsConfig::with_defaults().pipelines;
+        let steps: Vec<_> = pipelines.steps_for(typs::ASR).collect();
+        assert_eq!(steps.len(), 1);
+        assert_eq!(steps[0].job, typs::NLP);
+        assert_eq!(steps[0].target, Target::Posts);
+        assert_eq!(pipelines.steps_for(typs::NLP).count(), 0);
+    }
+
+    #[test]
+    fn conditions() {
+        let pipelines = JobsConfig::from_toml(
+            r#"
+            [[pipeline]]
+            on = "asr"
+            job = "summarize"
+            when = { missing = ["nlp.summary"], present = ["transcript"], setting = true }
+            "#,
+        )
+        .unwrap()
+        .pipelines;
+        let step = pipelines.steps_for("asr").next().unwrap();
+        assert_eq!(step.target, Target::Same);
+
+        let mut media: Media = serde_json::from_value(json!({
+            "contentUrl": "https://example.org/1.mp3",
+            "transcript": { "text": "hello", "parts": [], "segments": [] },
+            "nlp": { "keywords": [] }
+        }))
+        .unwrap();
+        let mut record = Record::from_id_and_value("1", media.clone());
+        assert!(!step.when.matches(&record, &step.job));
+
+        record
+            .meta_mut()
+            .jobs_mut()
+            .settings_mut()
+            .insert("summarize".into(), Some(json!({})));
+        assert!(step.when.matches(&record, &step.job));
+
+        record.value.nlp = Some(json!({ "summary": "hi" }));
+        assert!(!step.when.matches(&record, &step.job));
+
+        media.transcript = None;
+        record.value = media;
+        assert!(!step.when.matches(&record, &step.job));
+    }
+}
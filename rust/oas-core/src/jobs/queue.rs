@@ -0,0 +1,63 @@
+use std::fmt;
+use thiserror::Error;
+
+use super::{JobCreate, JobFilter, JobId, JobInfo, JobInput, JobOutput, JobStatus, Queue};
+
+/// The error returned by [JobQueue::get_job] if a job does not exist (anymore).
+#[derive(Error, Debug)]
+#[error("Job not found: {0}")]
+pub struct JobNotFound(pub JobId);
+
+/// A backend that stores jobs and hands them out to workers.
+///
+/// Implemented by [OcypodClient](super::OcypodClient) (Ocypod on top of Redis) and by
+/// [CouchQueue](super::CouchQueue) (a built-in queue persisted in CouchDB).
+#[async_trait::async_trait]
+pub trait JobQueue: fmt::Debug + Send + Sync {
+    /// Prepare the backend, e.g. create indexes. Called once on startup.
+    async fn init(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Get the names of all queues.
+    async fn get_queues(&self) -> anyhow::Result<Vec<String>>;
+
+    /// Get the settings of a queue, or `None` if the queue does not exist.
+    async fn get_queue(&self, name: &str) -> anyhow::Result<Option<Queue>>;
+
+    /// Create a queue or update its settings.
+    async fn put_queue(&self, name: &str, queue: Queue) -> anyhow::Result<()>;
+
+    /// Push a job onto a queue.
+    async fn put_job(&self, queue: &str, job: JobCreate) -> anyhow::Result<JobId>;
+
+    /// Take the next queued job from a queue and mark it as running.
+    async fn take_job(&self, queue: &str) -> anyhow::Result<Option<JobInput>>;
+
+    /// Set the status of a job and merge the output into its current output.
+    async fn update_job(
+        &self,
+        job_id: JobId,
+        status: Option<JobStatus>,
+        output: Option<JobOutput>,
+    ) -> anyhow::Result<()>;
+
+    /// Put a running job back in its queue without counting a retry.
+    ///
+    /// The default implementation fails the job, which counts a retry.
+    async fn requeue_job(&self, job_id: JobId) -> anyhow::Result<()> {
+        self.update_job(job_id, Some(JobStatus::Failed), None).await
+    }
+
+    /// Signal that a running job is still alive.
+    async fn heartbeat(&self, job_id: JobId) -> anyhow::Result<()>;
+
+    /// Get a job by ID. Fails with [JobNotFound] if the job does not exist.
+    async fn get_job(&self, job_id: JobId) -> anyhow::Result<JobInfo>;
+
+    /// Delete a job by ID.
+    async fn delete_job(&self, job_id: JobId) -> anyhow::Result<()>;
+
+    /// Get all jobs that match a filter.
+    async fn fetch_filtered(&self, filter: JobFilter) -> anyhow::Result<Vec<JobInfo>>;
+}
@@ -68,6 +68,10 @@ impl State {
             .init(&self.db)
             .await
             .context("Failed to initialize RSS feed watcher")?;
+        self.jobs
+            .init()
+            .await
+            .context("Failed to initialize jobs")?;
         self.index_manager
             .init(Default::default())
             .await
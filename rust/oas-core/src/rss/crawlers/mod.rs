@@ -1,14 +1,212 @@
-use std::pin::Pin;
+//! Crawl rules for paginated feeds.
+//!
+//! Many archives publish their feeds in pages, selected by a query parameter (e.g. `offset` or
+//! `start`) or linked with `<atom:link rel="next">`. How to get from one page to the next is
+//! described declaratively in crawl rules, which are loaded from a TOML file (see
+//! `config/crawlers.toml` for the defaults).
 
-use super::Crawler;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tokio::fs::read_to_string;
+use url::Url;
 
-pub fn default_crawlers() -> Vec<Pin<Box<dyn Crawler>>> {
-    vec![Box::pin(frn::FrnCrawler {}), Box::pin(cba::CbaCrawler {})]
+use super::mapping::config_file_path;
+use super::{Crawler, FetchedFeedPage, Next};
+use anyhow::Context;
+
+const DEFAULT_CRAWL_RULES: &str = include_str!("../../../../../config/crawlers.toml");
+const CRAWL_RULES_FILE: &str = "crawlers.toml";
+
+/// How the pagination parameter is increased from one page to the next.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum Step {
+    /// Increase by the number of items on the current page (offset-based pagination).
+    Items,
+    /// Increase by one (page-based pagination).
+    Page,
+    /// Increase by a fixed amount.
+    Fixed(usize),
+}
+
+impl Default for Step {
+    fn default() -> Self {
+        Self::Items
+    }
+}
+
+/// Conditions that end a crawl.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct StopConditions {
+    /// Stop on a page without items. Crawls with `step = "items"` always stop there, as
+    /// they would not get to another page.
+    #[serde(default = "default_true")]
+    pub empty_page: bool,
+    /// Stop on a page with fewer items than this (i.e. the last page).
+    #[serde(default)]
+    pub min_items: Option<usize>,
+    /// Stop after this many pages.
+    #[serde(default)]
+    pub max_pages: Option<usize>,
+}
+
+impl Default for StopConditions {
+    fn default() -> Self {
+        Self {
+            empty_page: true,
+            min_items: None,
+            max_pages: None,
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// A crawl rule.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CrawlRule {
+    pub name: String,
+    /// Domain patterns this rule applies to. Either a domain (`cba.media`), a domain with
+    /// all subdomains (`*.cba.media`) or `*` for all domains.
+    pub domains: Vec<String>,
+    /// Query parameter that selects the page.
+    #[serde(default)]
+    pub param: Option<String>,
+    /// Value of the query parameter for the first page, if the feed URL does not contain it.
+    #[serde(default)]
+    pub start: usize,
+    #[serde(default)]
+    pub step: Step,
+    /// Follow `<atom:link rel="next">` links. If the page has no such link, the
+    /// query parameter is used (if set).
+    #[serde(default)]
+    pub follow_next: bool,
+    #[serde(default)]
+    pub stop: StopConditions,
+}
+
+impl CrawlRule {
+    /// Check if the rule applies to a domain.
+    pub fn matches(&self, domain: &str) -> bool {
+        util::domain_matches(&self.domains, domain)
+    }
+
+    /// Get the URL of the page after `url`, which contained `len` items.
+    fn next_url(&self, url: &Url, len: usize) -> Option<Url> {
+        let param = self.param.as_ref()?;
+        if self.step == Step::Items && len == 0 {
+            return None;
+        }
+        let mut url = url.clone();
+        let mut params = util::query_map(&url);
+        let current = params
+            .get(param)
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(self.start);
+        let next = match self.step {
+            Step::Items => current + len,
+            Step::Page => current + 1,
+            Step::Fixed(step) => current + step,
+        };
+        params.insert(param.to_string(), next.to_string());
+        util::set_query_map(&mut url, &params);
+        Some(url)
+    }
+}
+
+#[async_trait::async_trait]
+impl Crawler for CrawlRule {
+    async fn next(&self, feed_page: FetchedFeedPage) -> anyhow::Result<Next> {
+        let len = feed_page.feed.item_count();
+        let stop = &self.stop;
+        if (stop.empty_page && len == 0) || stop.min_items.map_or(false, |min| len < min) {
+            return Ok(Next::Finished);
+        }
+        let next = match feed_page.feed.channel_link("next") {
+            Some(next) if self.follow_next => Some(feed_page.url.join(&next)?),
+            _ => self.next_url(&feed_page.url, len),
+        };
+        match next {
+            // A page that links to itself would be fetched again and again.
+            Some(url) if url != feed_page.url => Ok(Next::NextPage(url)),
+            _ => Ok(Next::Finished),
+        }
+    }
+
+    fn domains(&self) -> Vec<String> {
+        self.domains.clone()
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct CrawlRulesFile {
+    #[serde(default)]
+    rule: Vec<CrawlRule>,
+}
+
+/// The list of crawl rules.
+#[derive(Debug, Default, Clone)]
+pub struct CrawlRules {
+    rules: Vec<CrawlRule>,
+}
+
+impl CrawlRules {
+    pub fn from_toml(contents: &str) -> anyhow::Result<Self> {
+        let file: CrawlRulesFile = toml::from_str(contents)?;
+        Ok(Self { rules: file.rule })
+    }
+
+    /// Load the crawl rules from a file, from the default config path or the
+    /// defaults included at compile time.
+    pub async fn load(path: Option<PathBuf>) -> anyhow::Result<Self> {
+        let path = match path {
+            Some(path) => Some(path),
+            None => config_file_path(CRAWL_RULES_FILE).await,
+        };
+        match path {
+            Some(path) => {
+                let contents = read_to_string(&path)
+                    .await
+                    .with_context(|| format!("File not found: {}", path.display()))?;
+                Self::from_toml(&contents)
+            }
+            None => Self::from_toml(DEFAULT_CRAWL_RULES),
+        }
+    }
+
+    /// Find the first rule that applies to the domain of a URL.
+    pub fn find(&self, url: &Url) -> Option<&CrawlRule> {
+        let domain = url.domain()?;
+        self.rules.iter().find(|rule| rule.matches(domain))
+    }
+
+    pub fn rules(&self) -> &[CrawlRule] {
+        &self.rules
+    }
 }
 
 pub mod util {
     use std::collections::HashMap;
     use url::Url;
+
+    /// Check if a domain matches any of a list of patterns. A pattern is either a domain
+    /// (`cba.media`), a domain with all subdomains (`*.cba.media`) or `*` for all domains.
+    pub fn domain_matches(patterns: &[String], domain: &str) -> bool {
+        patterns.iter().any(|pattern| {
+            if pattern == "*" {
+                true
+            } else if let Some(suffix) = pattern.strip_prefix("*.") {
+                domain == suffix || domain.ends_with(&format!(".{}", suffix))
+            } else {
+                pattern == domain
+            }
+        })
+    }
+
     pub fn query_map(url: &Url) -> HashMap<String, String> {
         url.query_pairs().into_owned().collect()
     }
@@ -28,61 +226,67 @@ pub mod util {
     }
 }
 
-pub mod frn {
-    use super::util::{insert_or_add, query_map, set_query_map};
-    use crate::rss::{Crawler, FetchedFeedPage, Next};
-
-    pub struct FrnCrawler {}
-
-    #[async_trait::async_trait]
-    impl Crawler for FrnCrawler {
-        async fn next(&self, feed_page: FetchedFeedPage) -> anyhow::Result<Next> {
-            match feed_page.items.len() {
-                0 => Ok(Next::Finished),
-                _ => {
-                    let len = feed_page.items.len();
-                    let mut url = feed_page.url;
-                    let mut params = query_map(&url);
-                    insert_or_add(&mut params, "start", len, len);
-                    set_query_map(&mut url, &params);
-                    Ok(Next::NextPage(url))
-                }
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        fn domains(&self) -> Vec<String> {
-            vec![
-                "freie-radios.net".to_string(),
-                "www.freie-radios.net".to_string(),
-            ]
-        }
+    #[test]
+    fn default_rules() {
+        let rules = CrawlRules::from_toml(DEFAULT_CRAWL_RULES).unwrap();
+        let url: Url = "https://cba.media/podcast/foo/feed".parse().unwrap();
+        let rule = rules.find(&url).unwrap();
+        assert_eq!(rule.param.as_deref(), Some("offset"));
+        let next = rule.next_url(&url, 10).unwrap();
+        assert_eq!(
+            next.as_str(),
+            "https://cba.media/podcast/foo/feed?offset=10"
+        );
+        let next = rule.next_url(&next, 10).unwrap();
+        assert_eq!(
+            next.as_str(),
+            "https://cba.media/podcast/foo/feed?offset=20"
+        );
+
+        let url: Url = "https://www.freie-radios.net/portal/podcast.php?rss"
+            .parse()
+            .unwrap();
+        assert_eq!(rules.find(&url).unwrap().param.as_deref(), Some("start"));
     }
-}
 
-pub mod cba {
-    use super::util::{insert_or_add, query_map, set_query_map};
-    use crate::rss::{Crawler, FetchedFeedPage, Next};
-
-    pub struct CbaCrawler {}
-
-    #[async_trait::async_trait]
-    impl Crawler for CbaCrawler {
-        async fn next(&self, feed_page: FetchedFeedPage) -> anyhow::Result<Next> {
-            let len = feed_page.items.len();
-            match len {
-                0 => Ok(Next::Finished),
-                _ => {
-                    let mut url = feed_page.url;
-                    let mut params = query_map(&url);
-                    insert_or_add(&mut params, "offset", len, len);
-                    set_query_map(&mut url, &params);
-                    Ok(Next::NextPage(url))
-                }
-            }
-        }
+    #[test]
+    fn page_step() {
+        let rule = CrawlRule {
+            name: "test".into(),
+            domains: vec!["*.example.org".into()],
+            param: Some("page".into()),
+            start: 1,
+            step: Step::Page,
+            follow_next: false,
+            stop: Default::default(),
+        };
+        assert!(rule.matches("feeds.example.org"));
+        assert!(!rule.matches("example.com"));
+        let url: Url = "https://feeds.example.org/rss".parse().unwrap();
+        let next = rule.next_url(&url, 50).unwrap();
+        assert_eq!(next.as_str(), "https://feeds.example.org/rss?page=2");
+    }
 
-        fn domains(&self) -> Vec<String> {
-            vec!["cba.media".to_string(), "cba.fro.at".to_string()]
-        }
+    #[test]
+    fn items_step_stops_on_empty_page() {
+        let rule = CrawlRule {
+            name: "test".into(),
+            domains: vec!["*".into()],
+            param: Some("offset".into()),
+            start: 0,
+            step: Step::Items,
+            follow_next: false,
+            stop: StopConditions {
+                empty_page: false,
+                ..Default::default()
+            },
+        };
+        let url: Url = "https://example.org/rss?offset=20".parse().unwrap();
+        assert!(rule.next_url(&url, 0).is_none());
+        assert!(rule.next_url(&url, 10).is_some());
     }
 }
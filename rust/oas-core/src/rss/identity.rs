@@ -0,0 +1,218 @@
+//! Stable identities for feed items.
+//!
+//! Post IDs are derived from values of the feed item as configured by the feed's
+//! [ItemIdentity] setting. This module also contains the migration of existing posts
+//! of a feed from one identity strategy to another.
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use oas_common::types::{Feed, ItemIdentity, ItemKeys, Media, Post};
+use oas_common::{Record, Reference, Resolver};
+use std::collections::HashMap;
+
+use crate::couch::CouchDB;
+
+/// Read the values of a feed item that may be used to identify it.
+pub fn item_keys(item: &rss::Item) -> ItemKeys {
+    ItemKeys {
+        guid: item.guid().map(|guid| guid.value().to_string()),
+        enclosure: item
+            .enclosure()
+            .map(|enclosure| enclosure.url().to_string()),
+        link: item.link().map(|link| link.to_string()),
+        title: item.title().map(|title| title.to_string()),
+        date: item
+            .pub_date()
+            .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+            .map(|date| date.with_timezone(&Utc)),
+    }
+}
+
+#[derive(Parser, Debug)]
+pub struct MigrateIdsOpts {
+    /// Feed ID or URL
+    pub id_or_url: String,
+    /// Use feed-scoped IDs (hash the feed URL together with the item key)
+    #[clap(long)]
+    pub scoped: bool,
+    /// Only print what would be changed
+    #[clap(long)]
+    pub dry_run: bool,
+}
+
+/// Result of an ID migration.
+#[derive(Debug, Default)]
+pub struct MigrateIdsReport {
+    /// Map of old post guid to new post guid.
+    pub moved: HashMap<String, String>,
+    /// Posts for which no new ID could be derived, e.g. because their item keys were not
+    /// stored yet. The keys are stored when the feed is fetched again.
+    pub skipped: Vec<String>,
+    /// Posts that already have the correct ID.
+    pub unchanged: usize,
+    /// Map of old post guid to the error that prevented its migration. These posts are kept
+    /// at their old IDs.
+    pub failed: HashMap<String, String>,
+}
+
+/// Migrate the posts of a feed to a new identity strategy.
+///
+/// The posts are copied to their new IDs (keeping all metadata, job settings and results),
+/// references from medias are updated and the old posts are deleted. Finally the new
+/// strategy is stored in the feed settings, so that further fetches use the new IDs.
+/// The search index should be recreated afterwards to drop the old posts.
+///
+/// The new IDs are derived from the item keys that were stored on the posts when they were
+/// mapped from the feed. Posts whose new ID collides with the new ID of another post are
+/// not moved. Old posts are only deleted once their new post and its medias are written.
+/// If anything fails, the failures are reported and the feed keeps its old strategy, so
+/// that the migration can be run again.
+pub async fn migrate_ids(
+    db: &CouchDB,
+    mut feed: Record<Feed>,
+    identity: ItemIdentity,
+    dry_run: bool,
+) -> anyhow::Result<MigrateIdsReport> {
+    let mut report = MigrateIdsReport::default();
+    let feed_guid = feed.guid().to_string();
+    let mut posts: Vec<Record<Post>> = db
+        .get_all_records::<Post>()
+        .await?
+        .into_iter()
+        .filter(|post| post.value.feeds.iter().any(|r| r.guid() == feed_guid))
+        .collect();
+    db.resolve_all_refs(&mut posts)
+        .await
+        .context("Failed to resolve medias")?;
+
+    let mut new_posts = vec![];
+    for post in posts.into_iter() {
+        let id = post
+            .meta()
+            .item_keys()
+            .and_then(|keys| keys.id(&identity, feed.value.identity_scope()));
+        match id {
+            None => report.skipped.push(post.guid().to_string()),
+            Some(id) if id == post.id() => report.unchanged += 1,
+            Some(id) => {
+                let old_guid = post.guid().to_string();
+                let post = post.with_id(id);
+                report.moved.insert(old_guid, post.guid().to_string());
+                new_posts.push(post);
+            }
+        }
+    }
+
+    // Posts that would be moved to the same ID are kept at their old IDs.
+    let mut targets: HashMap<String, Vec<String>> = HashMap::new();
+    for (old_guid, new_guid) in report.moved.iter() {
+        targets
+            .entry(new_guid.clone())
+            .or_default()
+            .push(old_guid.clone());
+    }
+    targets.retain(|_, old_guids| old_guids.len() > 1);
+    for (new_guid, old_guids) in targets.iter() {
+        for old_guid in old_guids.iter() {
+            report.moved.remove(old_guid);
+            report.failed.insert(
+                old_guid.clone(),
+                format!(
+                    "Posts {} would be moved to the same post {}",
+                    old_guids.join(", "),
+                    new_guid
+                ),
+            );
+        }
+    }
+    new_posts.retain(|post| !targets.contains_key(post.guid()));
+
+    if dry_run {
+        return Ok(report);
+    }
+
+    // Update the post references on all medias of the moved posts.
+    let mut medias: HashMap<String, Record<Media>> = HashMap::new();
+    for post in new_posts.iter_mut() {
+        for media in post.value.media.iter_mut() {
+            if let Some(record) = media.extract_record() {
+                medias.insert(record.guid().to_string(), record);
+            }
+        }
+    }
+    for media in medias.values_mut() {
+        for post_ref in media.value.posts.iter_mut() {
+            if let Some(new_guid) = report.moved.get(post_ref.guid()) {
+                *post_ref = Reference::Id(new_guid.clone());
+            }
+        }
+    }
+
+    // Map of post guid to the old guids of the moved posts.
+    let old_guids: HashMap<String, String> = report
+        .moved
+        .iter()
+        .map(|(old_guid, new_guid)| (new_guid.clone(), old_guid.clone()))
+        .collect();
+    // Map of media guid to the new guids of the posts that reference it.
+    let mut media_posts: HashMap<String, Vec<String>> = HashMap::new();
+    for media in medias.values() {
+        for post_ref in media.value.posts.iter() {
+            if old_guids.contains_key(post_ref.guid()) {
+                media_posts
+                    .entry(media.guid().to_string())
+                    .or_default()
+                    .push(post_ref.guid().to_string());
+            }
+        }
+    }
+
+    let post_guids: Vec<String> = new_posts
+        .iter()
+        .map(|post| post.guid().to_string())
+        .collect();
+    // New posts may have been written by an earlier run whose media updates failed.
+    let results = db.put_record_bulk_update(new_posts).await?;
+    for (guid, res) in post_guids.iter().zip(results.iter()) {
+        if let Some(err) = res.as_err() {
+            report
+                .failed
+                .insert(old_guids[guid].clone(), err.to_string());
+        }
+    }
+
+    let media_guids: Vec<String> = medias.keys().cloned().collect();
+    let results = db
+        .put_record_bulk_update(medias.into_iter().map(|(_, media)| media).collect())
+        .await?;
+    for (guid, res) in media_guids.iter().zip(results.iter()) {
+        if let Some(err) = res.as_err() {
+            // The old posts are still referenced by the media, so keep them.
+            for post_guid in media_posts.get(guid).into_iter().flatten() {
+                let old_guid = old_guids[post_guid].clone();
+                report
+                    .failed
+                    .entry(old_guid)
+                    .or_insert_with(|| format!("Failed to update media {}: {}", guid, err));
+            }
+        }
+    }
+
+    for old_guid in report.failed.keys() {
+        report.moved.remove(old_guid);
+    }
+    for old_guid in report.moved.keys() {
+        db.delete_record(old_guid).await?;
+    }
+    if !report.failed.is_empty() {
+        return Ok(report);
+    }
+
+    let mut settings = feed.value.settings.take().unwrap_or_default();
+    settings.identity = identity;
+    feed.value.settings = Some(settings);
+    db.put_record(feed).await?;
+
+    Ok(report)
+}
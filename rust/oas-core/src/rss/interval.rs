@@ -0,0 +1,112 @@
+//! Adaptive check intervals.
+//!
+//! Derives how often a feed should be fetched from the gaps between the publication dates
+//! of its items.
+
+use chrono::{DateTime, Utc};
+use oas_common::types::{AdaptiveInterval, MIN_CHECK_INTERVAL};
+use std::time::Duration;
+
+/// Number of most recent gaps that are taken into account.
+const MAX_GAPS: usize = 10;
+
+/// The feed is fetched this many times per typical gap between two posts.
+const FETCHES_PER_GAP: u32 = 4;
+
+/// Compute the check interval for a feed from the publication dates of its items.
+///
+/// The interval is a fraction of the median gap between the most recent posts. If the latest
+/// post is much older than the median gap, the feed is considered dormant and the time since
+/// the latest post is used instead. Returns `None` if there are not enough dated posts.
+///
+/// Bounds that were stored without validation are normalised: The minimum is raised to
+/// [MIN_CHECK_INTERVAL] and the maximum to the minimum.
+pub fn adaptive_interval(
+    dates: &[DateTime<Utc>],
+    now: DateTime<Utc>,
+    bounds: &AdaptiveInterval,
+) -> Option<Duration> {
+    let mut dates = dates.to_vec();
+    dates.sort_unstable_by(|a, b| b.cmp(a));
+    dates.dedup();
+
+    let mut gaps: Vec<i64> = dates
+        .windows(2)
+        .take(MAX_GAPS)
+        .map(|pair| (pair[0] - pair[1]).num_seconds())
+        .collect();
+    if gaps.is_empty() {
+        return None;
+    }
+    gaps.sort_unstable();
+    let median = gaps[gaps.len() / 2];
+    let since_latest = (now - dates[0]).num_seconds();
+    let gap = median.max(since_latest / FETCHES_PER_GAP as i64).max(0) as u64;
+
+    let min = bounds.min_interval.max(MIN_CHECK_INTERVAL);
+    let max = bounds.max_interval.max(min);
+    let secs = (gap / FETCHES_PER_GAP as u64).clamp(min, max);
+    Some(Duration::from_secs(secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bounds() -> AdaptiveInterval {
+        AdaptiveInterval {
+            min_interval: 600,
+            max_interval: 7 * 86400,
+        }
+    }
+
+    fn days_ago(now: DateTime<Utc>, days: &[i64]) -> Vec<DateTime<Utc>> {
+        days.iter()
+            .map(|d| now - chrono::Duration::days(*d))
+            .collect()
+    }
+
+    #[test]
+    fn daily_show() {
+        let now: DateTime<Utc> = "2021-06-01T12:00:00Z".parse().unwrap();
+        let dates = days_ago(now, &[0, 1, 2, 3, 4, 5]);
+        let interval = adaptive_interval(&dates, now, &bounds()).unwrap();
+        assert_eq!(interval, Duration::from_secs(6 * 3600));
+    }
+
+    #[test]
+    fn dormant_archive() {
+        let now: DateTime<Utc> = "2021-06-01T12:00:00Z".parse().unwrap();
+        let dates = days_ago(now, &[400, 401, 402]);
+        let interval = adaptive_interval(&dates, now, &bounds()).unwrap();
+        assert_eq!(interval, Duration::from_secs(7 * 86400));
+    }
+
+    #[test]
+    fn inverted_bounds() {
+        let now: DateTime<Utc> = "2021-06-01T12:00:00Z".parse().unwrap();
+        let dates = days_ago(now, &[0, 1, 2, 3, 4, 5]);
+        let bounds = AdaptiveInterval {
+            min_interval: 86400,
+            max_interval: 0,
+        };
+        assert!(bounds.validate().is_err());
+        let interval = adaptive_interval(&dates, now, &bounds).unwrap();
+        assert_eq!(interval, Duration::from_secs(86400));
+
+        let bounds = AdaptiveInterval {
+            min_interval: 0,
+            max_interval: 0,
+        };
+        assert!(bounds.validate().is_err());
+        let interval = adaptive_interval(&dates, now, &bounds).unwrap();
+        assert_eq!(interval, Duration::from_secs(MIN_CHECK_INTERVAL));
+    }
+
+    #[test]
+    fn not_enough_dates() {
+        let now: DateTime<Utc> = "2021-06-01T12:00:00Z".parse().unwrap();
+        let dates = days_ago(now, &[1]);
+        assert!(adaptive_interval(&dates, now, &bounds()).is_none());
+    }
+}
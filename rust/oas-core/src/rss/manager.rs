@@ -2,47 +2,196 @@ use clap::Parser;
 use oas_common::types;
 use oas_common::types::Feed;
 use oas_common::util::id_from_hashed_string;
-use oas_common::TypedRecord;
+use oas_common::{TypedRecord, TypedValue};
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
-use tokio::task::JoinHandle;
 use tokio_stream::StreamExt;
 
+use super::crawlers::CrawlRules;
 use super::error::RssError;
-use super::mapping::{AllMappings, MappingManager};
+use super::mapping::{MappingManager, MappingProfiles};
+use super::moves::{self, MoveReport};
+use super::oai::{self, HarvestOpts, HarvestReport};
+use super::ops::{self, CrawlOpts, FetchOpts};
+use super::preview::FeedPreview;
+use super::scheduler::{self, Scheduler, SchedulerOpts, WatcherInfo};
+use super::websub::{PushCheck, WebSub};
 use super::FeedWatcher;
 use crate::couch::CouchDB;
-
-type Task<T> = JoinHandle<Result<T, RssError>>;
-// type Store = HashMap<String, TypedRecord<types::Feed>>;
+use crate::types::FeedSettings;
 
 #[derive(Debug, Clone, Default, Parser)]
 pub struct FeedManagerOpts {
     #[clap(long)]
     pub mapping_file: Option<String>,
+
+    /// Path to crawl rules file
+    #[clap(long)]
+    pub crawl_rules_file: Option<String>,
+
+    /// Max number of feeds to fetch concurrently
+    #[clap(long)]
+    pub feed_concurrency: Option<usize>,
+
+    /// Min delay between requests to the same host (in seconds)
+    #[clap(long)]
+    pub feed_host_delay: Option<u64>,
+
+    /// Timeout for feed requests (in seconds)
+    #[clap(long)]
+    pub feed_timeout: Option<u64>,
+
+    /// User agent for feed requests
+    #[clap(long)]
+    pub feed_user_agent: Option<String>,
+
+    /// Public URL of the API for WebSub callbacks (WebSub is disabled if not set)
+    #[clap(long)]
+    pub websub_callback_url: Option<String>,
 }
 
 impl FeedManagerOpts {
     pub fn with_mapping_file(f: String) -> Self {
         Self {
             mapping_file: Some(f),
+            ..Default::default()
+        }
+    }
+
+    fn scheduler_opts(&self) -> SchedulerOpts {
+        SchedulerOpts {
+            concurrency: self
+                .feed_concurrency
+                .unwrap_or(scheduler::DEFAULT_CONCURRENCY),
+            host_delay: Duration::from_secs(
+                self.feed_host_delay
+                    .unwrap_or(scheduler::DEFAULT_HOST_DELAY),
+            ),
         }
     }
+
+    fn client_builder(&self) -> reqwest::ClientBuilder {
+        let user_agent = self
+            .feed_user_agent
+            .as_deref()
+            .unwrap_or(scheduler::DEFAULT_USER_AGENT);
+        let timeout = self.feed_timeout.unwrap_or(scheduler::DEFAULT_TIMEOUT);
+        reqwest::Client::builder()
+            .user_agent(user_agent)
+            .timeout(Duration::from_secs(timeout))
+    }
+
+    /// Build the HTTP client used for requests other than loading watched feeds (e.g. podcast
+    /// transcripts, OAI-PMH and WebSub hubs). It follows all redirects.
+    fn http_client(&self) -> reqwest::Client {
+        self.client_builder()
+            .build()
+            .expect("Failed to build HTTP client")
+    }
+
+    /// Build the HTTP client used to load watched feeds. It does not follow permanent
+    /// redirects, so that moves are noticed by [FeedWatcher::load].
+    fn feed_client(&self) -> reqwest::Client {
+        self.client_builder()
+            .redirect(moves::redirect_policy())
+            .build()
+            .expect("Failed to build HTTP client")
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct FeedManager {
     pub(crate) inner: Arc<Mutex<FeedManagerInner>>,
+    scheduler: Scheduler,
+    client: reqwest::Client,
+    feed_client: reqwest::Client,
+    websub: Option<WebSub>,
 }
 
 impl FeedManager {
     pub fn new(opts: FeedManagerOpts) -> Self {
+        let scheduler = Scheduler::new(opts.scheduler_opts());
+        let client = opts.http_client();
+        let feed_client = opts.feed_client();
+        let websub = opts
+            .websub_callback_url
+            .as_deref()
+            .map(|url| WebSub::new(client.clone(), url));
         Self {
             inner: Arc::new(Mutex::new(FeedManagerInner::new(opts))),
+            scheduler,
+            client,
+            feed_client,
+            websub,
         }
     }
 
+    /// The HTTP client that should be used for requests other than loading watched feeds.
+    pub fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    /// The WebSub subscriber, if WebSub is enabled.
+    pub fn websub(&self) -> Option<&WebSub> {
+        self.websub.as_ref()
+    }
+
+    /// Save the content that a WebSub hub pushed for a feed.
+    ///
+    /// The content is only saved if its signature is valid.
+    pub async fn ingest_push(
+        &self,
+        db: &CouchDB,
+        feed_id: &str,
+        signature: Option<&str>,
+        body: &[u8],
+    ) -> anyhow::Result<PushCheck> {
+        let websub = match &self.websub {
+            Some(websub) => websub,
+            None => return Ok(PushCheck::Unknown),
+        };
+        let check = websub.check_signature(feed_id, signature, body).await;
+        if check != PushCheck::Valid {
+            return Ok(check);
+        }
+        let (feed, profiles) = {
+            let inner = self.inner.lock().await;
+            (
+                inner.store.get(feed_id).cloned(),
+                inner.mapping_manager.profiles().clone(),
+            )
+        };
+        let feed = match feed {
+            Some(feed) => feed,
+            None => return Ok(PushCheck::Unknown),
+        };
+        log::debug!("Received WebSub content for feed {}", feed.value.url);
+        let mapping = profiles.select(&feed.value.url, feed.value.settings.as_ref());
+        let mut watcher = FeedWatcher::with_client(
+            self.client.clone(),
+            &feed.value.url.clone(),
+            feed.value.settings.clone(),
+            mapping,
+            Some(feed),
+        )?;
+        watcher.load_from_bytes(body)?;
+        watcher.save(db, false).await?;
+        Ok(check)
+    }
+
+    /// Get the state of the watcher for a feed.
+    pub async fn watcher_info(&self, id: &str) -> Option<WatcherInfo> {
+        self.scheduler.info(id).await
+    }
+
+    /// Get the state of all feed watchers.
+    pub async fn watcher_infos(&self) -> Vec<WatcherInfo> {
+        self.scheduler.infos().await
+    }
+
     /// Init initial feed state.
     pub async fn init(&self, db: &CouchDB) -> anyhow::Result<()> {
         self.inner.lock().await.init(db).await
@@ -50,7 +199,91 @@ impl FeedManager {
 
     /// Refetch a single feed by ID or URL.
     pub async fn refetch(&self, db: &CouchDB, id_or_url: &str) -> anyhow::Result<()> {
-        self.inner.lock().await.refetch(db, id_or_url).await
+        self.inner
+            .lock()
+            .await
+            .refetch(db, &self.feed_client, &self.client, id_or_url)
+            .await
+    }
+
+    /// Fetch a feed by URL and save its items.
+    pub async fn fetch(&self, db: &CouchDB, opts: &FetchOpts) -> anyhow::Result<()> {
+        let profiles = {
+            let mut inner = self.inner.lock().await;
+            inner.init(db).await?;
+            inner.mapping_manager.profiles().clone()
+        };
+        ops::fetch_and_save(&self.client, &profiles, db, opts).await?;
+        Ok(())
+    }
+
+    /// Fetch a feed by URL and map its items with the selected mapping profile, without
+    /// saving anything.
+    ///
+    /// If the feed exists in the database, its settings are used unless `settings` is set.
+    pub async fn preview(
+        &self,
+        db: &CouchDB,
+        url: &str,
+        settings: Option<FeedSettings>,
+    ) -> anyhow::Result<FeedPreview> {
+        let profiles = {
+            let mut inner = self.inner.lock().await;
+            inner.init(db).await?;
+            inner.mapping_manager.profiles().clone()
+        };
+        let feed = get_feed(db, url).await.ok();
+        let settings = settings.or_else(|| feed.as_ref().and_then(|f| f.value.settings.clone()));
+        let name = profiles
+            .select_name(url, settings.as_ref())
+            .map(|name| name.to_string());
+        let mapping = profiles.select(url, settings.as_ref());
+        let mut watcher =
+            FeedWatcher::with_client(self.client.clone(), url, settings, mapping, feed)?;
+        watcher.load().await?;
+        let preview = watcher.preview(name)?;
+        Ok(preview)
+    }
+
+    /// Harvest an OAI-PMH source (by ID or URL) with its mapping profile.
+    pub async fn harvest(
+        &self,
+        db: &CouchDB,
+        id_or_url: &str,
+        opts: &HarvestOpts,
+    ) -> anyhow::Result<HarvestReport> {
+        let profiles = {
+            let mut inner = self.inner.lock().await;
+            inner.init(db).await?;
+            inner.mapping_manager.profiles().clone()
+        };
+        let source = oai::get_source(db, id_or_url).await?;
+        let mapping = profiles.select_oai(&source.value);
+        let report = oai::harvest(&self.client, &mapping, db, &source, opts).await?;
+        log::info!(
+            "Harvested {} records from {} ({} created, {} updated, {} unchanged, {} deleted)",
+            report.records,
+            source.value.url,
+            report.created,
+            report.updated,
+            report.unchanged,
+            report.deleted
+        );
+        Ok(report)
+    }
+
+    /// Crawl a paginated feed with the matching crawl rule.
+    pub async fn crawl(&self, db: &CouchDB, opts: &CrawlOpts) -> anyhow::Result<()> {
+        let (rules, profiles) = {
+            let mut inner = self.inner.lock().await;
+            inner.init(db).await?;
+            (
+                inner.crawl_rules.clone(),
+                inner.mapping_manager.profiles().clone(),
+            )
+        };
+        ops::crawl_and_save(&self.client, &rules, &profiles, db, opts).await?;
+        Ok(())
     }
 
     /// Opens a feed manager and start to track and watch all feeds in the database,
@@ -71,6 +304,7 @@ impl FeedManager {
 pub struct FeedManagerInner {
     store: HashMap<String, TypedRecord<types::Feed>>,
     mapping_manager: MappingManager,
+    crawl_rules: CrawlRules,
     opts: FeedManagerOpts,
     init: bool,
 }
@@ -86,6 +320,7 @@ impl FeedManagerInner {
             store: HashMap::new(),
             opts,
             mapping_manager,
+            crawl_rules: CrawlRules::default(),
             init: false,
         }
     }
@@ -94,7 +329,9 @@ impl FeedManagerInner {
         if self.init {
             return Ok(());
         }
-        self.mapping_manager.init().await?;
+        self.mapping_manager.init(db).await?;
+        let crawl_rules_file = self.opts.crawl_rules_file.as_ref().map(PathBuf::from);
+        self.crawl_rules = CrawlRules::load(crawl_rules_file).await?;
         let records = db.get_all_records::<types::Feed>().await?;
         for record in records {
             self.store.insert(record.id().into(), record);
@@ -103,105 +340,179 @@ impl FeedManagerInner {
         Ok(())
     }
 
-    pub async fn refetch(&mut self, db: &CouchDB, id_or_url: &str) -> anyhow::Result<()> {
+    pub async fn refetch(
+        &mut self,
+        db: &CouchDB,
+        feed_client: &reqwest::Client,
+        client: &reqwest::Client,
+        id_or_url: &str,
+    ) -> anyhow::Result<()> {
         self.init(db).await?;
-        let table = db.table::<Feed>();
-        let feed = match table.get(id_or_url).await {
-            Ok(feed) => feed,
-            Err(_) => match table.get(&id_from_hashed_string(&id_or_url)).await {
-                Ok(feed) => feed,
-                Err(err) => return Err(err.into()),
-            },
-        };
+        let feed = get_feed(db, id_or_url).await?;
 
         log::info!("Refetch feed {} ({})", feed.id(), feed.value.url);
 
-        let mut watcher = FeedWatcher::new(
+        let mapping = self
+            .mapping_manager
+            .profiles()
+            .select(&feed.value.url, feed.value.settings.as_ref());
+        let mut watcher = FeedWatcher::with_client(
+            feed_client.clone(),
             &feed.value.url.clone(),
             feed.value.settings.clone(),
-            self.mapping_manager.to_field_hashmap(),
+            mapping,
             Some(feed),
-        )?;
+        )?
+        .with_resource_client(client.clone());
 
         watcher.load().await?;
         watcher.save(db, true).await?;
+        watcher.apply_move(db).await?;
         Ok(())
     }
 }
 
+/// Get a feed record by ID or URL. Feeds that moved are also found by their old URLs.
+pub async fn get_feed(db: &CouchDB, id_or_url: &str) -> anyhow::Result<TypedRecord<Feed>> {
+    let table = db.table::<Feed>();
+    let feed = match table.get(id_or_url).await {
+        Ok(feed) => feed,
+        Err(_) => match table.get(&id_from_hashed_string(&id_or_url)).await {
+            Ok(feed) => feed,
+            Err(err) => {
+                let feeds = db.get_all_records::<Feed>().await?;
+                match feeds.into_iter().find(|feed| feed.value.has_url(id_or_url)) {
+                    Some(feed) => feed,
+                    None => return Err(err.into()),
+                }
+            }
+        },
+    };
+    Ok(feed)
+}
+
+/// Move a feed (by ID or URL) to a new URL, or to the URL the feed announced.
+pub async fn move_feed(
+    db: &CouchDB,
+    id_or_url: &str,
+    url: Option<&str>,
+) -> anyhow::Result<MoveReport> {
+    let feed = get_feed(db, id_or_url).await?;
+    let url = match url.or_else(|| feed.value.moved_to.as_deref()) {
+        Some(url) => url.to_string(),
+        None => anyhow::bail!("Feed {} did not announce a new URL", feed.id()),
+    };
+    moves::move_feed(db, feed, &url).await
+}
+
 async fn run_watch(manager: FeedManager, db: CouchDB) -> anyhow::Result<()> {
-    let tasks = start_feed_tasks(&manager, db.clone()).await?;
-    let mapping = {
-        manager
-            .inner
-            .lock()
-            .await
-            .mapping_manager
-            .to_field_hashmap()
+    let (feeds, profiles, crawl_rules) = {
+        let inner = manager.inner.lock().await;
+        let feeds: Vec<_> = inner.store.values().cloned().collect();
+        (
+            feeds,
+            inner.mapping_manager.profiles().clone(),
+            inner.crawl_rules.clone(),
+        )
     };
-    let watch_task = tokio::spawn({
+    // Start to listen for changes before starting the watchers to not miss any.
+    let last_seq = db.get_last_seq().await?;
+    for feed in feeds.into_iter() {
+        start_watcher(&manager, &profiles, feed).await?;
+    }
+    let scheduler_task = tokio::spawn({
+        let scheduler = manager.scheduler.clone();
         let db = db.clone();
-        async move { watch_changes(mapping, db).await }
+        let websub = manager.websub.clone();
+        async move { scheduler.run(db, crawl_rules, websub).await }
     });
-    for handle in tasks.into_iter() {
-        handle.await??;
+    if let Some(websub) = manager.websub.clone() {
+        tokio::spawn(websub.run_renewal());
     }
-    watch_task.await??;
+    watch_changes(&manager, &db, last_seq).await?;
+    scheduler_task.await?;
     Ok(())
 }
 
-async fn start_feed_tasks(manager: &FeedManager, db: CouchDB) -> Result<Vec<Task<()>>, RssError> {
-    let mut tasks = Vec::new();
-    let manager = manager.inner.lock().await;
-    let store = &manager.store;
-    let mapping = manager.mapping_manager.to_field_hashmap();
-    for (id, feed) in store.iter() {
-        let settings = feed.value.settings.clone();
-        log::debug!(
-            "Start to watch feed {} [{}] for updates",
-            id,
-            feed.value.url
-        );
-        let mut watcher = FeedWatcher::new(
-            &feed.value.url,
-            settings,
-            mapping.clone(),
-            Some(feed.clone()),
-        )?;
-        let db = db.clone();
-        tasks.push(tokio::spawn(async move { watcher.watch(db).await }));
-    }
-    Ok(tasks)
+async fn start_watcher(
+    manager: &FeedManager,
+    profiles: &MappingProfiles,
+    feed: TypedRecord<types::Feed>,
+) -> Result<(), RssError> {
+    let mapping = profiles.select(&feed.value.url, feed.value.settings.as_ref());
+    let watcher = FeedWatcher::with_client(
+        manager.feed_client.clone(),
+        &feed.value.url,
+        feed.value.settings.clone(),
+        mapping,
+        Some(feed.clone()),
+    )?
+    .with_resource_client(manager.client.clone());
+    manager.scheduler.upsert(feed, watcher).await;
+    Ok(())
 }
 
-async fn watch_changes(mapping: AllMappings, db: CouchDB) -> Result<(), RssError> {
-    let last_seq = db.get_last_seq().await?;
+async fn watch_changes(
+    manager: &FeedManager,
+    db: &CouchDB,
+    last_seq: String,
+) -> Result<(), RssError> {
     let mut stream = db.changes(Some(last_seq));
-    let mut tasks = Vec::new();
     stream.set_infinite(true);
-    let client = reqwest::Client::new();
+    let prefix = format!("{}_", types::Feed::NAME);
+    let mapping_prefix = format!("{}_", types::FeedMapping::NAME);
     while let Some(event) = stream.next().await {
         let event = event?;
-        if let Some(doc) = event.doc {
-            let _id = doc.id().to_string();
-            let record = doc.into_typed_record::<types::Feed>();
-            match record {
-                Err(_err) => {}
-                Ok(record) => {
-                    let url = record.value.url.clone();
-                    let settings = record.value.settings.clone();
-                    let mut watcher = FeedWatcher::with_client(
-                        client.clone(),
-                        &url,
-                        settings,
-                        mapping.clone(),
-                        Some(record),
-                    )?;
-                    let db = db.clone();
-                    tasks.push(tokio::spawn(async move { watcher.watch(db).await }));
+        if event.id.starts_with(&mapping_prefix) {
+            reload_mappings(manager, db).await;
+            continue;
+        }
+        if !event.id.starts_with(&prefix) {
+            continue;
+        }
+        let id = &event.id[prefix.len()..];
+        if event.deleted {
+            manager.inner.lock().await.store.remove(id);
+            manager.scheduler.remove(id).await;
+            if let Some(websub) = &manager.websub {
+                if let Err(err) = websub.unsubscribe(id).await {
+                    log::warn!("Failed to unsubscribe feed {}: {}", id, err);
                 }
             }
+            continue;
+        }
+        let record = match event.doc.map(|doc| doc.into_typed_record::<types::Feed>()) {
+            Some(Ok(record)) => record,
+            _ => continue,
+        };
+        let profiles = {
+            let mut inner = manager.inner.lock().await;
+            inner.store.insert(id.to_string(), record.clone());
+            inner.mapping_manager.profiles().clone()
+        };
+        if let Err(err) = start_watcher(manager, &profiles, record).await {
+            log::warn!("Failed to start watcher for feed {}: {}", id, err);
         }
     }
     Ok(())
 }
+
+/// Reload the mapping profiles and restart the watchers whose mapping changed.
+async fn reload_mappings(manager: &FeedManager, db: &CouchDB) {
+    let (feeds, profiles) = {
+        let mut inner = manager.inner.lock().await;
+        if let Err(err) = inner.mapping_manager.reload(db).await {
+            log::warn!("Failed to reload mappings: {}", err);
+            return;
+        }
+        let feeds: Vec<_> = inner.store.values().cloned().collect();
+        (feeds, inner.mapping_manager.profiles().clone())
+    };
+    log::info!("Reloaded {} mapping profiles", profiles.len());
+    for feed in feeds.into_iter() {
+        let id = feed.id().to_string();
+        if let Err(err) = start_watcher(manager, &profiles, feed).await {
+            log::warn!("Failed to restart watcher for feed {}: {}", id, err);
+        }
+    }
+}
@@ -1,31 +1,324 @@
+use convert_case::{Case, Casing};
 use dirs::config_dir;
-use serde::Deserialize;
+use oas_common::types::{FeedMapping, MappingField, Media, OaiSource, Post};
+use oas_common::{Record, TypedValue, ValidationError};
+use schemars::schema::{InstanceType, RootSchema, Schema, SingleOrVec};
+use schemars::{schema_for, JsonSchema};
+use serde::{Deserialize, Serialize};
 use toml;
 extern crate dirs;
 use anyhow::Context;
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use tokio::fs::{metadata, read_to_string};
+use url::Url;
 
-pub type AllMappings = HashMap<String, String>;
+use super::crawlers::util::domain_matches;
+use crate::couch::{CouchDB, CouchError, ErrorDetails, PutResponse, PutResult};
+use crate::types::FeedSettings;
+
+/// A flat mapping of feed item fields to post fields, as applied by a feed watcher.
+pub type FieldMap = HashMap<String, MappingField>;
 
 const DEFAULT_MAPPING: &str = include_str!("../../../../config/mapping.toml");
+const MAPPING_FILE: &str = "mapping.toml";
+
+/// Name of the profile that is used for feeds without a profile in their settings
+/// and without a profile for their domain.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Name of the profile for Dublin Core records that is used for OAI-PMH sources without
+/// a profile.
+pub const OAI_DC_PROFILE: &str = "oai_dc";
 
+/// Manages the mapping profiles.
+///
+/// The profiles are stored as [FeedMapping] records in CouchDB. Profiles of the mapping file
+/// (or the default mapping) that are missing in the database are seeded on init.
 #[derive(Debug, Default)]
 pub struct MappingManager {
-    mappings: HashMap<String, Mapping>,
+    profiles: MappingProfiles,
     path: Option<PathBuf>,
 }
 
-#[derive(Deserialize, Debug, Default, Clone)]
-pub struct Mapping {
-    fields: Vec<FieldMapping>,
+/// All mapping profiles, keyed by their name.
+#[derive(Debug, Default, Clone)]
+pub struct MappingProfiles {
+    profiles: HashMap<String, FeedMapping>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
-pub struct FieldMapping {
-    pub from: String,
-    pub to: String,
+impl MappingProfiles {
+    pub fn from_toml(contents: &str) -> anyhow::Result<Self> {
+        let profiles: HashMap<String, FeedMapping> = toml::from_str(contents)?;
+        let profiles = Self { profiles };
+        profiles.validate()?;
+        Ok(profiles)
+    }
+
+    pub fn from_records(records: Vec<Record<FeedMapping>>) -> Self {
+        let profiles = records
+            .into_iter()
+            .map(|record| (record.id().to_string(), record.value))
+            .collect();
+        Self { profiles }
+    }
+
+    pub fn into_records(self) -> Vec<Record<FeedMapping>> {
+        self.profiles
+            .into_iter()
+            .map(|(name, mapping)| Record::from_id_and_value(name, mapping))
+            .collect()
+    }
+
+    /// Check that all inherited profiles exist and warn about conflicting fields.
+    fn validate(&self) -> anyhow::Result<()> {
+        for (name, mapping) in self.profiles.iter() {
+            self.resolve(name)?;
+            let mut targets: HashMap<&str, &str> = HashMap::new();
+            for field in mapping.fields.iter() {
+                match targets.insert(&field.from, &field.to) {
+                    Some(to) if to == field.to => {
+                        log::warn!("Mapping {}: duplicate field {}", name, field.from)
+                    }
+                    Some(to) => log::warn!(
+                        "Mapping {}: field {} is mapped to both {} and {}",
+                        name,
+                        field.from,
+                        to,
+                        field.to
+                    ),
+                    None => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&FeedMapping> {
+        self.profiles.get(name)
+    }
+
+    pub fn insert(&mut self, name: String, mapping: FeedMapping) {
+        self.profiles.insert(name, mapping);
+    }
+
+    pub fn len(&self) -> usize {
+        self.profiles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.profiles.is_empty()
+    }
+
+    /// Resolve a profile and the profiles it inherits from into a flat field map.
+    pub fn resolve(&self, name: &str) -> anyhow::Result<FieldMap> {
+        let mut chain = vec![];
+        let mut next = Some(name);
+        while let Some(name) = next {
+            if chain.iter().any(|(n, _)| *n == name) {
+                anyhow::bail!("Mapping {} inherits from itself", name);
+            }
+            let mapping = self
+                .profiles
+                .get(name)
+                .ok_or_else(|| anyhow::anyhow!("Mapping not found: {}", name))?;
+            chain.push((name, mapping));
+            next = mapping.extends.as_deref();
+        }
+        let map = chain
+            .iter()
+            .rev()
+            .flat_map(|(_, mapping)| mapping.fields.iter())
+            .map(|field| (field.from.clone(), field.clone()))
+            .collect();
+        Ok(map)
+    }
+
+    /// Find the profile for a domain.
+    pub fn find_by_domain(&self, domain: &str) -> Option<&str> {
+        let mut names: Vec<&String> = self.profiles.keys().collect();
+        names.sort();
+        names
+            .into_iter()
+            .find(|name| domain_matches(&self.profiles[*name].domains, domain))
+            .map(|name| name.as_str())
+    }
+
+    /// Select the field map for a feed.
+    ///
+    /// Uses the profile from the feed settings, or else the profile for the domain of the
+    /// feed URL, or else the default profile (if defined).
+    pub fn select(&self, url: &str, settings: Option<&FeedSettings>) -> FieldMap {
+        match self.select_name(url, settings) {
+            Some(name) => self.resolve(name).unwrap_or_else(|err| {
+                log::warn!("Failed to apply mapping to feed {}: {}", url, err);
+                FieldMap::default()
+            }),
+            None => FieldMap::default(),
+        }
+    }
+
+    /// Select the field map for an OAI-PMH source.
+    ///
+    /// Uses the profile of the source, or else the Dublin Core profile. If the Dublin Core
+    /// profile is not in the database (e.g. because it was deleted), the profile from the
+    /// default mapping is used.
+    pub fn select_oai(&self, source: &OaiSource) -> FieldMap {
+        let name = source.mapping.as_deref().unwrap_or(OAI_DC_PROFILE);
+        let result = match (self.get(name), name) {
+            (None, OAI_DC_PROFILE) => {
+                MappingProfiles::from_toml(DEFAULT_MAPPING).and_then(|p| p.resolve(name))
+            }
+            _ => self.resolve(name),
+        };
+        result.unwrap_or_else(|err| {
+            log::warn!(
+                "Failed to apply mapping to OAI source {}: {}",
+                source.url,
+                err
+            );
+            FieldMap::default()
+        })
+    }
+
+    /// The name of the profile that [select](Self::select) uses for a feed.
+    pub fn select_name<'a>(
+        &'a self,
+        url: &str,
+        settings: Option<&'a FeedSettings>,
+    ) -> Option<&'a str> {
+        let domain = Url::parse(url)
+            .ok()
+            .and_then(|url| url.domain().map(|domain| domain.to_string()));
+        settings
+            .and_then(|settings| settings.mapping.as_deref())
+            .or_else(|| domain.as_deref().and_then(|d| self.find_by_domain(d)))
+            .or_else(|| self.profiles.get(DEFAULT_PROFILE).map(|_| DEFAULT_PROFILE))
+    }
+}
+
+/// The result of checking a mapping profile.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MappingCheck {
+    /// Target fields that are not part of the post or media schema. Their values
+    /// are stored as additional properties.
+    pub warnings: Vec<String>,
+}
+
+/// Check a mapping profile before saving it.
+///
+/// The profile must pass the record validation, the profiles it inherits from must exist in
+/// `profiles` and all target fields must hold text values in the `Post` or `Media` schema.
+pub fn check_mapping(
+    profiles: &MappingProfiles,
+    name: &str,
+    mapping: &FeedMapping,
+) -> Result<MappingCheck, ValidationError> {
+    mapping.validate()?;
+    let mut profiles = profiles.clone();
+    profiles.insert(name.to_string(), mapping.clone());
+    profiles
+        .resolve(name)
+        .map_err(|err| ValidationError::with_message(err.to_string()))?;
+
+    let post_schema = schema_for!(Post);
+    let media_schema = schema_for!(Media);
+    let mut errors = vec![];
+    let mut check = MappingCheck::default();
+    for field in mapping.fields.iter() {
+        let (schema, typ, key) = match field.to.strip_prefix("media.") {
+            Some(key) => (&media_schema, Media::NAME, key.to_case(Case::Camel)),
+            None => (&post_schema, Post::NAME, field.to.to_case(Case::Camel)),
+        };
+        match property(schema, &key) {
+            Some(property) if accepts_text(property) => {}
+            Some(_) => errors.push(format!(
+                "{}: {} field {} does not hold text values",
+                field.from, typ, key
+            )),
+            None => check
+                .warnings
+                .push(format!("{}: {} has no field {}", field.from, typ, key)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(ValidationError::with_message(errors.join(", ")));
+    }
+    Ok(check)
+}
+
+fn property<'a>(schema: &'a RootSchema, key: &str) -> Option<&'a Schema> {
+    schema.schema.object.as_ref()?.properties.get(key)
+}
+
+/// Check if a schema accepts a string or a list of strings.
+fn accepts_text(schema: &Schema) -> bool {
+    let schema = match schema {
+        Schema::Object(schema) => schema,
+        Schema::Bool(_) => return false,
+    };
+    let is_text = |typ: &InstanceType| match typ {
+        InstanceType::String | InstanceType::Number | InstanceType::Integer => true,
+        InstanceType::Boolean => true,
+        InstanceType::Array => match schema.array.as_ref().and_then(|a| a.items.as_ref()) {
+            Some(SingleOrVec::Single(items)) => accepts_text(items),
+            _ => false,
+        },
+        _ => false,
+    };
+    match &schema.instance_type {
+        Some(SingleOrVec::Single(typ)) => is_text(typ),
+        Some(SingleOrVec::Vec(types)) => types.iter().any(is_text),
+        None => false,
+    }
+}
+
+/// Check the mapping profiles from a TOML file and save them to the database.
+///
+/// Existing profiles with the same names are replaced. Returns the check result per profile.
+pub async fn import_mappings(
+    db: &CouchDB,
+    contents: &str,
+) -> anyhow::Result<Vec<(String, MappingCheck)>> {
+    let file = MappingProfiles::from_toml(contents)?;
+    let mut profiles = MappingProfiles::from_records(db.get_all_records().await?);
+    for (name, mapping) in file.profiles.iter() {
+        profiles.insert(name.clone(), mapping.clone());
+    }
+    let mut checks = vec![];
+    for (name, mapping) in file.profiles.iter() {
+        let check = check_mapping(&profiles, name, mapping)
+            .map_err(|err| anyhow::anyhow!("Mapping {}: {}", name, err))?;
+        checks.push((name.clone(), check));
+    }
+    let results = db.put_record_bulk_update(file.into_records()).await?;
+    if let Some(err) = results.iter().find_map(PutResult::as_err) {
+        anyhow::bail!("Failed to save mapping profile: {}", err);
+    }
+    Ok(checks)
+}
+
+/// Delete a mapping profile from the database.
+///
+/// Fails with a conflict if another profile extends the profile, because a dangling
+/// `extends` makes the profiles invalid.
+pub async fn delete_profile(db: &CouchDB, name: &str) -> Result<PutResponse, CouchError> {
+    let records: Vec<Record<FeedMapping>> = db.get_all_records().await?;
+    if let Some(child) = records
+        .iter()
+        .find(|record| record.value.extends.as_deref() == Some(name))
+    {
+        return Err(CouchError::Couch(
+            reqwest::StatusCode::CONFLICT,
+            ErrorDetails::new(
+                "conflict",
+                format!("Mapping {} is extended by {}", name, child.id()),
+                Some(FeedMapping::guid(name)),
+            ),
+        ));
+    }
+    db.delete_record(&FeedMapping::guid(name)).await
 }
 
 impl MappingManager {
@@ -35,27 +328,63 @@ impl MappingManager {
 
     pub fn with_file(f: &str) -> Self {
         Self {
-            mappings: Default::default(),
+            profiles: Default::default(),
             path: Some(f.to_string().into()),
         }
     }
 
-    pub fn to_field_hashmap(&self) -> HashMap<String, String> {
-        let map: HashMap<String, String> = self
-            .mappings
-            .clone()
+    pub fn profiles(&self) -> &MappingProfiles {
+        &self.profiles
+    }
+
+    /// Load the mapping profiles from the database, seeding it with the profiles from the
+    /// mapping file that are missing in the database.
+    pub async fn init(&mut self, db: &CouchDB) -> anyhow::Result<()> {
+        let mut profiles = MappingProfiles::from_records(db.get_all_records().await?);
+        let missing: Vec<(String, FeedMapping)> = self
+            .load_file()
+            .await?
+            .profiles
             .into_iter()
-            .map(|(_namespace, mapping)| mapping.fields.into_iter())
-            .flatten()
-            .map(|field| (field.from, field.to))
+            .filter(|(name, _)| profiles.get(name).is_none())
             .collect();
-        map
+        for (name, mapping) in missing.iter() {
+            profiles.insert(name.clone(), mapping.clone());
+        }
+        profiles.validate()?;
+        if !missing.is_empty() {
+            log::info!("Saving {} mapping profiles to the database", missing.len());
+            let records = missing
+                .into_iter()
+                .map(|(name, mapping)| Record::from_id_and_value(name, mapping))
+                .collect();
+            let results = db.put_record_bulk(records).await?;
+            // A conflict means that the profile was saved concurrently.
+            if let Some(err) = results
+                .iter()
+                .filter_map(PutResult::as_err)
+                .find(|err| err.error != "conflict")
+            {
+                anyhow::bail!("Failed to save mapping profile: {}", err);
+            }
+        }
+        self.profiles = profiles;
+        Ok(())
+    }
+
+    /// Reload the mapping profiles from the database.
+    pub async fn reload(&mut self, db: &CouchDB) -> anyhow::Result<()> {
+        let records = db.get_all_records::<FeedMapping>().await?;
+        let profiles = MappingProfiles::from_records(records);
+        profiles.validate()?;
+        self.profiles = profiles;
+        Ok(())
     }
 
-    pub async fn init(&mut self) -> anyhow::Result<()> {
+    async fn load_file(&mut self) -> anyhow::Result<MappingProfiles> {
         // Use path that was passed in (via command line arguments)
         if self.path == None {
-            self.path = mapping_path().await;
+            self.path = config_file_path(MAPPING_FILE).await;
         }
         // Use a default path if it exists.
         // This checks for user system-dependent config path, e.g. on linux:
@@ -64,26 +393,26 @@ impl MappingManager {
             let contents = read_to_string(&path)
                 .await
                 .with_context(|| format!("File not found: {}", path.as_path().to_str().unwrap()))?;
-            let mapping: HashMap<String, Mapping> = toml::from_str(&contents)?;
-            self.mappings = mapping;
+            MappingProfiles::from_toml(&contents)
+                .with_context(|| format!("Invalid mapping file: {}", path.display()))
         // Use default mapping (included at compile time)
         } else {
-            let mapping: HashMap<String, Mapping> = toml::from_str(DEFAULT_MAPPING)?;
-            self.mappings = mapping;
+            MappingProfiles::from_toml(DEFAULT_MAPPING)
         }
-        Ok(())
     }
 }
 
-async fn mapping_path() -> Option<PathBuf> {
-    let suffix = PathBuf::from(r"openaudiosearch/mapping.toml");
+/// Find a config file in the user config dir or in `/etc`, e.g. on linux:
+/// `~/.config/openaudiosearch/{name}` or `/etc/openaudiosearch/{name}`
+pub(crate) async fn config_file_path(name: &str) -> Option<PathBuf> {
+    let suffix = PathBuf::from(r"openaudiosearch").join(name);
     if let Some(config_path) = config_dir() {
         let path = config_path.join(&suffix);
         if path_exists(&path).await {
             return Some(path);
         }
     }
-    let path = PathBuf::from(r"/etc/openaudiosearch/mapping.toml").join(&suffix);
+    let path = PathBuf::from(r"/etc").join(&suffix);
     if path_exists(&path).await {
         return Some(path);
     }
@@ -105,3 +434,97 @@ async fn path_exists(path: &Path) -> bool {
     };
     metadata
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILES: &str = r#"
+[base]
+fields = [
+  { from = "itunes:author", to = "creator" },
+  { from = "cba:duration", to = "media.duration" },
+]
+
+[cba]
+extends = "base"
+domains = ["cba.media", "*.cba.media"]
+fields = [{ from = "cba:teaser", to = "abstract" }]
+
+[default]
+extends = "base"
+"#;
+
+    #[test]
+    fn select_profiles() {
+        let profiles = MappingProfiles::from_toml(PROFILES).unwrap();
+        let map = profiles.select("https://cba.media/podcast/feed", None);
+        assert_eq!(map.get("cba:teaser").unwrap().to, "abstract");
+        assert_eq!(map.get("itunes:author").unwrap().to, "creator");
+
+        let map = profiles.select("https://example.org/feed", None);
+        assert!(map.get("cba:teaser").is_none());
+        assert_eq!(map.get("itunes:author").unwrap().to, "creator");
+
+        let settings = FeedSettings {
+            mapping: Some("cba".into()),
+            ..Default::default()
+        };
+        let map = profiles.select("https://example.org/feed", Some(&settings));
+        assert_eq!(map.get("cba:teaser").unwrap().to, "abstract");
+    }
+
+    #[test]
+    fn invalid_inheritance() {
+        let missing = "[a]\nextends = \"b\"\nfields = []";
+        assert!(MappingProfiles::from_toml(missing).is_err());
+        let cycle = "[a]\nextends = \"b\"\n[b]\nextends = \"a\"";
+        assert!(MappingProfiles::from_toml(cycle).is_err());
+    }
+
+    #[test]
+    fn check_targets() {
+        let profiles = MappingProfiles::from_toml(DEFAULT_MAPPING).unwrap();
+        let mapping: FeedMapping = toml::from_str(
+            r#"
+extends = "cba"
+fields = [
+  { from = "a:title", to = "headline" },
+  { from = "a:tags", to = "genre" },
+  { from = "a:length", to = "media.duration" },
+  { from = "a:foo", to = "foo" },
+]
+"#,
+        )
+        .unwrap();
+        let check = check_mapping(&profiles, "test", &mapping).unwrap();
+        assert_eq!(check.warnings.len(), 1);
+
+        let mut invalid = mapping.clone();
+        invalid.fields[0].to = "media".into();
+        assert!(check_mapping(&profiles, "test", &invalid).is_err());
+
+        let mut invalid = mapping;
+        invalid.extends = Some("missing".into());
+        assert!(check_mapping(&profiles, "test", &invalid).is_err());
+    }
+
+    #[test]
+    fn select_oai_profile() {
+        let profiles = MappingProfiles::from_toml(PROFILES).unwrap();
+        let mut source = OaiSource::with_url("https://archive.example/oai".into());
+        let map = profiles.select_oai(&source);
+        assert_eq!(map.get("dc:title").unwrap().to, "headline");
+        source.mapping = Some("cba".into());
+        let map = profiles.select_oai(&source);
+        assert!(map.get("dc:title").is_none());
+        assert_eq!(map.get("cba:teaser").unwrap().to, "abstract");
+    }
+
+    #[test]
+    fn default_mapping() {
+        let profiles = MappingProfiles::from_toml(DEFAULT_MAPPING).unwrap();
+        let map = profiles.select("https://www.freie-radios.net/portal/podcast.php?rss", None);
+        assert_eq!(map.get("frn:radio").unwrap().to, "publisher");
+    }
+}
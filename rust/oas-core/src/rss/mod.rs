@@ -4,155 +4,347 @@ use convert_case::{Case, Casing};
 use oas_common::types::Feed;
 use oas_common::{types::Post, util};
 use oas_common::{Reference, UntypedRecord};
+use rss::extension::itunes::ITunesItemExtension;
 use rss::extension::ExtensionMap;
 use rss::Channel;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::time::Duration;
 use url::{ParseError, Url};
 
 use crate::types::{FeedSettings, Media};
 use crate::Record;
+use mapping::FieldMap;
+use oas_common::mapping::{apply_transforms, parse_duration};
+use podcast::PodcastItem;
+use update::ItemChange;
 pub mod crawlers;
 mod error;
+pub mod identity;
+pub mod interval;
 pub mod manager;
 pub mod mapping;
+pub mod moves;
+pub mod oai;
+pub mod opml;
 pub mod ops;
+pub mod podcast;
+pub mod preview;
+pub mod scheduler;
+pub mod show;
+pub mod update;
+pub mod websub;
 
 pub use error::{RssError, RssResult};
 pub use manager::FeedManager;
 pub use ops::{Crawler, FetchedFeedPage, Next};
+pub use preview::FeedPreview;
+
+/// The result of saving the items of a feed.
+#[derive(Debug, Clone, Default)]
+pub struct SaveReport {
+    /// The records that were mapped from the feed
+    pub records: Vec<UntypedRecord>,
+    /// The results of writing new and changed records
+    pub put_result: Vec<PutResult>,
+    /// Number of records that were created
+    pub created: usize,
+    /// Number of saved records that were updated
+    pub updated: usize,
+    /// Number of saved records that did not change
+    pub unchanged: usize,
+}
+
+impl SaveReport {
+    /// Whether any of the records was saved before.
+    pub fn contains_existing(&self) -> bool {
+        self.updated + self.unchanged > 0
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct FeedWatcher {
     url: Url,
     client: reqwest::Client,
+    resource_client: reqwest::Client,
     channel: Option<Channel>,
     settings: FeedSettings,
-    mapping: HashMap<String, String>,
+    mapping: FieldMap,
     feed_record: Option<Record<Feed>>,
+    identity_scope: Option<String>,
+    moved_to: Option<Url>,
 }
 
 impl FeedWatcher {
     pub fn new(
         url: impl AsRef<str>,
         settings: Option<FeedSettings>,
-        mapping: HashMap<String, String>,
+        mapping: FieldMap,
         feed_record: Option<Record<Feed>>,
     ) -> Result<Self, ParseError> {
-        let client = reqwest::Client::new();
-        Self::with_client(client, url, settings, mapping, feed_record)
+        let client = reqwest::Client::builder()
+            .redirect(moves::redirect_policy())
+            .build()
+            .unwrap_or_default();
+        let watcher = Self::with_client(client, url, settings, mapping, feed_record)?;
+        Ok(watcher.with_resource_client(reqwest::Client::new()))
     }
 
     pub fn with_client(
         client: reqwest::Client,
         url: impl AsRef<str>,
         settings: Option<FeedSettings>,
-        mapping: HashMap<String, String>,
+        mapping: FieldMap,
         feed_record: Option<Record<Feed>>,
     ) -> Result<Self, ParseError> {
         let url = url.as_ref().parse()?;
         let feed = Self {
             url,
+            resource_client: client.clone(),
             client,
             channel: None,
             settings: settings.unwrap_or_default(),
             mapping,
             feed_record,
+            identity_scope: None,
+            moved_to: None,
         };
         Ok(feed)
     }
 
+    /// Use a separate HTTP client for the resources of feed items (podcast transcripts and
+    /// chapters), e.g. one that follows permanent redirects.
+    pub fn with_resource_client(mut self, client: reqwest::Client) -> Self {
+        self.resource_client = client;
+        self
+    }
+
     pub fn url(&self) -> &Url {
         &self.url
     }
-    pub async fn watch(&mut self, db: CouchDB) -> Result<(), RssError> {
-        let duration = Duration::from_secs(self.settings.check_interval);
-        let mut interval = tokio::time::interval(duration);
-        loop {
-            self.load().await?;
-            self.save(&db, false).await?;
-            interval.tick().await;
-        }
+
+    /// Set the URL that scopes the item IDs if no feed record is set.
+    ///
+    /// Used when crawling the pages of a feed, which all have different URLs.
+    pub fn set_identity_scope(&mut self, scope: impl ToString) {
+        self.identity_scope = Some(scope.to_string());
     }
 
-    pub async fn save(
-        &mut self,
-        db: &CouchDB,
-        update: bool,
-    ) -> Result<(Vec<PutResult>, Vec<UntypedRecord>), RssError> {
-        let records = self.to_post_and_media_records()?;
-        let put_result = if update {
-            db.put_untyped_record_bulk_update(records.clone()).await?
-        } else {
-            db.put_untyped_record_bulk(records.clone()).await?
-        };
+    /// The field mapping that is applied to the feed items.
+    pub fn mapping(&self) -> &FieldMap {
+        &self.mapping
+    }
+
+    /// Create a watcher for another page of this feed, e.g. when crawling.
+    ///
+    /// The new watcher shares the settings, mapping and feed record of this watcher.
+    pub fn for_page(&self, url: &Url) -> Self {
+        let mut watcher = self.clone();
+        watcher.identity_scope = Some(self.identity_scope().to_string());
+        watcher.url = url.clone();
+        watcher.channel = None;
+        watcher.moved_to = None;
+        watcher
+    }
 
-        // log::trace!("put result: {:?}", put_result);
+    /// The number of items in the loaded feed.
+    pub fn item_count(&self) -> usize {
+        self.channel
+            .as_ref()
+            .map(|channel| channel.items().len())
+            .unwrap_or_default()
+    }
 
-        let (success, error): (Vec<_>, Vec<_>) = put_result
+    /// Get the target of a `<atom:link>` with a `rel` attribute from the loaded feed.
+    pub fn channel_link(&self, rel: &str) -> Option<String> {
+        let links = self
+            .channel
+            .as_ref()?
+            .extensions()
+            .get("atom")?
+            .get("link")?;
+        links
             .iter()
-            .partition(|r| matches!(r, PutResult::Ok(_)));
+            .find(|link| link.attrs().get("rel").map(|r| r.as_str()) == Some(rel))
+            .and_then(|link| link.attrs().get("href"))
+            .map(|href| href.to_string())
+    }
 
-        log::debug!(
-            "saved posts from feed {} ({} success, {} exists or error)",
-            self.url,
-            success.len(),
-            error.len()
-        );
-        for error in error {
-            if let PutResult::Err(error) = error {
-                log::trace!(
-                    "skipped to save post `{}` from feed `{}`: {}",
-                    error.id.as_deref().unwrap_or_default(),
-                    self.url(),
-                    error.reason
-                );
+    /// The URL that scopes item IDs, see [ItemIdentity](oas_common::types::ItemIdentity).
+    pub fn identity_scope(&self) -> &str {
+        if let Some(feed) = &self.feed_record {
+            feed.value.identity_scope()
+        } else if let Some(scope) = &self.identity_scope {
+            scope
+        } else {
+            self.url.as_str()
+        }
+    }
+
+    /// The interval after which the feed should be fetched again.
+    ///
+    /// If adaptive intervals are enabled in the feed settings, the interval is derived from the
+    /// publication dates of the items of the loaded feed.
+    pub fn check_interval(&self) -> Duration {
+        let fixed = Duration::from_secs(self.settings.check_interval);
+        match (&self.settings.adaptive_interval, &self.channel) {
+            (Some(bounds), Some(channel)) => {
+                let dates: Vec<_> = channel
+                    .items()
+                    .iter()
+                    .filter_map(|item| item.pub_date())
+                    .filter_map(|date| chrono::DateTime::parse_from_rfc2822(date).ok())
+                    .map(|date| date.with_timezone(&Utc))
+                    .collect();
+                interval::adaptive_interval(&dates, Utc::now(), bounds).unwrap_or(fixed)
             }
+            _ => fixed,
         }
-        Ok((put_result, records))
     }
 
+    pub async fn watch(&mut self, db: CouchDB) -> Result<(), RssError> {
+        loop {
+            self.load().await?;
+            self.save(&db, false).await?;
+            tokio::time::sleep(self.check_interval()).await;
+        }
+    }
+
+    /// Save the posts and medias of the loaded feed, and the show of the feed.
+    ///
+    /// New items are created. Items that were saved before are updated if they changed in
+    /// the feed (see [update]), or always if `update` is true.
+    pub async fn save(&mut self, db: &CouchDB, update: bool) -> Result<SaveReport, RssError> {
+        let mut posts = self.to_posts_with_podcast_items()?;
+        self.load_podcast_resources(db, &mut posts).await;
+        let posts = posts.into_iter().map(|(post, _podcast)| post).collect();
+        let records = self.posts_into_records(posts);
+        let report = save_records(db, records, update, self.url.as_str()).await?;
+        // The show is saved separately, as the report only covers the items of the feed.
+        let show = self
+            .to_show()?
+            .into_untyped()
+            .map_err(anyhow::Error::from)?;
+        save_records(db, vec![show], false, self.url.as_str()).await?;
+        Ok(report)
+    }
+
+    /// Fetch and parse the feed.
+    ///
+    /// Permanent redirects are followed here (see [moves::redirect_policy]). If the feed was
+    /// permanently redirected or announces a new URL with `<itunes:new-feed-url>`, the new
+    /// URL is available from [moved_to](Self::moved_to) afterwards.
     pub async fn load(&mut self) -> Result<(), RssError> {
-        let res = self.client.get(self.url.as_str()).send().await?;
+        let mut url = self.url.clone();
+        let mut res = self.client.get(url.as_str()).send().await?;
+        let mut redirects = 0;
+        while moves::is_permanent_redirect(res.status()) {
+            redirects += 1;
+            if redirects > moves::MAX_REDIRECTS {
+                return Err(RssError::Other(format!(
+                    "Too many redirects for {}",
+                    self.url
+                )));
+            }
+            let location = res
+                .headers()
+                .get(reqwest::header::LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .ok_or_else(|| RssError::Other(format!("Invalid redirect for {}", res.url())))?;
+            url = res.url().join(location)?;
+            res = self.client.get(url.as_str()).send().await?;
+        }
         if !res.status().is_success() {
             return Err(RssError::RemoteHttpError(Box::new(res)));
         }
         let bytes = res.bytes().await?;
-        let channel = Channel::read_from(&bytes[..])?;
+        self.load_from_bytes(&bytes[..])?;
+        if url != self.url {
+            self.moved_to = Some(url);
+        }
+        Ok(())
+    }
+
+    /// Parse the feed from its contents, e.g. when the feed was pushed by a WebSub hub.
+    pub fn load_from_bytes(&mut self, bytes: &[u8]) -> Result<(), RssError> {
+        let channel = Channel::read_from(bytes)?;
+        let new_feed_url = channel
+            .itunes_ext()
+            .and_then(|ext| ext.new_feed_url())
+            .and_then(|new_url| Url::parse(new_url.trim()).ok());
+        self.moved_to = new_feed_url.filter(|new_url| *new_url != self.url);
         self.channel = Some(channel);
         Ok(())
     }
 
+    /// The new URL of the feed, if the loaded feed announced a move.
+    pub fn moved_to(&self) -> Option<&Url> {
+        self.moved_to.as_ref()
+    }
+
+    /// Fetch publisher-provided transcripts and chapters for the medias of new posts.
+    ///
+    /// Medias that already exist in the database are skipped, so that the linked documents are
+    /// only fetched once. Errors are logged and do not fail the whole feed.
+    async fn load_podcast_resources(
+        &self,
+        db: &CouchDB,
+        posts: &mut [(Record<Post>, PodcastItem)],
+    ) {
+        let guids: Vec<String> = posts
+            .iter()
+            .filter(|(_post, podcast)| podcast.has_resources())
+            .map(|(post, _podcast)| post.value.media.iter().map(|m| m.guid().to_string()))
+            .flatten()
+            .collect();
+        if guids.is_empty() {
+            return;
+        }
+        let guids: Vec<&str> = guids.iter().map(|s| s.as_str()).collect();
+        let existing: HashSet<String> = match db.get_many_records::<Media>(&guids).await {
+            Ok(records) => records.into_iter().map(|r| r.guid().to_string()).collect(),
+            Err(err) => {
+                log::warn!(
+                    "Failed to load existing medias for feed {}: {}",
+                    self.url,
+                    err
+                );
+                return;
+            }
+        };
+
+        for (post, podcast) in posts.iter_mut() {
+            if !podcast.has_resources() {
+                continue;
+            }
+            for media in post.value.media.iter_mut().filter_map(|r| r.record_mut()) {
+                if existing.contains(media.guid()) {
+                    continue;
+                }
+                if let Err(err) = podcast
+                    .load_into_media(&self.resource_client, &mut media.value)
+                    .await
+                {
+                    log::warn!(
+                        "Failed to load podcast transcript or chapters for media {}: {}",
+                        media.guid(),
+                        err
+                    );
+                }
+            }
+        }
+    }
+
     pub fn to_post_and_media_records(&self) -> Result<Vec<UntypedRecord>, RssError> {
         let posts = self.to_posts()?;
+        Ok(self.posts_into_records(posts))
+    }
+
+    fn posts_into_records(&self, posts: Vec<Record<Post>>) -> Vec<UntypedRecord> {
         let mut docs = vec![];
+        let show_guid = self.show_guid();
         for mut post in posts.into_iter() {
-            if let Some(feed) = &self.feed_record {
-                // Assign feed reference on post.
-                post.value.feeds = vec![Reference::Id(feed.guid().to_string())];
-                // Assign feed reference on medias.
-                let post_guid = post.guid().to_string();
-                // Copy post job settings.
-                if !feed.value.post_jobs.is_empty() {
-                    post.meta_mut()
-                        .jobs_mut()
-                        .copy_settings(&feed.value.post_jobs);
-                }
-                // Copy media job settings and add references to media.
-                for media in post.value.media.iter_mut().filter_map(|r| r.record_mut()) {
-                    media.value.posts.push(Reference::Id(post_guid.clone()));
-                    media
-                        .value
-                        .feeds
-                        .push(Reference::Id(feed.guid().to_string()));
-                    if !feed.value.media_jobs.is_empty() {
-                        media
-                            .meta_mut()
-                            .jobs_mut()
-                            .copy_settings(&feed.value.media_jobs);
-                    }
-                }
-            }
+            self.assign_feed(&mut post);
+            post.value.shows = vec![Reference::Id(show_guid.clone())];
             let mut refs = post.extract_refs();
             docs.append(&mut refs);
             // TODO: Handle error?
@@ -160,18 +352,95 @@ impl FeedWatcher {
                 docs.push(record);
             }
         }
-        Ok(docs)
+        docs
+    }
+
+    /// Assign the feed reference and the job settings of the feed record to a post
+    /// and its medias.
+    fn assign_feed(&self, post: &mut Record<Post>) {
+        if let Some(feed) = &self.feed_record {
+            // Assign feed reference on post.
+            post.value.feeds = vec![Reference::Id(feed.guid().to_string())];
+            // Assign feed reference on medias.
+            let post_guid = post.guid().to_string();
+            // Copy post job settings.
+            if !feed.value.post_jobs.is_empty() {
+                post.meta_mut()
+                    .jobs_mut()
+                    .copy_settings(&feed.value.post_jobs);
+            }
+            // Copy media job settings and add references to media.
+            for media in post.value.media.iter_mut().filter_map(|r| r.record_mut()) {
+                media.value.posts.push(Reference::Id(post_guid.clone()));
+                media
+                    .value
+                    .feeds
+                    .push(Reference::Id(feed.guid().to_string()));
+                if !feed.value.media_jobs.is_empty() {
+                    media
+                        .meta_mut()
+                        .jobs_mut()
+                        .copy_settings(&feed.value.media_jobs);
+                }
+            }
+        }
     }
 
     pub fn to_posts(&self) -> Result<Vec<Record<Post>>, RssError> {
+        let posts = self.to_posts_with_podcast_items()?;
+        Ok(posts.into_iter().map(|(post, _podcast)| post).collect())
+    }
+
+    fn to_posts_with_podcast_items(&self) -> Result<Vec<(Record<Post>, PodcastItem)>, RssError> {
+        let mut warnings = vec![];
+        let records = self.map_items(&mut warnings)?;
+        for warning in warnings {
+            log::warn!("Feed {}: {}", self.url, warning);
+        }
+        Ok(records)
+    }
+
+    /// Map the items of the loaded feed to posts.
+    ///
+    /// Items that are skipped or could not be mapped completely are reported in `warnings`.
+    fn map_items(
+        &self,
+        warnings: &mut Vec<String>,
+    ) -> Result<Vec<(Record<Post>, PodcastItem)>, RssError> {
         if self.channel.is_none() {
             return Err(RssError::NoChannel);
         }
         let channel = self.channel.as_ref().unwrap();
+        // Items without artwork use the artwork of the feed.
+        let channel_image = channel
+            .itunes_ext()
+            .and_then(|ext| ext.image())
+            .or_else(|| channel.image().map(|image| image.url()));
         let mut records = vec![];
         for item in channel.items() {
-            let record = item_into_post(&self.mapping, item.clone());
-            records.push(record);
+            let keys = identity::item_keys(item);
+            let id = match keys.id(&self.settings.identity, self.identity_scope()) {
+                Some(id) => id,
+                None => {
+                    warnings.push(format!(
+                        "Skipping item without identity: {:?}",
+                        item.title()
+                    ));
+                    continue;
+                }
+            };
+            let podcast = PodcastItem::from_item(item);
+            let mut record = item_into_post(&self.mapping, item.clone(), id, warnings);
+            record.meta_mut().set_item_keys(Some(keys));
+            if record.value.image.is_none() {
+                record.value.image = channel_image.map(|image| image.to_string());
+            }
+            for person in podcast.persons.iter() {
+                if !record.value.contributor.contains(&person.name) {
+                    record.value.contributor.push(person.name.clone());
+                }
+            }
+            records.push((record, podcast));
         }
         Ok(records)
     }
@@ -180,8 +449,11 @@ impl FeedWatcher {
         if let Some(channel) = &self.channel {
             let mut records = vec![];
             for item in channel.items() {
-                let record = item_into_record(item.clone());
-                records.push(record);
+                let id =
+                    identity::item_keys(item).id(&self.settings.identity, self.identity_scope());
+                if let Some(id) = id {
+                    records.push(item_into_record(item.clone(), id));
+                }
             }
             Ok(records)
         } else {
@@ -190,84 +462,207 @@ impl FeedWatcher {
     }
 }
 
+/// Save records that were mapped from a source (e.g. a feed URL).
+///
+/// New records are created. Records that were saved before are updated if they changed in
+/// the source (see [update]), or always if `update` is true.
+pub async fn save_records(
+    db: &CouchDB,
+    mut records: Vec<UntypedRecord>,
+    update: bool,
+    source: &str,
+) -> Result<SaveReport, RssError> {
+    for record in records.iter_mut() {
+        update::set_source_hash(record);
+    }
+
+    let guids: Vec<&str> = records.iter().map(|r| r.guid()).collect();
+    let existing: HashMap<String, UntypedRecord> = db
+        .get_many_records_untyped(&guids)
+        .await?
+        .into_iter()
+        .map(|r| (r.guid().to_string(), r))
+        .collect();
+
+    let mut report = SaveReport::default();
+    let mut docs = vec![];
+    for record in records.iter() {
+        let saved = existing.get(record.guid()).cloned();
+        match (update::detect_change(saved.as_ref(), record), saved) {
+            (ItemChange::New, _) => {
+                report.created += 1;
+                docs.push(record.clone());
+            }
+            (ItemChange::Unchanged | ItemChange::Backfill, Some(saved)) if update => {
+                report.updated += 1;
+                docs.push(update::merge(saved, record.clone()));
+            }
+            (ItemChange::Changed, Some(saved)) => {
+                report.updated += 1;
+                docs.push(update::merge(saved, record.clone()));
+            }
+            (ItemChange::Backfill, Some(saved)) => {
+                report.unchanged += 1;
+                docs.push(update::backfill(saved, record));
+            }
+            _ => report.unchanged += 1,
+        }
+    }
+    let put_result = db.put_untyped_record_bulk_update(docs).await?;
+
+    let error: Vec<_> = put_result
+        .iter()
+        .filter(|r| !matches!(r, PutResult::Ok(_)))
+        .collect();
+
+    log::debug!(
+        "saved records from {} ({} created, {} updated, {} unchanged, {} errors)",
+        source,
+        report.created,
+        report.updated,
+        report.unchanged,
+        error.len()
+    );
+    for error in error {
+        if let PutResult::Err(error) = error {
+            log::trace!(
+                "skipped to save record `{}` from `{}`: {}",
+                error.id.as_deref().unwrap_or_default(),
+                source,
+                error.reason
+            );
+        }
+    }
+    report.put_result = put_result;
+    report.records = records;
+    Ok(report)
+}
+
 fn resolve_extensions(
     extensions: &rss::extension::ExtensionMap,
-    mapping: &HashMap<String, String>,
-) -> HashMap<String, String> {
-    let result: HashMap<String, String> = mapping
+    mapping: &FieldMap,
+) -> HashMap<String, serde_json::Value> {
+    apply_mapping(mapping, |from_key| {
+        let mut parts = from_key.split(':');
+        match (parts.next(), parts.next()) {
+            (Some(prefix), Some(suffix)) => extensions
+                .get(prefix)
+                .and_then(|inner_map| inner_map.get(suffix))
+                .and_then(|extension| extension.get(0))
+                .and_then(|extension| extension.value())
+                .map(|value| serde_json::Value::String(value.to_string())),
+            _ => None,
+        }
+    })
+}
+
+/// Apply a field mapping to the values of a source record. Returns the mapped values keyed
+/// by their target field.
+fn apply_mapping<F>(mapping: &FieldMap, get: F) -> HashMap<String, serde_json::Value>
+where
+    F: Fn(&str) -> Option<serde_json::Value>,
+{
+    mapping
         .iter()
-        .filter_map(|(from_key, target_key)| {
-            let mut parts = from_key.split(':');
-            match (parts.next(), parts.next()) {
-                (Some(prefix), Some(suffix)) => {
-                    let value = extensions
-                        .get(prefix)
-                        .and_then(|inner_map| inner_map.get(suffix))
-                        .and_then(|extension| extension.get(0))
-                        .and_then(|extension| extension.value().map(|value| value.to_string()))
-                        .map(|value| (target_key.to_string(), value));
-                    value
+        .filter_map(|(from_key, field)| {
+            // Transforms are applied to missing values too, so that defaults can be set.
+            let value = get(from_key).unwrap_or(serde_json::Value::Null);
+            match apply_transforms(&field.transforms, value) {
+                Ok(serde_json::Value::Null) => None,
+                Ok(value) => Some((field.to.clone(), value)),
+                Err(err) => {
+                    log::debug!("Failed to map {}: {}", from_key, err);
+                    None
                 }
-                _ => None,
             }
         })
+        .collect()
+}
+
+/// Build a post from mapped fields. Media fields (`media.*`) are skipped.
+///
+/// If the fields cannot be deserialized into a post, they are ignored and a warning is added.
+fn post_from_fields(
+    mapped_fields: &HashMap<String, serde_json::Value>,
+    id: &str,
+    warnings: &mut Vec<String>,
+) -> Post {
+    let mapped_fields_json: serde_json::Map<String, serde_json::Value> = mapped_fields
+        .iter()
+        .filter(|(k, _v)| !(k.starts_with("media.")))
+        .map(|(k, v)| (k.to_case(Case::Camel), v.clone()))
         .collect();
+    let post: Result<Post, serde_json::Error> =
+        serde_json::from_value(serde_json::Value::Object(mapped_fields_json));
+    match post {
+        Ok(post) => post,
+        Err(err) => {
+            warnings.push(format!(
+                "Failed to map post {}, mapped fields are ignored: {}",
+                id, err
+            ));
+            Post::default()
+        }
+    }
+}
 
-    result
+/// Build a media from the mapped media fields (`media.*`) and additional fields.
+///
+/// If the fields cannot be deserialized into a media, they are ignored and a warning is added.
+fn media_from_fields(
+    mapped_fields: &HashMap<String, serde_json::Value>,
+    additional_fields: serde_json::Map<String, serde_json::Value>,
+    id: &str,
+    warnings: &mut Vec<String>,
+) -> Media {
+    let mut mapped_fields_json: serde_json::Map<String, serde_json::Value> = mapped_fields
+        .iter()
+        .filter_map(|(k, v)| {
+            k.strip_prefix("media.")
+                .map(|k| (k.to_case(Case::Camel), v.clone()))
+        })
+        .collect();
+    mapped_fields_json.extend(additional_fields);
+    let media: Result<Media, serde_json::Error> =
+        serde_json::from_value(serde_json::Value::Object(mapped_fields_json));
+    match media {
+        Ok(media) => media,
+        Err(err) => {
+            warnings.push(format!(
+                "Failed to map media of post {}, mapped fields are ignored: {}",
+                id, err
+            ));
+            Media::default()
+        }
+    }
 }
 
-fn item_into_post(mapping: &HashMap<String, String>, item: rss::Item) -> Record<Post> {
+fn item_into_post(
+    mapping: &FieldMap,
+    item: rss::Item,
+    id: String,
+    warnings: &mut Vec<String>,
+) -> Record<Post> {
     // Create initial post by parsing extension values from the RSS item
     // and deserializing via serde into the Post struct. Further regular
     // values will be set on this struct manually (see below.)
-    // TODO: implement mapping management (load mapping, save mapping)
     let extensions: &ExtensionMap = item.extensions();
 
     let mapped_fields = resolve_extensions(extensions, mapping);
-    // log::debug!("resolve_extensions result: {:#?}", mapped_fields);
-    let mut post = {
-        let mapped_fields_json: serde_json::Map<String, serde_json::Value> = mapped_fields
-            .clone()
-            .into_iter()
-            .map(|(k, v)| (k.to_case(Case::Camel), serde_json::Value::String(v)))
-            .filter(|(k, _v)| !(k.starts_with("media.")))
-            .collect();
-        let post: Result<Post, serde_json::Error> =
-            serde_json::from_value(serde_json::Value::Object(mapped_fields_json));
-
-        post.unwrap_or_default()
-    };
-    // log::debug!("post after mapping {:#?}", post);
+    let mut post = post_from_fields(&mapped_fields, &id, warnings);
 
     // If the RSS item has an enclosure set create a Media record that will be referenced by the post.
     let media = if let Some(enclosure) = item.clone().enclosure {
-        let mut mapped_fields_json: serde_json::Map<String, serde_json::Value> = mapped_fields
-            .into_iter()
-            .filter(|(k, _v)| k.starts_with("media."))
-            .map(|(k, v)| {
-                let arr: Vec<&str> = k.split('.').collect();
-                let v = serde_json::Value::String(v);
-                let k = arr[1].to_case(Case::Camel);
-                (k, v)
-            })
-            .collect();
-        mapped_fields_json.insert(
+        let mut additional_fields = serde_json::Map::new();
+        additional_fields.insert(
             "contentUrl".into(),
             serde_json::Value::String(enclosure.url.clone()),
         );
-        mapped_fields_json.insert(
+        additional_fields.insert(
             "encodingFormat".into(),
             serde_json::Value::String(enclosure.mime_type.clone()),
         );
-        let media: Result<Media, serde_json::Error> =
-            serde_json::from_value(serde_json::Value::Object(mapped_fields_json));
-        let mut media = match media {
-            Ok(media) => media,
-            Err(err) => {
-                log::error!("Failed to map media record: {:?}", err);
-                Media::default()
-            }
-        };
+        let mut media = media_from_fields(&mapped_fields, additional_fields, &id, warnings);
         media.content_url = enclosure.url;
         media.encoding_format = Some(enclosure.mime_type);
         if let Ok(length) = enclosure.length.parse::<u32>() {
@@ -305,6 +700,10 @@ fn item_into_post(mapping: &HashMap<String, String>, item: rss::Item) -> Record<
         }
     }
 
+    if let Some(ext) = itunes_ext {
+        set_itunes_fields(&mut post, ext);
+    }
+
     if post.creator.is_empty() {
         if let Some(ext) = dublin_core_ext {
             post.creator = ext.creators().to_vec();
@@ -330,14 +729,46 @@ fn item_into_post(mapping: &HashMap<String, String>, item: rss::Item) -> Record<
         post.genre.push(category.name);
     }
 
-    // TODO: What to do with items without GUID?
-    let guid = guid.unwrap();
-    let id = util::id_from_hashed_string(guid.value().to_string());
     Record::from_id_and_value(id, post)
 }
 
-fn item_into_record(item: rss::Item) -> Record<Media> {
-    let guid = item.guid.clone();
+/// Set the episode metadata from the iTunes extension of an item on a post and its medias.
+///
+/// Values that were set by the mapping are kept.
+fn set_itunes_fields(post: &mut Post, ext: &ITunesItemExtension) {
+    if post.image.is_none() {
+        post.image = ext.image().map(|image| image.to_string());
+    }
+    if post.episode_number.is_none() {
+        post.episode_number = ext.episode().and_then(|n| n.trim().parse().ok());
+    }
+    if post.season_number.is_none() {
+        post.season_number = ext.season().and_then(|n| n.trim().parse().ok());
+    }
+    if post.episode_type.is_none() {
+        post.episode_type = ext.episode_type().map(|t| t.trim().to_lowercase());
+    }
+    if post.explicit.is_none() {
+        post.explicit = ext.explicit().and_then(parse_explicit);
+    }
+    if let Some(duration) = ext.duration().and_then(parse_duration) {
+        for media in post.media.iter_mut().filter_map(|r| r.record_mut()) {
+            if media.value.duration.is_none() {
+                media.value.duration = Some(duration as f32);
+            }
+        }
+    }
+}
+
+fn parse_explicit(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "yes" | "true" | "explicit" => Some(true),
+        "no" | "false" | "clean" => Some(false),
+        _ => None,
+    }
+}
+
+fn item_into_record(item: rss::Item, id: String) -> Record<Media> {
     let mut value = Media {
         ..Default::default()
     };
@@ -345,9 +776,60 @@ fn item_into_record(item: rss::Item) -> Record<Media> {
         value.content_url = enclosure.url;
         value.encoding_format = Some(enclosure.mime_type);
     }
-
-    // TODO: What to do with items without GUID?
-    let guid = guid.unwrap();
-    let id = util::id_from_hashed_string(guid.value().to_string());
     Record::from_id_and_value(id, value)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FEED: &str = r#"<?xml version="1.0"?>
+<rss version="2.0" xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd">
+  <channel>
+    <title>Test</title>
+    <link>https://example.org</link>
+    <description>Test</description>
+    <itunes:image href="https://example.org/show.jpg"/>
+    <item>
+      <title>One</title>
+      <guid>one</guid>
+      <itunes:image href="https://example.org/one.jpg"/>
+      <itunes:episode>3</itunes:episode>
+      <itunes:season>2</itunes:season>
+      <itunes:episodeType>Full</itunes:episodeType>
+      <itunes:explicit>no</itunes:explicit>
+      <itunes:duration>01:02:03</itunes:duration>
+      <enclosure url="https://example.org/one.mp3" length="100" type="audio/mpeg"/>
+    </item>
+    <item>
+      <title>Two</title>
+      <guid>two</guid>
+      <itunes:episodeType>trailer</itunes:episodeType>
+      <itunes:explicit>true</itunes:explicit>
+    </item>
+  </channel>
+</rss>"#;
+
+    #[test]
+    fn itunes_fields() {
+        let mut watcher =
+            FeedWatcher::new("https://example.org/feed", None, FieldMap::new(), None).unwrap();
+        watcher.load_from_bytes(FEED.as_bytes()).unwrap();
+        let posts = watcher.to_posts().unwrap();
+
+        let one = &posts[0].value;
+        assert_eq!(one.image.as_deref(), Some("https://example.org/one.jpg"));
+        assert_eq!(one.episode_number, Some(3));
+        assert_eq!(one.season_number, Some(2));
+        assert_eq!(one.episode_type.as_deref(), Some("full"));
+        assert_eq!(one.explicit, Some(false));
+        let media = one.media[0].record().unwrap();
+        assert_eq!(media.value.duration, Some(3723.));
+
+        let two = &posts[1].value;
+        assert_eq!(two.image.as_deref(), Some("https://example.org/show.jpg"));
+        assert_eq!(two.episode_number, None);
+        assert_eq!(two.episode_type.as_deref(), Some("trailer"));
+        assert_eq!(two.explicit, Some(true));
+    }
+}
@@ -0,0 +1,228 @@
+//! Feeds that move to a new URL.
+//!
+//! A feed announces a move with a permanent redirect (301 or 308) or with
+//! `<itunes:new-feed-url>`. Feed IDs are derived from the feed URL, so moving a feed creates
+//! a feed record with the ID of the new URL, keeps the old URLs as aliases and points the
+//! posts, medias and show of the old feed to the new feed record. Item IDs are scoped by the
+//! original feed URL (see [Feed::identity_scope]), so items are not duplicated.
+//!
+//! Moves are applied automatically if `followMoves` is set in the feed settings. Otherwise
+//! the new URL is stored in `movedTo` on the feed until an admin moves the feed.
+
+use anyhow::Context;
+use clap::Parser;
+use oas_common::types::{Feed, Media, Post, Show};
+use oas_common::util::id_from_hashed_string;
+use oas_common::{Record, Reference, TypedValue};
+use reqwest::redirect::{Attempt, Policy};
+use reqwest::StatusCode;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::FeedWatcher;
+use crate::couch::{CouchDB, PutResult};
+
+/// Max number of redirects that are followed for a feed request.
+pub const MAX_REDIRECTS: usize = 10;
+
+#[derive(Parser, Debug)]
+pub struct MoveOpts {
+    /// Feed ID or URL
+    pub id_or_url: String,
+    /// New URL (default: the URL the feed announced)
+    pub url: Option<String>,
+}
+
+/// The result of moving a feed.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MoveReport {
+    /// ID of the feed record at the new URL
+    pub feed_id: String,
+    pub from: String,
+    pub to: String,
+    /// Number of posts that were moved to the new feed record
+    pub posts: usize,
+    /// Number of medias that were moved to the new feed record
+    pub medias: usize,
+}
+
+/// Whether a response status is a permanent redirect.
+pub fn is_permanent_redirect(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::MOVED_PERMANENTLY | StatusCode::PERMANENT_REDIRECT
+    )
+}
+
+/// Redirect policy for feed requests.
+///
+/// Permanent redirects are not followed by the client but by [FeedWatcher::load], which
+/// records the new URL.
+pub fn redirect_policy() -> Policy {
+    Policy::custom(|attempt: Attempt| {
+        if is_permanent_redirect(attempt.status()) {
+            attempt.stop()
+        } else if attempt.previous().len() >= MAX_REDIRECTS {
+            attempt.error("too many redirects")
+        } else {
+            attempt.follow()
+        }
+    })
+}
+
+impl FeedWatcher {
+    /// Move the feed record if the loaded feed announced a new URL.
+    ///
+    /// Without `followMoves` in the feed settings, the new URL is only stored on the feed.
+    pub async fn apply_move(&self, db: &CouchDB) -> anyhow::Result<()> {
+        let (feed, to) = match (&self.feed_record, &self.moved_to) {
+            (Some(feed), Some(to)) => (feed, to.to_string()),
+            _ => return Ok(()),
+        };
+        if feed.value.has_url(&to) {
+            return Ok(());
+        }
+        if self.settings.follow_moves {
+            let report = move_feed(db, feed.clone(), &to).await?;
+            log::info!(
+                "Moved feed {} to {} ({} posts, {} medias)",
+                report.from,
+                report.to,
+                report.posts,
+                report.medias
+            );
+        } else if feed.value.moved_to.as_deref() != Some(&to) {
+            log::info!("Feed {} moved to {}, awaiting approval", feed.value.url, to);
+            let mut feed: Record<Feed> = db.get_record(feed.guid()).await?;
+            feed.value.moved_to = Some(to);
+            db.put_record(feed).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Move a feed to a new URL.
+///
+/// The feed record is stored under the ID of the new URL (merged into an existing feed
+/// record for that URL) with the old URLs as aliases, the posts and medias of the feed are
+/// updated to reference it and the old feed record is deleted. If a post or media cannot be
+/// updated, the old feed record is kept and the move can be retried.
+pub async fn move_feed(db: &CouchDB, feed: Record<Feed>, to: &str) -> anyhow::Result<MoveReport> {
+    let to = url::Url::parse(to).context("Invalid feed URL")?.to_string();
+    let new_id = id_from_hashed_string(&to);
+    if new_id == feed.id() {
+        anyhow::bail!("Feed {} is already at {}", feed.id(), to);
+    }
+    let old_guid = feed.guid().to_string();
+    let new_guid = Feed::guid(&new_id);
+
+    let mut aliases = feed.value.aliases.clone();
+    aliases.push(feed.value.url.clone());
+    let mut target = match db.get_record::<Feed>(&new_guid).await {
+        Ok(target) => target,
+        Err(_) => Record::from_id_and_value(
+            &new_id,
+            Feed {
+                url: to.clone(),
+                aliases: vec![],
+                moved_to: None,
+                ..feed.value.clone()
+            },
+        ),
+    };
+    for alias in aliases {
+        if alias != to && !target.value.aliases.contains(&alias) {
+            target.value.aliases.push(alias);
+        }
+    }
+    let mut report = MoveReport {
+        feed_id: new_id,
+        from: feed.value.url.clone(),
+        to,
+        ..Default::default()
+    };
+    db.put_record(target).await?;
+
+    let selector = json!({ "feeds": { "$elemMatch": { "$eq": old_guid } } });
+    let posts: Vec<Record<Post>> = db
+        .find_records::<Post>(&selector)
+        .await?
+        .into_iter()
+        .filter_map(|mut post| {
+            replace_ref(&mut post.value.feeds, &old_guid, &new_guid).then(|| post)
+        })
+        .collect();
+    let medias: Vec<Record<Media>> = db
+        .find_records::<Media>(&selector)
+        .await?
+        .into_iter()
+        .filter_map(|mut media| {
+            replace_ref(&mut media.value.feeds, &old_guid, &new_guid).then(|| media)
+        })
+        .collect();
+    let post_results = db.put_record_bulk_update(posts).await?;
+    let media_results = db.put_record_bulk_update(medias).await?;
+    report.posts = post_results.iter().filter(|r| r.as_err().is_none()).count();
+    report.medias = media_results
+        .iter()
+        .filter(|r| r.as_err().is_none())
+        .count();
+    let errors: Vec<_> = post_results
+        .iter()
+        .chain(media_results.iter())
+        .filter_map(PutResult::as_err)
+        .collect();
+    // Keep the old feed record so that the move can be retried.
+    if let Some(err) = errors.first() {
+        anyhow::bail!(
+            "Failed to move {} posts and medias of feed {} to {} (first error: {})",
+            errors.len(),
+            old_guid,
+            new_guid,
+            err
+        );
+    }
+    let show_guid = Show::guid(&id_from_hashed_string(feed.value.identity_scope()));
+    if let Ok(mut show) = db.get_record::<Show>(&show_guid).await {
+        if replace_ref(&mut show.value.feeds, &old_guid, &new_guid) {
+            db.put_record(show).await?;
+        }
+    }
+    db.delete_record(&old_guid).await?;
+    Ok(report)
+}
+
+/// Replace the references to a feed. Returns true if any reference was replaced.
+fn replace_ref(refs: &mut Vec<Reference<Feed>>, old_guid: &str, new_guid: &str) -> bool {
+    if !refs.iter().any(|r| r.guid() == old_guid) {
+        return false;
+    }
+    refs.retain(|r| r.guid() != old_guid && r.guid() != new_guid);
+    refs.push(Reference::Id(new_guid.to_string()));
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replace_feed_refs() {
+        let mut refs = vec![
+            Reference::Id("oas.Feed_old".to_string()),
+            Reference::Id("oas.Feed_other".to_string()),
+        ];
+        assert!(!replace_ref(&mut refs, "oas.Feed_missing", "oas.Feed_new"));
+        assert!(replace_ref(&mut refs, "oas.Feed_old", "oas.Feed_new"));
+        let guids: Vec<_> = refs.iter().map(|r| r.guid()).collect();
+        assert_eq!(guids, vec!["oas.Feed_other", "oas.Feed_new"]);
+
+        let mut feed = Feed::with_url("https://new.example/feed".into());
+        assert_eq!(feed.identity_scope(), "https://new.example/feed");
+        feed.aliases = vec!["https://old.example/feed".into()];
+        assert_eq!(feed.identity_scope(), "https://old.example/feed");
+        assert!(feed.has_url("https://old.example/feed"));
+    }
+}
@@ -0,0 +1,521 @@
+//! Harvest OAI-PMH repositories, e.g. of broadcast archives.
+//!
+//! An [OaiSource] record describes a repository (and optionally a set of it). A harvest
+//! requests `ListRecords` and follows the resumption tokens until the list is complete.
+//! The metadata of each record is collected by the qualified names of its elements (e.g.
+//! `dc:title`), mapped to a post (and a media, if `media.contentUrl` is mapped) with the
+//! selected mapping profile and saved like feed items (see [update](super::update)).
+//!
+//! After a complete harvest the response date is stored in `harvestedUntil` on the source,
+//! and the next harvest only requests records from that day on. Dates are requested with day
+//! granularity, which all repositories support.
+
+use anyhow::Context;
+use chrono::Utc;
+use clap::Parser;
+use oas_common::types::{OaiSource, Post};
+use oas_common::util::id_from_hashed_string;
+use oas_common::{Record, Reference, TypedValue, UntypedRecord};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use url::Url;
+
+use super::{apply_mapping, media_from_fields, post_from_fields, save_records, FieldMap};
+use crate::couch::CouchDB;
+
+/// OAI-PMH error code for an empty result, which is not an error for a harvest.
+const NO_RECORDS_MATCH: &str = "noRecordsMatch";
+
+#[derive(Parser, Debug)]
+pub struct AddSourceOpts {
+    /// Base URL of the OAI-PMH interface
+    pub url: String,
+    /// Only harvest the records of this set
+    #[clap(long)]
+    pub set: Option<String>,
+    /// Metadata format to harvest
+    #[clap(long)]
+    pub metadata_prefix: Option<String>,
+    /// Mapping profile for the records
+    #[clap(long)]
+    pub mapping: Option<String>,
+}
+
+#[derive(Parser, Debug, Serialize, Deserialize, Default, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HarvestOpts {
+    /// Harvest all records, not only those that changed since the last harvest
+    #[clap(long)]
+    #[serde(default)]
+    pub full: bool,
+    /// Update all records, even if they did not change
+    #[clap(short, long)]
+    #[serde(default)]
+    pub update: bool,
+    /// Max number of pages to harvest
+    #[clap(long)]
+    #[serde(default)]
+    pub max_pages: Option<usize>,
+}
+
+/// The result of a harvest.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HarvestReport {
+    pub source_id: String,
+    /// The date from which records were requested
+    pub from: Option<String>,
+    /// Whether all pages were harvested
+    pub complete: bool,
+    pub pages: usize,
+    /// Number of records in the harvested pages
+    pub records: usize,
+    /// Number of records that were deleted in the repository
+    pub deleted: usize,
+    /// Number of posts and medias that were created
+    pub created: usize,
+    /// Number of posts and medias that were updated
+    pub updated: usize,
+    /// Number of posts and medias that did not change
+    pub unchanged: usize,
+    /// Records that could not be mapped completely
+    pub warnings: Vec<String>,
+}
+
+/// A record of a `ListRecords` response.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OaiRecord {
+    pub identifier: String,
+    pub datestamp: Option<String>,
+    /// Whether the record was deleted in the repository
+    pub deleted: bool,
+    /// The text values of the metadata elements, by their qualified names
+    pub metadata: BTreeMap<String, Vec<String>>,
+}
+
+/// A page of a `ListRecords` response.
+#[derive(Debug, Clone, Default)]
+pub struct ListRecordsPage {
+    pub response_date: Option<String>,
+    pub records: Vec<OaiRecord>,
+    /// Token to request the next page. `None` if this is the last page.
+    pub resumption_token: Option<String>,
+}
+
+/// The URL of a `ListRecords` request.
+///
+/// Requests with a resumption token must not repeat the other arguments.
+pub fn list_records_url(
+    source: &OaiSource,
+    from: Option<&str>,
+    resumption_token: Option<&str>,
+) -> anyhow::Result<Url> {
+    let mut url = Url::parse(&source.url).context("Invalid OAI-PMH URL")?;
+    {
+        let mut query = url.query_pairs_mut();
+        query.append_pair("verb", "ListRecords");
+        match resumption_token {
+            Some(token) => {
+                query.append_pair("resumptionToken", token);
+            }
+            None => {
+                query.append_pair("metadataPrefix", &source.metadata_prefix);
+                if let Some(set) = &source.set {
+                    query.append_pair("set", set);
+                }
+                // Day granularity is supported by all repositories.
+                if let Some(from) = from.and_then(|from| from.get(..10)) {
+                    query.append_pair("from", from);
+                }
+            }
+        }
+    }
+    Ok(url)
+}
+
+/// Parse a `ListRecords` response.
+///
+/// An OAI-PMH error fails the parsing, except for `noRecordsMatch`, which yields an empty page.
+pub fn parse_list_records(xml: &str) -> anyhow::Result<ListRecordsPage> {
+    let mut reader = Reader::from_str(xml);
+    let mut buf = Vec::new();
+    let mut page = ListRecordsPage::default();
+    let mut path: Vec<String> = vec![];
+    let mut record: Option<OaiRecord> = None;
+    let mut metadata_depth: Option<usize> = None;
+    let mut error_code: Option<String> = None;
+    let mut text = String::new();
+    loop {
+        match reader.read_event(&mut buf)? {
+            Event::Start(ref el) => {
+                let name = local_name(el);
+                match name.as_str() {
+                    "record" => record = Some(OaiRecord::default()),
+                    "header" => {
+                        if let Some(record) = record.as_mut() {
+                            record.deleted =
+                                attribute(el, &reader, "status")?.as_deref() == Some("deleted");
+                        }
+                    }
+                    "metadata" if metadata_depth.is_none() => metadata_depth = Some(path.len()),
+                    "error" => error_code = attribute(el, &reader, "code")?,
+                    _ => {}
+                }
+                path.push(String::from_utf8_lossy(el.name()).to_string());
+                text.clear();
+            }
+            Event::Empty(ref el) => {
+                if local_name(el) == "error" {
+                    error_code = attribute(el, &reader, "code")?;
+                    check_error(error_code.as_deref(), "")?;
+                }
+            }
+            Event::Text(ref el) => text.push_str(&el.unescape_and_decode(&reader)?),
+            Event::CData(ref el) => text.push_str(&String::from_utf8_lossy(el)),
+            Event::End(_) => {
+                let name = path.pop().unwrap_or_default();
+                let local = name.rsplit(':').next().unwrap_or_default();
+                let value = text.trim().to_string();
+                let in_header = path.last().map(|p| p.ends_with("header")) == Some(true);
+                match (metadata_depth, record.as_mut()) {
+                    (Some(depth), _) if path.len() == depth => metadata_depth = None,
+                    (Some(_), Some(record)) => {
+                        if !value.is_empty() {
+                            record.metadata.entry(name).or_default().push(value);
+                        }
+                    }
+                    (None, Some(record)) if in_header && local == "identifier" => {
+                        record.identifier = value
+                    }
+                    (None, Some(record)) if in_header && local == "datestamp" => {
+                        record.datestamp = Some(value)
+                    }
+                    (None, Some(_)) if local == "record" => {
+                        page.records.extend(record.take());
+                    }
+                    (None, _) if local == "responseDate" => page.response_date = Some(value),
+                    (None, _) if local == "resumptionToken" && !value.is_empty() => {
+                        page.resumption_token = Some(value)
+                    }
+                    (None, _) if local == "error" => check_error(error_code.as_deref(), &value)?,
+                    _ => {}
+                }
+                text.clear();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(page)
+}
+
+fn check_error(code: Option<&str>, message: &str) -> anyhow::Result<()> {
+    match code {
+        Some(NO_RECORDS_MATCH) => Ok(()),
+        code => anyhow::bail!("OAI-PMH error {}: {}", code.unwrap_or("unknown"), message),
+    }
+}
+
+fn local_name(el: &BytesStart) -> String {
+    String::from_utf8_lossy(el.local_name()).to_string()
+}
+
+fn attribute(el: &BytesStart, reader: &Reader<&[u8]>, key: &str) -> anyhow::Result<Option<String>> {
+    for attr in el.attributes() {
+        let attr = attr?;
+        if attr.key == key.as_bytes() {
+            return Ok(Some(attr.unescape_and_decode_value(reader)?));
+        }
+    }
+    Ok(None)
+}
+
+/// Map an OAI-PMH record to a post and its media.
+///
+/// The ID of the post is derived from the OAI identifier, which is unique across
+/// repositories. Returns `None` for deleted records.
+pub fn record_into_post(
+    mapping: &FieldMap,
+    record: &OaiRecord,
+    warnings: &mut Vec<String>,
+) -> Option<Record<Post>> {
+    if record.deleted || record.identifier.is_empty() {
+        return None;
+    }
+    let id = id_from_hashed_string(&record.identifier);
+    let mapped_fields = apply_mapping(mapping, |from_key| {
+        record
+            .metadata
+            .get(from_key)
+            .map(|values| match &values[..] {
+                [value] => Value::String(value.clone()),
+                values => values.iter().cloned().map(Value::String).collect(),
+            })
+    });
+    let mut post = post_from_fields(&mapped_fields, &id, warnings);
+
+    let content_url = match mapped_fields.get("media.contentUrl") {
+        Some(Value::String(url)) => Some(url.clone()),
+        Some(Value::Array(urls)) => urls.iter().find_map(|url| url.as_str().map(String::from)),
+        _ => None,
+    };
+    if let Some(content_url) = content_url {
+        let mut media = media_from_fields(&mapped_fields, Default::default(), &id, warnings);
+        media.content_url = content_url;
+        let media = Record::from_id_and_value(id_from_hashed_string(&media.content_url), media);
+        post.media = vec![Reference::Resolved(media)];
+    }
+
+    post.identifier = Some(record.identifier.clone());
+    if post.url.is_none() {
+        // Use the first web identifier (that is not the media) as the URL of the post.
+        let media_urls: Vec<&str> = post.media.iter().filter_map(media_url).collect();
+        post.url = record
+            .metadata
+            .get("dc:identifier")
+            .into_iter()
+            .flatten()
+            .find(|id| {
+                (id.starts_with("http://") || id.starts_with("https://"))
+                    && !media_urls.contains(&id.as_str())
+            })
+            .cloned();
+    }
+    if post.date_modified.is_none() {
+        post.date_modified = record
+            .datestamp
+            .as_deref()
+            .and_then(|date| chrono::DateTime::parse_from_rfc3339(date).ok())
+            .map(|date| date.with_timezone(&Utc));
+    }
+    Some(Record::from_id_and_value(id, post))
+}
+
+fn media_url(media: &Reference<oas_common::types::Media>) -> Option<&str> {
+    media.record().map(|media| media.value.content_url.as_str())
+}
+
+/// Harvest an OAI-PMH source and save its records as posts and medias.
+///
+/// Only records that changed since the last complete harvest are requested, unless
+/// `opts.full` is set. The date of the harvest is saved on the source if all pages were
+/// harvested.
+pub async fn harvest(
+    client: &reqwest::Client,
+    mapping: &FieldMap,
+    db: &CouchDB,
+    source: &Record<OaiSource>,
+    opts: &HarvestOpts,
+) -> anyhow::Result<HarvestReport> {
+    let from = match opts.full {
+        true => None,
+        false => source.value.harvested_until.clone(),
+    };
+    let mut report = HarvestReport {
+        source_id: source.id().to_string(),
+        from: from.clone(),
+        ..Default::default()
+    };
+    let max_pages = opts.max_pages.unwrap_or(usize::MAX);
+    let mut response_date = None;
+    let mut token: Option<String> = None;
+    while report.pages < max_pages {
+        let url = list_records_url(&source.value, from.as_deref(), token.as_deref())?;
+        log::debug!("harvesting {}", url);
+        let xml = client
+            .get(url.as_str())
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let page = parse_list_records(&xml)
+            .with_context(|| format!("Invalid OAI-PMH response from {}", url))?;
+        report.pages += 1;
+        report.records += page.records.len();
+        if response_date.is_none() {
+            response_date = page.response_date.clone();
+        }
+
+        let mut records: Vec<UntypedRecord> = vec![];
+        for record in page.records.iter() {
+            if record.deleted {
+                report.deleted += 1;
+                continue;
+            }
+            let mut post = match record_into_post(mapping, record, &mut report.warnings) {
+                Some(post) => post,
+                None => continue,
+            };
+            assign_source(&source.value, &mut post);
+            records.append(&mut post.extract_refs());
+            records.push(post.into_untyped()?);
+        }
+        let saved = save_records(db, records, opts.update, &source.value.url).await?;
+        report.created += saved.created;
+        report.updated += saved.updated;
+        report.unchanged += saved.unchanged;
+
+        token = page.resumption_token;
+        if token.is_none() {
+            report.complete = true;
+            break;
+        }
+    }
+
+    for warning in report.warnings.iter() {
+        log::warn!("OAI source {}: {}", source.value.url, warning);
+    }
+    if report.complete {
+        let mut source: Record<OaiSource> = db.get_record(source.guid()).await?;
+        source.value.harvested_until =
+            response_date.or_else(|| Some(Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string()));
+        db.put_record(source).await?;
+    }
+    Ok(report)
+}
+
+/// Copy the job settings of the source to a post and its medias.
+fn assign_source(source: &OaiSource, post: &mut Record<Post>) {
+    if !source.post_jobs.is_empty() {
+        post.meta_mut().jobs_mut().copy_settings(&source.post_jobs);
+    }
+    let post_guid = post.guid().to_string();
+    for media in post.value.media.iter_mut().filter_map(|r| r.record_mut()) {
+        media.value.posts.push(Reference::Id(post_guid.clone()));
+        if !source.media_jobs.is_empty() {
+            media
+                .meta_mut()
+                .jobs_mut()
+                .copy_settings(&source.media_jobs);
+        }
+    }
+}
+
+/// Create an OAI-PMH source. Returns the existing source if it was created before.
+pub async fn add_source(db: &CouchDB, source: OaiSource) -> anyhow::Result<Record<OaiSource>> {
+    source
+        .validate()
+        .map_err(|err| anyhow::anyhow!("Invalid OAI source: {}", err))?;
+    let id = id_from_hashed_string(source.identity());
+    if let Ok(existing) = db.get_record::<OaiSource>(&OaiSource::guid(&id)).await {
+        return Ok(existing);
+    }
+    let record = Record::from_id_and_value(id, source);
+    db.put_record(record.clone()).await?;
+    Ok(record)
+}
+
+/// Get an OAI-PMH source by ID or URL.
+pub async fn get_source(db: &CouchDB, id_or_url: &str) -> anyhow::Result<Record<OaiSource>> {
+    match db.get_record(&OaiSource::guid(id_or_url)).await {
+        Ok(source) => Ok(source),
+        Err(err) => {
+            let sources = db.get_all_records::<OaiSource>().await?;
+            sources
+                .into_iter()
+                .find(|source| source.value.url == id_or_url)
+                .ok_or_else(|| err.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rss::mapping::MappingProfiles;
+
+    const PAGE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<OAI-PMH xmlns="http://www.openarchives.org/OAI/2.0/">
+  <responseDate>2021-11-02T10:00:00Z</responseDate>
+  <request verb="ListRecords" metadataPrefix="oai_dc">https://archive.example/oai</request>
+  <ListRecords>
+    <record>
+      <header>
+        <identifier>oai:archive.example:1</identifier>
+        <datestamp>2021-10-01T08:00:00Z</datestamp>
+        <setSpec>radio</setSpec>
+      </header>
+      <metadata>
+        <oai_dc:dc xmlns:oai_dc="http://www.openarchives.org/OAI/2.0/oai_dc/"
+                   xmlns:dc="http://purl.org/dc/elements/1.1/">
+          <dc:title>Evening News &amp; Weather</dc:title>
+          <dc:creator>Jane</dc:creator>
+          <dc:creator>John</dc:creator>
+          <dc:date>2001-05-04</dc:date>
+          <dc:language>de</dc:language>
+          <dc:identifier>https://archive.example/item/1</dc:identifier>
+          <dc:identifier>https://archive.example/media/1.mp3</dc:identifier>
+        </oai_dc:dc>
+      </metadata>
+    </record>
+    <record>
+      <header status="deleted">
+        <identifier>oai:archive.example:2</identifier>
+        <datestamp>2021-10-02</datestamp>
+      </header>
+    </record>
+    <resumptionToken completeListSize="3" cursor="0">token-1</resumptionToken>
+  </ListRecords>
+</OAI-PMH>"#;
+
+    #[test]
+    fn parse_and_map_records() {
+        let page = parse_list_records(PAGE).unwrap();
+        assert_eq!(page.response_date.as_deref(), Some("2021-11-02T10:00:00Z"));
+        assert_eq!(page.resumption_token.as_deref(), Some("token-1"));
+        assert_eq!(page.records.len(), 2);
+        let record = &page.records[0];
+        assert_eq!(record.identifier, "oai:archive.example:1");
+        assert_eq!(record.metadata["dc:creator"], vec!["Jane", "John"]);
+        assert!(page.records[1].deleted);
+
+        let source = OaiSource::with_url("https://archive.example/oai".into());
+        let mapping = MappingProfiles::default().select_oai(&source);
+        let mut warnings = vec![];
+        let post = record_into_post(&mapping, record, &mut warnings).unwrap();
+        assert!(warnings.is_empty());
+        assert!(record_into_post(&mapping, &page.records[1], &mut warnings).is_none());
+        let post = post.value;
+        assert_eq!(post.headline.as_deref(), Some("Evening News & Weather"));
+        assert_eq!(post.creator, vec!["Jane", "John"]);
+        assert_eq!(post.in_language.as_deref(), Some("de"));
+        assert_eq!(post.url.as_deref(), Some("https://archive.example/item/1"));
+        assert_eq!(
+            post.date_published.unwrap().to_rfc3339(),
+            "2001-05-04T00:00:00+00:00"
+        );
+        let media = post.media[0].record().unwrap();
+        assert_eq!(
+            media.value.content_url,
+            "https://archive.example/media/1.mp3"
+        );
+    }
+
+    #[test]
+    fn request_urls() {
+        let mut source = OaiSource::with_url("https://archive.example/oai".into());
+        source.set = Some("radio".into());
+        let url = list_records_url(&source, Some("2021-11-02T10:00:00Z"), None).unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://archive.example/oai?verb=ListRecords&metadataPrefix=oai_dc&set=radio&from=2021-11-02"
+        );
+        let url = list_records_url(&source, None, Some("a b")).unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://archive.example/oai?verb=ListRecords&resumptionToken=a+b"
+        );
+
+        let empty = r#"<OAI-PMH><error code="noRecordsMatch">No records</error></OAI-PMH>"#;
+        let page = parse_list_records(empty).unwrap();
+        assert!(page.records.is_empty() && page.resumption_token.is_none());
+        let error = r#"<OAI-PMH><error code="badResumptionToken">Expired</error></OAI-PMH>"#;
+        assert!(parse_list_records(error).is_err());
+    }
+}
@@ -0,0 +1,276 @@
+//! OPML import and export of feed subscriptions.
+//!
+//! Each feed is an `<outline type="rss">` element with the feed URL in `xmlUrl`. The feed
+//! settings and jobs are encoded as JSON in the custom attributes `oas:settings`,
+//! `oas:mediaJobs` and `oas:postJobs`, so that an export can be imported into another
+//! instance without losing them. Outlines may be nested (e.g. in categories).
+
+use clap::Parser;
+use oas_common::types::Feed;
+use oas_common::util::id_from_hashed_string;
+use oas_common::{Record, TypedValue};
+use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::Cursor;
+use std::path::PathBuf;
+use thiserror::Error;
+
+use crate::couch::CouchDB;
+
+const OAS_NAMESPACE: &str = "https://openaudiosearch.org/ns/opml";
+const ATTR_SETTINGS: &str = "oas:settings";
+const ATTR_MEDIA_JOBS: &str = "oas:mediaJobs";
+const ATTR_POST_JOBS: &str = "oas:postJobs";
+
+#[derive(Parser, Debug)]
+pub struct ImportOpts {
+    /// OPML file to import
+    pub file: PathBuf,
+    /// Only report which feeds would be created
+    #[clap(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct ExportOpts {
+    /// Write to file instead of stdout
+    #[clap(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+/// The result of an OPML import.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    /// URLs of feeds that were created (or would be created in a dry run)
+    pub created: Vec<String>,
+    /// URLs of feeds that exist already
+    pub existing: Vec<String>,
+    /// Outlines that could not be imported, with the reason
+    pub invalid: Vec<String>,
+    /// Feeds that could not be saved, with the reason
+    pub failed: Vec<String>,
+}
+
+/// The error returned by [import] if the document is not valid OPML.
+#[derive(Error, Debug)]
+#[error("Invalid OPML: {0}")]
+pub struct InvalidOpml(pub String);
+
+/// Parse the feeds from an OPML document.
+///
+/// Returns the feeds and a list of errors for outlines that are not valid feeds.
+pub fn parse_opml(xml: &str) -> anyhow::Result<(Vec<Feed>, Vec<String>)> {
+    let mut reader = Reader::from_str(xml);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    let mut feeds = vec![];
+    let mut invalid = vec![];
+    loop {
+        match reader.read_event(&mut buf)? {
+            Event::Start(ref el) | Event::Empty(ref el) if el.name() == b"outline" => {
+                let mut url = None;
+                let mut feed = Feed::default();
+                let mut err = None;
+                for attr in el.attributes() {
+                    let attr = attr?;
+                    let value = attr.unescape_and_decode_value(&reader)?;
+                    let key = String::from_utf8_lossy(attr.key);
+                    let res = match key.as_ref() {
+                        "xmlUrl" => {
+                            url = Some(value);
+                            Ok(())
+                        }
+                        ATTR_SETTINGS => serde_json::from_str(&value)
+                            .map(|settings| feed.settings = Some(settings)),
+                        ATTR_MEDIA_JOBS => {
+                            serde_json::from_str(&value).map(|jobs| feed.media_jobs = jobs)
+                        }
+                        ATTR_POST_JOBS => {
+                            serde_json::from_str(&value).map(|jobs| feed.post_jobs = jobs)
+                        }
+                        _ => Ok(()),
+                    };
+                    if let Err(e) = res {
+                        err = Some(format!("invalid attribute {}: {}", key, e));
+                    }
+                }
+                // Outlines without xmlUrl are categories.
+                if let Some(url) = url {
+                    feed.url = url;
+                    match (err, feed.validate()) {
+                        (Some(err), _) => invalid.push(format!("{}: {}", feed.url, err)),
+                        (None, Err(err)) => invalid.push(format!("{}: {}", feed.url, err)),
+                        (None, Ok(())) => feeds.push(feed),
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok((feeds, invalid))
+}
+
+/// Write feeds as an OPML document.
+pub fn write_opml(feeds: &[Feed]) -> anyhow::Result<String> {
+    let mut writer = Writer::new_with_indent(Cursor::new(Vec::new()), b' ', 2);
+    writer.write_event(Event::Decl(BytesDecl::new(b"1.0", Some(b"UTF-8"), None)))?;
+    let opml = BytesStart::borrowed_name(b"opml")
+        .with_attributes(vec![("version", "2.0"), ("xmlns:oas", OAS_NAMESPACE)]);
+    writer.write_event(Event::Start(opml))?;
+    writer.write_event(Event::Start(BytesStart::borrowed_name(b"head")))?;
+    writer.write_event(Event::Start(BytesStart::borrowed_name(b"title")))?;
+    writer.write_event(Event::Text(BytesText::from_plain_str(
+        "Open Audio Search feeds",
+    )))?;
+    writer.write_event(Event::End(BytesEnd::borrowed(b"title")))?;
+    writer.write_event(Event::End(BytesEnd::borrowed(b"head")))?;
+    writer.write_event(Event::Start(BytesStart::borrowed_name(b"body")))?;
+    for feed in feeds {
+        let mut outline = BytesStart::borrowed_name(b"outline");
+        outline.push_attribute(("type", "rss"));
+        outline.push_attribute(("text", feed.url.as_str()));
+        outline.push_attribute(("xmlUrl", feed.url.as_str()));
+        if let Some(settings) = &feed.settings {
+            let value = serde_json::to_string(settings)?;
+            outline.push_attribute((ATTR_SETTINGS, value.as_str()));
+        }
+        if !feed.media_jobs.is_empty() {
+            let value = serde_json::to_string(&feed.media_jobs)?;
+            outline.push_attribute((ATTR_MEDIA_JOBS, value.as_str()));
+        }
+        if !feed.post_jobs.is_empty() {
+            let value = serde_json::to_string(&feed.post_jobs)?;
+            outline.push_attribute((ATTR_POST_JOBS, value.as_str()));
+        }
+        writer.write_event(Event::Empty(outline))?;
+    }
+    writer.write_event(Event::End(BytesEnd::borrowed(b"body")))?;
+    writer.write_event(Event::End(BytesEnd::borrowed(b"opml")))?;
+    let xml = String::from_utf8(writer.into_inner().into_inner())?;
+    Ok(xml)
+}
+
+/// Import the feeds from an OPML document.
+///
+/// Feeds are identified by the hash of their URL. Feeds that exist already (or appear twice
+/// in the document) are not changed. In a dry run, nothing is written to the database.
+/// Fails with [InvalidOpml] if the document cannot be parsed.
+pub async fn import(db: &CouchDB, xml: &str, dry_run: bool) -> anyhow::Result<ImportReport> {
+    let (feeds, invalid) = parse_opml(xml).map_err(|err| InvalidOpml(err.to_string()))?;
+    let mut report = import_feeds(db, feeds, dry_run).await?;
+    report.invalid = invalid;
+    Ok(report)
+}
+
+/// Create feed records for all feeds that do not exist yet.
+///
+/// Feeds whose URL is an alias of an existing feed (because that feed moved) exist already.
+pub async fn import_feeds(
+    db: &CouchDB,
+    feeds: Vec<Feed>,
+    dry_run: bool,
+) -> anyhow::Result<ImportReport> {
+    let mut report = ImportReport::default();
+
+    let mut seen = HashSet::new();
+    let records: Vec<_> = feeds
+        .into_iter()
+        .filter(|feed| seen.insert(feed.url.clone()))
+        .map(|feed| Record::from_id_and_value(id_from_hashed_string(&feed.url), feed))
+        .collect();
+    let guids: Vec<&str> = records.iter().map(|record| record.guid()).collect();
+    let existing: HashSet<_> = db
+        .get_many_records::<Feed>(&guids)
+        .await?
+        .into_iter()
+        .map(|record| record.id().to_string())
+        .collect();
+    let aliases: HashSet<_> = db
+        .get_all_records::<Feed>()
+        .await?
+        .into_iter()
+        .flat_map(|record| record.value.aliases)
+        .collect();
+
+    let (existing, new): (Vec<_>, Vec<_>) = records
+        .into_iter()
+        .partition(|record| existing.contains(record.id()) || aliases.contains(&record.value.url));
+    report.existing = existing.into_iter().map(|r| r.value.url).collect();
+    let urls: Vec<_> = new.iter().map(|r| r.value.url.clone()).collect();
+    if dry_run || new.is_empty() {
+        report.created = urls;
+        return Ok(report);
+    }
+    let results = db.put_record_bulk(new).await?;
+    for (url, result) in urls.into_iter().zip(results.iter()) {
+        match result.as_err() {
+            None => report.created.push(url),
+            Some(err) => report.failed.push(format!("{}: {}", url, err)),
+        }
+    }
+    Ok(report)
+}
+
+/// Export all feeds as an OPML document.
+pub async fn export(db: &CouchDB) -> anyhow::Result<String> {
+    let mut feeds: Vec<Feed> = db
+        .get_all_records::<Feed>()
+        .await?
+        .into_iter()
+        .map(|record| record.value)
+        .collect();
+    feeds.sort_by(|a, b| a.url.cmp(&b.url));
+    write_opml(&feeds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oas_common::types::FeedSettings;
+
+    #[test]
+    fn roundtrip() {
+        let mut feed = Feed::with_url("https://example.org/feed?a=1&b=2".into());
+        feed.settings = Some(FeedSettings {
+            crawl_backwards: true,
+            ..Default::default()
+        });
+        feed.media_jobs.insert("asr".into(), None);
+        let xml = write_opml(&[feed, Feed::with_url("https://example.com/rss".into())]).unwrap();
+        let (feeds, invalid) = parse_opml(&xml).unwrap();
+        assert!(invalid.is_empty());
+        assert_eq!(feeds.len(), 2);
+        assert_eq!(feeds[0].url, "https://example.org/feed?a=1&b=2");
+        assert!(feeds[0].settings.as_ref().unwrap().crawl_backwards);
+        assert!(feeds[0].media_jobs.contains_key("asr"));
+        assert!(feeds[1].settings.is_none());
+    }
+
+    #[test]
+    fn nested_outlines() {
+        let xml = r#"<?xml version="1.0"?>
+<opml version="1.0">
+  <head><title>Subscriptions</title></head>
+  <body>
+    <outline text="Radio">
+      <outline type="rss" text="One" xmlUrl="https://one.example/feed"/>
+      <outline type="rss" text="Broken" xmlUrl="not a url"/>
+    </outline>
+    <outline type="rss" text="Two" xmlUrl="https://two.example/feed"></outline>
+  </body>
+</opml>"#;
+        let (feeds, invalid) = parse_opml(xml).unwrap();
+        let urls: Vec<_> = feeds.iter().map(|feed| feed.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            vec!["https://one.example/feed", "https://two.example/feed"]
+        );
+        assert_eq!(invalid.len(), 1);
+    }
+}
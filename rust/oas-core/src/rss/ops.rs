@@ -1,9 +1,12 @@
 use clap::Parser;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::time::Instant;
 use url::Url;
 
-use super::crawlers::default_crawlers;
+use super::crawlers::CrawlRules;
+use super::mapping::MappingProfiles;
+use super::scheduler::HostLimiter;
 use super::*;
 
 pub enum Next {
@@ -24,10 +27,13 @@ pub trait Crawler: Send + Sync {
 #[derive(Parser, Debug)]
 pub struct FetchOpts {
     /// Feed URL
-    url: Url,
+    pub url: Url,
     /// Force update
     #[clap(short, long)]
-    update: bool,
+    pub update: bool,
+    /// Only print the mapped posts and medias
+    #[clap(long)]
+    pub dry_run: bool,
 }
 
 #[derive(Parser, Debug, Serialize, Deserialize)]
@@ -68,19 +74,26 @@ impl CrawlOpts {
     }
 }
 
-pub async fn crawl_and_save(db: &CouchDB, opts: &CrawlOpts) -> RssResult<()> {
+/// Crawl a paginated feed with the first crawl rule that matches its domain.
+pub async fn crawl_and_save(
+    client: &reqwest::Client,
+    rules: &CrawlRules,
+    profiles: &MappingProfiles,
+    db: &CouchDB,
+    opts: &CrawlOpts,
+) -> RssResult<()> {
     let url = &opts.url;
-    let crawlers = default_crawlers();
-
-    let domain = url
-        .domain()
+    let rule = rules
+        .find(url)
         .ok_or_else(|| RssError::MissingCrawlRule(url.to_string()))?;
-    for crawler in crawlers.into_iter() {
-        if crawler.domains().contains(&domain.to_string()) {
-            return crawler_loop(db, opts, &*crawler).await;
-        }
-    }
-    Err(RssError::MissingCrawlRule(domain.to_string()))
+    log::debug!("crawling {} with rule {}", url, rule.name);
+    let mapping = profiles.select(url.as_str(), None);
+    let watcher = FeedWatcher::with_client(client.clone(), url, None, mapping, None)?;
+    let max_pages = match (opts.max_pages, rule.stop.max_pages) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+    crawler_loop(db, &watcher, opts.update, max_pages, rule, None).await
 }
 
 #[derive(Debug, Clone)]
@@ -89,35 +102,50 @@ pub struct FetchedFeedPage {
     pub items: Vec<UntypedRecord>,
     pub feed: FeedWatcher,
     pub put_result: Vec<PutResult>,
+    /// Whether any of the items was saved before
+    pub contains_existing: bool,
 }
 
+/// Fetch and save the pages of a feed, starting with the URL of `watcher`.
+///
+/// Each page is fetched with a copy of `watcher`, so all pages share its settings, mapping
+/// and item identity scope. The crawl stops if the crawler returns a page that was fetched
+/// already. If `hosts` is set, the pages after the first page wait for the
+/// per-host limit (the caller is expected to have waited for the first page).
 pub async fn crawler_loop(
     db: &CouchDB,
-    opts: &CrawlOpts,
-    crawler: &dyn Crawler, // crawler: T,
+    watcher: &FeedWatcher,
+    update: bool,
+    max_pages: Option<usize>,
+    crawler: &dyn Crawler,
+    hosts: Option<&HostLimiter>,
 ) -> RssResult<()> {
-    let client = reqwest::Client::new();
-    let mut url = opts.url.clone();
+    let mut url = watcher.url().clone();
+    let mut visited = HashSet::new();
     let mut total = 0;
-    let max_pages = opts.max_pages.unwrap_or(usize::MAX);
+    let max_pages = max_pages.unwrap_or(usize::MAX);
     let start = Instant::now();
-    for _i in 0..max_pages {
+    for i in 0..max_pages {
+        if let Some(hosts) = hosts.filter(|_| i > 0) {
+            hosts.wait(url.host_str().unwrap_or_default()).await;
+        }
+        visited.insert(url.clone());
         log::debug!("fetching {}", url);
-        let feed_page = fetch_and_save_with_client(client.clone(), db, &url, opts.update).await?;
-
-        // Check if the batch put to db contained any errors.
-        // An error should occur when putting an existing ID
-        // (i.e. existing URL).
-        // TODO: Actually check the error.
-        if !opts.update {
-            let contains_existing = feed_page.put_result.iter().find_map(|result| match result {
-                PutResult::Err(err) => Some(err),
-                _ => None,
-            });
-            if let Some(err) = contains_existing {
-                log::debug!("breaking crawl loop on {}", err);
-                break;
-            }
+        let mut feed = watcher.for_page(&url);
+        feed.load().await?;
+        let report = feed.save(db, update).await?;
+        let feed_page = FetchedFeedPage {
+            url: feed.url.clone(),
+            feed,
+            contains_existing: report.contains_existing(),
+            items: report.records,
+            put_result: report.put_result,
+        };
+
+        // Stop at the first page with items that were saved before.
+        if !update && feed_page.contains_existing {
+            log::debug!("breaking crawl loop on existing items");
+            break;
         }
 
         log::debug!(
@@ -133,7 +161,11 @@ pub async fn crawler_loop(
                 log::debug!("breaking crawl loop: finished");
                 break;
             }
-            Next::NextPage(url) => url,
+            Next::NextPage(next) if visited.contains(&next) => {
+                log::debug!("breaking crawl loop: {} was fetched already", next);
+                break;
+            }
+            Next::NextPage(next) => next,
         };
     }
     let duration = start.elapsed();
@@ -148,33 +180,22 @@ pub async fn crawler_loop(
     Ok(())
 }
 
-pub async fn fetch_and_save_with_client(
-    client: reqwest::Client,
+pub async fn fetch_and_save(
+    client: &reqwest::Client,
+    profiles: &MappingProfiles,
     db: &CouchDB,
-    url: &Url,
-    update: bool,
+    opts: &FetchOpts,
 ) -> RssResult<FetchedFeedPage> {
-    let mut feed = FeedWatcher::with_client(client, &url, None, Default::default(), None).unwrap();
-    feed.load().await?;
-    let (put_result, records) = feed.save(db, update).await?;
-    let feed_page = FetchedFeedPage {
-        url: feed.url.clone(),
-        feed,
-        items: records,
-        put_result,
-    };
-    Ok(feed_page)
-}
-
-pub async fn fetch_and_save(db: &CouchDB, opts: &FetchOpts) -> RssResult<FetchedFeedPage> {
-    let mut feed = FeedWatcher::new(&opts.url, None, Default::default(), None).unwrap();
+    let mapping = profiles.select(opts.url.as_str(), None);
+    let mut feed = FeedWatcher::with_client(client.clone(), &opts.url, None, mapping, None)?;
     feed.load().await?;
-    let (put_result, records) = feed.save(db, opts.update).await?;
+    let report = feed.save(db, opts.update).await?;
     let feed_page = FetchedFeedPage {
         url: feed.url.clone(),
         feed,
-        items: records,
-        put_result,
+        contains_existing: report.contains_existing(),
+        items: report.records,
+        put_result: report.put_result,
     };
     Ok(feed_page)
 }
@@ -0,0 +1,459 @@
+//! Support for the [Podcasting 2.0](https://podcastindex.org/namespace/1.0) namespace.
+//!
+//! Parses `podcast:transcript`, `podcast:chapters` and `podcast:person` tags from RSS items.
+//! Transcripts and chapters are linked documents that are fetched separately and converted
+//! into the [Transcript] and [Segment] types used throughout OAS.
+
+use oas_common::types::{Media, Segment, Transcript, TranscriptPart};
+use rss::extension::Extension;
+use serde::Deserialize;
+use serde_json::json;
+
+use super::RssError;
+
+/// Namespace prefix of the Podcasting 2.0 namespace.
+pub const PREFIX: &str = "podcast";
+
+/// Segment type for chapters.
+pub const SEGMENT_CHAPTER: &str = "chapter";
+/// Segment type for speaker turns (same as used by the ASR worker).
+pub const SEGMENT_SPEAKER: &str = "speaker";
+
+/// Transcript formats that carry timing information, in order of preference.
+const TRANSCRIPT_FORMATS: &[(&str, TranscriptFormat)] = &[
+    ("application/json", TranscriptFormat::Json),
+    ("text/vtt", TranscriptFormat::Vtt),
+    ("application/x-subrip", TranscriptFormat::Srt),
+    ("application/srt", TranscriptFormat::Srt),
+    ("text/srt", TranscriptFormat::Srt),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TranscriptFormat {
+    Json,
+    Vtt,
+    Srt,
+}
+
+impl TranscriptFormat {
+    pub fn from_mime_type(mime_type: &str) -> Option<Self> {
+        let mime_type = mime_type.split(';').next().unwrap_or_default().trim();
+        TRANSCRIPT_FORMATS
+            .iter()
+            .find(|(typ, _)| typ.eq_ignore_ascii_case(mime_type))
+            .map(|(_, format)| *format)
+    }
+
+    fn priority(&self) -> usize {
+        TRANSCRIPT_FORMATS
+            .iter()
+            .position(|(_, format)| format == self)
+            .unwrap_or(usize::MAX)
+    }
+}
+
+/// A `podcast:transcript` link.
+#[derive(Debug, Clone)]
+pub struct TranscriptLink {
+    pub url: String,
+    pub mime_type: String,
+    pub language: Option<String>,
+}
+
+/// A `podcast:person` tag.
+#[derive(Debug, Clone)]
+pub struct Person {
+    pub name: String,
+    pub role: Option<String>,
+    pub group: Option<String>,
+}
+
+/// Podcasting 2.0 metadata of a single RSS item.
+#[derive(Debug, Clone, Default)]
+pub struct PodcastItem {
+    pub transcripts: Vec<TranscriptLink>,
+    pub chapters: Option<String>,
+    pub persons: Vec<Person>,
+}
+
+impl PodcastItem {
+    pub fn from_item(item: &rss::Item) -> Self {
+        let ext = match item.extensions().get(PREFIX) {
+            Some(ext) => ext,
+            None => return Self::default(),
+        };
+        let tags = |name: &str| ext.get(name).map(|tags| tags.iter()).into_iter().flatten();
+
+        let transcripts = tags("transcript")
+            .filter_map(|tag| {
+                Some(TranscriptLink {
+                    url: attr(tag, "url")?,
+                    mime_type: attr(tag, "type")?,
+                    language: attr(tag, "language"),
+                })
+            })
+            .collect();
+        let chapters = tags("chapters").find_map(|tag| attr(tag, "url"));
+        let persons = tags("person")
+            .filter_map(|tag| {
+                let name = tag.value()?.trim();
+                if name.is_empty() {
+                    return None;
+                }
+                Some(Person {
+                    name: name.to_string(),
+                    role: attr(tag, "role"),
+                    group: attr(tag, "group"),
+                })
+            })
+            .collect();
+
+        Self {
+            transcripts,
+            chapters,
+            persons,
+        }
+    }
+
+    /// Returns true if the item links to documents that have to be fetched.
+    pub fn has_resources(&self) -> bool {
+        self.chapters.is_some() || self.best_transcript().is_some()
+    }
+
+    /// The transcript link with the best supported format, if any.
+    pub fn best_transcript(&self) -> Option<(&TranscriptLink, TranscriptFormat)> {
+        self.transcripts
+            .iter()
+            .filter_map(|link| {
+                TranscriptFormat::from_mime_type(&link.mime_type).map(|format| (link, format))
+            })
+            .min_by_key(|(_link, format)| format.priority())
+    }
+
+    /// Fetch the linked transcript and chapters and set them on the media.
+    ///
+    /// Values that are already set on the media are not overwritten.
+    pub async fn load_into_media(
+        &self,
+        client: &reqwest::Client,
+        media: &mut Media,
+    ) -> Result<(), RssError> {
+        if media.transcript.is_none() {
+            if let Some((link, format)) = self.best_transcript() {
+                let body = fetch_text(client, &link.url).await?;
+                let mut transcript = parse_transcript(&body, format)?;
+                transcript.meta.insert(
+                    "source".into(),
+                    json!({
+                        "engine": "podcast:transcript",
+                        "url": link.url,
+                        "type": link.mime_type,
+                        "language": link.language,
+                    }),
+                );
+                if !transcript.parts.is_empty() {
+                    media.transcript = Some(transcript);
+                }
+            }
+        }
+        if media.chapters.is_empty() {
+            if let Some(url) = &self.chapters {
+                let body = fetch_text(client, url).await?;
+                media.chapters = parse_chapters(&body, media.duration)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn attr(tag: &Extension, name: &str) -> Option<String> {
+    tag.attrs().get(name).map(|value| value.to_string())
+}
+
+async fn fetch_text(client: &reqwest::Client, url: &str) -> Result<String, RssError> {
+    let res = client.get(url).send().await?;
+    if !res.status().is_success() {
+        return Err(RssError::RemoteHttpError(Box::new(res)));
+    }
+    Ok(res.text().await?)
+}
+
+/// Parse a transcript document in one of the supported formats.
+pub fn parse_transcript(body: &str, format: TranscriptFormat) -> Result<Transcript, RssError> {
+    let cues = match format {
+        TranscriptFormat::Json => parse_json_cues(body)?,
+        TranscriptFormat::Vtt | TranscriptFormat::Srt => parse_cues(body),
+    };
+    Ok(cues_into_transcript(cues))
+}
+
+/// A timed block of text in a transcript.
+#[derive(Debug, Clone, PartialEq)]
+struct Cue {
+    start: f32,
+    end: f32,
+    text: String,
+    speaker: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JsonTranscript {
+    segments: Vec<JsonTranscriptSegment>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonTranscriptSegment {
+    start_time: f32,
+    end_time: f32,
+    body: String,
+    speaker: Option<String>,
+}
+
+fn parse_json_cues(body: &str) -> Result<Vec<Cue>, RssError> {
+    let transcript: JsonTranscript = serde_json::from_str(body)?;
+    let cues = transcript
+        .segments
+        .into_iter()
+        .map(|segment| Cue {
+            start: segment.start_time,
+            end: segment.end_time,
+            text: segment.body,
+            speaker: segment.speaker,
+        })
+        .collect();
+    Ok(cues)
+}
+
+/// Parse WebVTT and SRT documents. Both consist of blank-line separated blocks
+/// with a `start --> end` timing line followed by the cue text.
+fn parse_cues(body: &str) -> Vec<Cue> {
+    let body = body.replace("\r\n", "\n");
+    let mut cues = vec![];
+    for block in body.split("\n\n") {
+        let mut lines = block.lines().skip_while(|line| !line.contains("-->"));
+        let timing = match lines.next() {
+            Some(timing) => timing,
+            None => continue,
+        };
+        let mut times = timing.split("-->");
+        let start = times.next().and_then(parse_timestamp);
+        // WebVTT allows cue settings after the end timestamp.
+        let end = times
+            .next()
+            .and_then(|s| s.split_whitespace().next())
+            .and_then(parse_timestamp);
+        let (start, end) = match (start, end) {
+            (Some(start), Some(end)) => (start, end),
+            _ => continue,
+        };
+
+        let mut speaker = None;
+        let mut text = vec![];
+        for line in lines {
+            if speaker.is_none() {
+                speaker = voice_tag(line);
+            }
+            text.push(strip_tags(line));
+        }
+        cues.push(Cue {
+            start,
+            end,
+            text: text.join(" "),
+            speaker,
+        });
+    }
+    cues
+}
+
+/// Parse a timestamp in the form `hh:mm:ss.mmm`, `mm:ss.mmm` or `hh:mm:ss,mmm` into seconds.
+fn parse_timestamp(s: &str) -> Option<f32> {
+    let s = s.trim().replace(',', ".");
+    let mut secs = 0.;
+    for part in s.split(':') {
+        secs = secs * 60. + part.parse::<f32>().ok()?;
+    }
+    Some(secs)
+}
+
+/// Extract the speaker name from a WebVTT voice tag (`<v Speaker>`).
+fn voice_tag(line: &str) -> Option<String> {
+    let start = line.find("<v ")? + 3;
+    let end = start + line[start..].find('>')?;
+    let name = line[start..end].trim();
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.to_string())
+    }
+}
+
+fn strip_tags(line: &str) -> String {
+    let mut text = String::with_capacity(line.len());
+    let mut in_tag = false;
+    for c in line.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.trim().to_string()
+}
+
+/// Convert cues into a transcript. Cues only have timings for the whole block of text,
+/// so the word timings are interpolated evenly across the duration of the cue.
+fn cues_into_transcript(cues: Vec<Cue>) -> Transcript {
+    let mut transcript = Transcript::default();
+    let mut texts = vec![];
+    let mut speaker_segment: Option<Segment> = None;
+    for cue in cues.into_iter() {
+        let words: Vec<&str> = cue.text.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+        let step = (cue.end - cue.start).max(0.) / words.len() as f32;
+        for (i, word) in words.iter().enumerate() {
+            transcript.parts.push(TranscriptPart {
+                conf: 1.0,
+                start: cue.start + step * i as f32,
+                end: cue.start + step * (i + 1) as f32,
+                word: word.to_string(),
+                suffix: None,
+            });
+        }
+        texts.push(words.join(" "));
+
+        // Merge consecutive cues of the same speaker into one segment.
+        if let Some(speaker) = cue.speaker {
+            let speaker = serde_json::Value::String(speaker);
+            match speaker_segment.as_mut() {
+                Some(segment) if segment.data.as_ref() == Some(&speaker) => {
+                    segment.end = cue.end;
+                }
+                _ => {
+                    if let Some(segment) = speaker_segment.take() {
+                        transcript.segments.push(segment);
+                    }
+                    speaker_segment = Some(Segment {
+                        start: cue.start,
+                        end: cue.end,
+                        typ: SEGMENT_SPEAKER.to_string(),
+                        data: Some(speaker),
+                    });
+                }
+            }
+        }
+    }
+    if let Some(segment) = speaker_segment.take() {
+        transcript.segments.push(segment);
+    }
+    transcript.text = texts.join(" ");
+    transcript
+}
+
+#[derive(Deserialize)]
+struct JsonChapters {
+    chapters: Vec<JsonChapter>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonChapter {
+    start_time: f32,
+    end_time: Option<f32>,
+    title: Option<String>,
+    img: Option<String>,
+    url: Option<String>,
+    #[serde(default = "default_true")]
+    toc: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Parse a JSON chapters document into chapter segments.
+///
+/// Chapters without an explicit end time end where the next chapter starts, or at the end
+/// of the media.
+pub fn parse_chapters(body: &str, duration: Option<f32>) -> Result<Vec<Segment>, RssError> {
+    let mut chapters: JsonChapters = serde_json::from_str(body)?;
+    chapters
+        .chapters
+        .sort_by(|a, b| a.start_time.partial_cmp(&b.start_time).unwrap());
+    let starts: Vec<f32> = chapters.chapters.iter().map(|c| c.start_time).collect();
+    let segments = chapters
+        .chapters
+        .into_iter()
+        .enumerate()
+        .filter(|(_i, chapter)| chapter.toc)
+        .map(|(i, chapter)| {
+            let end = chapter
+                .end_time
+                .or_else(|| starts.get(i + 1).copied())
+                .or(duration)
+                .unwrap_or(chapter.start_time);
+            Segment {
+                start: chapter.start_time,
+                end,
+                typ: SEGMENT_CHAPTER.to_string(),
+                data: Some(json!({
+                    "title": chapter.title,
+                    "img": chapter.img,
+                    "url": chapter.url,
+                })),
+            }
+        })
+        .collect();
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_vtt() {
+        let body = "WEBVTT\n\n1\n00:00:00.000 --> 00:00:02.000\n<v Alice>Hello there</v>\n\n00:00:02.000 --> 00:00:04.000 align:start\n<v Alice>and welcome.\n\n00:04.000 --> 00:06.000\n<v Bob>Thanks!\n";
+        let transcript = parse_transcript(body, TranscriptFormat::Vtt).unwrap();
+        assert_eq!(transcript.text, "Hello there and welcome. Thanks!");
+        assert_eq!(transcript.parts.len(), 5);
+        assert_eq!(transcript.parts[1].word, "there");
+        assert_eq!(transcript.parts[1].start, 1.0);
+        assert_eq!(transcript.parts[1].end, 2.0);
+        assert_eq!(transcript.segments.len(), 2);
+        assert_eq!(transcript.segments[0].end, 4.0);
+        assert_eq!(transcript.segments[1].data, Some(json!("Bob")));
+    }
+
+    #[test]
+    fn parse_srt() {
+        let body = "1\r\n00:00:01,500 --> 00:00:03,500\r\nFirst line\r\nsecond line\r\n\r\n2\r\n01:00:00,000 --> 01:00:01,000\r\nEnd\r\n";
+        let transcript = parse_transcript(body, TranscriptFormat::Srt).unwrap();
+        assert_eq!(transcript.text, "First line second line End");
+        assert_eq!(transcript.parts[0].start, 1.5);
+        assert_eq!(transcript.parts[4].start, 3600.);
+        assert!(transcript.segments.is_empty());
+    }
+
+    #[test]
+    fn parse_json_transcript() {
+        let body = r#"{"version":"1.0.0","segments":[{"speaker":"Alice","startTime":0.5,"endTime":1.5,"body":"Hi"}]}"#;
+        let transcript = parse_transcript(body, TranscriptFormat::Json).unwrap();
+        assert_eq!(transcript.text, "Hi");
+        assert_eq!(transcript.parts[0].end, 1.5);
+        assert_eq!(transcript.segments[0].typ, SEGMENT_SPEAKER);
+    }
+
+    #[test]
+    fn parse_json_chapters() {
+        let body = r#"{"version":"1.2.0","chapters":[{"startTime":10,"title":"Second"},{"startTime":0,"title":"Intro"},{"startTime":20,"title":"Hidden","toc":false}]}"#;
+        let chapters = parse_chapters(body, Some(30.)).unwrap();
+        assert_eq!(chapters.len(), 2);
+        assert_eq!(chapters[0].end, 10.);
+        assert_eq!(chapters[1].end, 20.);
+        assert_eq!(chapters[1].data.as_ref().unwrap()["title"], "Second");
+    }
+}
@@ -0,0 +1,157 @@
+//! Preview how a feed is mapped without writing anything to the database.
+
+use oas_common::types::{Post, Show};
+use oas_common::{Record, Reference};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use super::{podcast, FeedWatcher, FieldMap, RssError};
+use crate::types::FeedSettings;
+
+/// The request body of a feed preview.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewRequest {
+    /// Feed URL
+    pub url: String,
+    /// Feed settings to use instead of the settings of an existing feed
+    #[serde(default)]
+    pub settings: Option<FeedSettings>,
+}
+
+/// The posts and medias that fetching a feed would create.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FeedPreview {
+    pub url: String,
+    /// Name of the selected mapping profile
+    pub mapping: Option<String>,
+    /// The show mapped from the channel of the feed
+    pub show: Record<Show>,
+    /// Number of items in the feed
+    pub items: usize,
+    /// Number of items that were skipped
+    pub skipped: usize,
+    /// Number of medias of all posts
+    pub medias: usize,
+    /// Posts with their medias
+    pub posts: Vec<Record<Post>>,
+    /// Extensions of the feed items that are not mapped, with the number of items they
+    /// appear in
+    pub unmapped: BTreeMap<String, usize>,
+    /// Items that could not be mapped completely
+    pub warnings: Vec<String>,
+}
+
+impl FeedWatcher {
+    /// Map the items of the loaded feed without saving them.
+    pub fn preview(&self, mapping: Option<String>) -> Result<FeedPreview, RssError> {
+        let mut warnings = vec![];
+        let posts = self.map_items(&mut warnings)?;
+        let mut posts: Vec<Record<Post>> = posts.into_iter().map(|(post, _podcast)| post).collect();
+        let show = self.to_show()?;
+        for post in posts.iter_mut() {
+            self.assign_feed(post);
+            post.value.shows = vec![Reference::Id(show.guid().to_string())];
+        }
+
+        let mut unmapped = BTreeMap::new();
+        if let Some(channel) = &self.channel {
+            for item in channel.items() {
+                for key in unmapped_extensions(item.extensions(), &self.mapping) {
+                    *unmapped.entry(key).or_insert(0) += 1;
+                }
+            }
+        }
+
+        let items = self.item_count();
+        Ok(FeedPreview {
+            url: self.url.to_string(),
+            mapping,
+            show,
+            items,
+            skipped: items - posts.len(),
+            medias: posts.iter().map(|post| post.value.media.len()).sum(),
+            posts,
+            unmapped,
+            warnings,
+        })
+    }
+}
+
+/// Get the keys (e.g. `cba:teaser`) of the extensions that are not in the mapping.
+///
+/// Podcast namespace extensions are skipped because they are handled separately.
+fn unmapped_extensions(
+    extensions: &rss::extension::ExtensionMap,
+    mapping: &FieldMap,
+) -> Vec<String> {
+    extensions
+        .iter()
+        .filter(|(prefix, _)| prefix.as_str() != podcast::PREFIX)
+        .flat_map(|(prefix, inner)| inner.keys().map(move |name| format!("{}:{}", prefix, name)))
+        .filter(|key| !mapping.contains_key(key))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oas_common::types::MappingField;
+    use rss::Channel;
+
+    const FEED: &str = r#"<?xml version="1.0"?>
+<rss version="2.0" xmlns:cba="https://cba.media/ns" xmlns:podcast="https://podcastindex.org/namespace/1.0">
+  <channel>
+    <title>Test</title>
+    <link>https://example.org</link>
+    <description>Test</description>
+    <item>
+      <title>One</title>
+      <guid>one</guid>
+      <cba:teaser>A teaser</cba:teaser>
+      <cba:broadcast>123</cba:broadcast>
+      <podcast:season>1</podcast:season>
+      <enclosure url="https://example.org/one.mp3" length="100" type="audio/mpeg"/>
+    </item>
+    <item>
+      <title>Two</title>
+      <guid>two</guid>
+      <cba:broadcast>456</cba:broadcast>
+    </item>
+    <item>
+      <title>No identity</title>
+    </item>
+  </channel>
+</rss>"#;
+
+    #[test]
+    fn preview() {
+        let mut mapping = FieldMap::new();
+        mapping.insert(
+            "cba:teaser".into(),
+            MappingField {
+                from: "cba:teaser".into(),
+                to: "abstract".into(),
+                transforms: vec![],
+            },
+        );
+        let mut watcher =
+            FeedWatcher::new("https://example.org/feed", None, mapping, None).unwrap();
+        watcher.channel = Some(Channel::read_from(FEED.as_bytes()).unwrap());
+
+        let preview = watcher.preview(Some("test".into())).unwrap();
+        assert_eq!(preview.items, 3);
+        assert_eq!(preview.skipped, 1);
+        assert_eq!(preview.medias, 1);
+        assert_eq!(preview.posts.len(), 2);
+        assert_eq!(
+            preview.posts[0].value.r#abstract.as_deref(),
+            Some("A teaser")
+        );
+        assert_eq!(preview.unmapped.len(), 1);
+        assert_eq!(preview.unmapped.get("cba:broadcast"), Some(&2));
+        assert_eq!(preview.warnings.len(), 1);
+    }
+}
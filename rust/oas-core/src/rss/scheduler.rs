@@ -0,0 +1,420 @@
+//! Scheduler for feed fetches.
+//!
+//! The [FeedManager](super::FeedManager) keeps one entry per feed, keyed by the feed id. A single
+//! scheduler loop takes due feeds from a queue ordered by their next fetch time and fetches
+//! them, while limiting the number of concurrent fetches and the request rate per host.
+//! Entries are replaced when their feed changes and removed when the feed is deleted.
+
+use chrono::{DateTime, Utc};
+use oas_common::types::Feed;
+use oas_common::TypedRecord;
+use rand::Rng;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{AcquireError, Mutex, Notify, Semaphore, SemaphorePermit};
+use tokio::time::Instant;
+
+use super::crawlers::CrawlRules;
+use super::mapping::FieldMap;
+use super::ops::crawler_loop;
+use super::websub::WebSub;
+use super::FeedWatcher;
+use crate::couch::CouchDB;
+
+/// Default number of feeds that are fetched concurrently.
+pub const DEFAULT_CONCURRENCY: usize = 8;
+/// Default minimal delay between two requests to the same host (in seconds).
+pub const DEFAULT_HOST_DELAY: u64 = 2;
+/// Default timeout for feed requests (in seconds).
+pub const DEFAULT_TIMEOUT: u64 = 30;
+/// Default user agent for feed requests.
+pub const DEFAULT_USER_AGENT: &str = concat!("openaudiosearch/", env!("CARGO_PKG_VERSION"));
+
+/// Fraction of the check interval that is randomly added to or subtracted from the next fetch
+/// time, so that feeds added at the same time are not fetched at the same time.
+const JITTER: f64 = 0.1;
+
+/// Options for the scheduler.
+#[derive(Debug, Clone)]
+pub struct SchedulerOpts {
+    /// Max number of concurrent fetches.
+    pub concurrency: usize,
+    /// Minimal delay between two requests to the same host.
+    pub host_delay: Duration,
+}
+
+impl Default for SchedulerOpts {
+    fn default() -> Self {
+        Self {
+            concurrency: DEFAULT_CONCURRENCY,
+            host_delay: Duration::from_secs(DEFAULT_HOST_DELAY),
+        }
+    }
+}
+
+/// The state of a feed watcher.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum WatcherState {
+    /// The feed is waiting for its next fetch.
+    Scheduled,
+    /// The feed is being fetched.
+    Fetching,
+    /// The feed is paused in its settings and not fetched.
+    Paused,
+}
+
+/// Public information about a feed watcher.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WatcherInfo {
+    pub feed_id: String,
+    pub url: String,
+    pub state: WatcherState,
+    pub started_at: DateTime<Utc>,
+    /// Current check interval in seconds
+    pub check_interval: u64,
+    pub next_fetch: Option<DateTime<Utc>>,
+    pub last_fetch: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+/// A scheduled feed.
+#[derive(Debug)]
+struct Entry {
+    feed: TypedRecord<Feed>,
+    info: WatcherInfo,
+    /// The watcher is taken out of the entry while it is being fetched.
+    watcher: Option<FeedWatcher>,
+    /// The field mapping of the watcher.
+    mapping: FieldMap,
+    /// Incremented whenever the entry is replaced, to discard stale queue items and fetches.
+    generation: u64,
+    /// Set once the feed was fetched, to crawl older pages only on the first fetch.
+    fetched: bool,
+}
+
+/// A due fetch taken from the queue.
+struct Due {
+    id: String,
+    generation: u64,
+    watcher: FeedWatcher,
+    /// Crawl all pages instead of fetching only the feed URL.
+    crawl: bool,
+}
+
+type QueueItem = Reverse<(Instant, u64, String)>;
+
+#[derive(Debug, Default)]
+struct SchedulerInner {
+    entries: HashMap<String, Entry>,
+    queue: BinaryHeap<QueueItem>,
+    generation: u64,
+}
+
+impl SchedulerInner {
+    fn schedule(&mut self, id: &str, at: Instant) {
+        if let Some(entry) = self.entries.get_mut(id) {
+            let delay = at.saturating_duration_since(Instant::now());
+            entry.info.state = WatcherState::Scheduled;
+            entry.info.next_fetch = chrono::Duration::from_std(delay)
+                .ok()
+                .map(|delay| Utc::now() + delay);
+            self.queue
+                .push(Reverse((at, entry.generation, id.to_string())));
+        }
+    }
+
+    /// Pop the next due entry from the queue, or return the time until the next entry is due.
+    fn pop_due(&mut self) -> Result<Due, Option<Instant>> {
+        while let Some(Reverse((at, generation, id))) = self.queue.peek().cloned() {
+            let entry = match self.entries.get_mut(&id) {
+                Some(entry) if entry.generation == generation && entry.watcher.is_some() => entry,
+                _ => {
+                    self.queue.pop();
+                    continue;
+                }
+            };
+            if at > Instant::now() {
+                return Err(Some(at));
+            }
+            self.queue.pop();
+            entry.info.state = WatcherState::Fetching;
+            entry.info.next_fetch = None;
+            let crawl = !entry.fetched
+                && entry
+                    .feed
+                    .value
+                    .settings
+                    .as_ref()
+                    .map(|settings| settings.crawl_backwards)
+                    .unwrap_or_default();
+            entry.fetched = true;
+            let watcher = entry.watcher.take().unwrap();
+            return Ok(Due {
+                id,
+                generation,
+                watcher,
+                crawl,
+            });
+        }
+        Err(None)
+    }
+}
+
+/// The feed fetch scheduler.
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    inner: Arc<Mutex<SchedulerInner>>,
+    notify: Arc<Notify>,
+    semaphore: Arc<Semaphore>,
+    hosts: HostLimiter,
+}
+
+impl Scheduler {
+    pub fn new(opts: SchedulerOpts) -> Self {
+        Self {
+            inner: Default::default(),
+            notify: Default::default(),
+            semaphore: Arc::new(Semaphore::new(opts.concurrency.max(1))),
+            hosts: HostLimiter::new(opts.host_delay),
+        }
+    }
+
+    /// Add, replace or pause the entry for a feed.
+    ///
+    /// If an entry with the same feed and mapping is registered already, nothing happens.
+    /// New feeds are fetched after a random delay within the jitter range of their interval.
+    pub async fn upsert(&self, feed: TypedRecord<Feed>, watcher: FeedWatcher) {
+        let mut inner = self.inner.lock().await;
+        let id = feed.id().to_string();
+        if let Some(existing) = inner.entries.get(&id) {
+            if feed_eq(&existing.feed, &feed) && &existing.mapping == watcher.mapping() {
+                return;
+            }
+            log::debug!("Feed {} [{}] changed, reschedule", id, feed.value.url);
+        }
+
+        let paused = feed
+            .value
+            .settings
+            .as_ref()
+            .map(|settings| settings.paused)
+            .unwrap_or_default();
+        let interval = watcher.check_interval();
+        // Keep the fetch state, so that changed feeds do not crawl older pages again.
+        let (fetched, last_fetch) = inner
+            .entries
+            .get(&id)
+            .map(|entry| (entry.fetched, entry.info.last_fetch))
+            .unwrap_or_default();
+        let info = WatcherInfo {
+            feed_id: id.clone(),
+            url: feed.value.url.clone(),
+            state: WatcherState::Paused,
+            started_at: Utc::now(),
+            check_interval: interval.as_secs(),
+            next_fetch: None,
+            last_fetch,
+            last_error: None,
+        };
+        inner.generation += 1;
+        let entry = Entry {
+            feed,
+            info,
+            mapping: watcher.mapping().clone(),
+            watcher: Some(watcher),
+            generation: inner.generation,
+            fetched,
+        };
+        // Inserting invalidates queued items and running fetches of the previous entry.
+        inner.entries.insert(id.clone(), entry);
+        if paused {
+            log::debug!("Feed {} is paused", id);
+        } else {
+            let delay = interval.mul_f64(rand::thread_rng().gen_range(0.0..JITTER));
+            inner.schedule(&id, Instant::now() + delay);
+            self.notify.notify_one();
+        }
+    }
+
+    /// Remove the entry for a feed.
+    pub async fn remove(&self, id: &str) {
+        if self.inner.lock().await.entries.remove(id).is_some() {
+            log::debug!("Stopped watching deleted feed {}", id);
+        }
+    }
+
+    /// Get information about the watcher of a feed.
+    pub async fn info(&self, id: &str) -> Option<WatcherInfo> {
+        let inner = self.inner.lock().await;
+        inner.entries.get(id).map(|entry| entry.info.clone())
+    }
+
+    /// Get information about all watched feeds.
+    pub async fn infos(&self) -> Vec<WatcherInfo> {
+        let inner = self.inner.lock().await;
+        inner
+            .entries
+            .values()
+            .map(|entry| entry.info.clone())
+            .collect()
+    }
+
+    /// Run the scheduler loop. This runs forever.
+    ///
+    /// Feeds with `crawl_backwards` set in their settings are crawled with the matching rule
+    /// from `rules` on their first fetch. If `websub` is set, feeds that advertise a hub are
+    /// subscribed there after they were fetched.
+    pub async fn run(&self, db: CouchDB, rules: CrawlRules, websub: Option<WebSub>) {
+        let rules = Arc::new(rules);
+        loop {
+            let next = self.inner.lock().await.pop_due();
+            match next {
+                Ok(due) => {
+                    let this = self.clone();
+                    let db = db.clone();
+                    let rules = rules.clone();
+                    let websub = websub.clone();
+                    tokio::spawn(async move { this.fetch(db, &rules, websub, due).await });
+                }
+                Err(Some(at)) => {
+                    tokio::select! {
+                        _ = tokio::time::sleep_until(at) => {}
+                        _ = self.notify.notified() => {}
+                    }
+                }
+                Err(None) => self.notify.notified().await,
+            }
+        }
+    }
+
+    async fn fetch(&self, db: CouchDB, rules: &CrawlRules, websub: Option<WebSub>, due: Due) {
+        let Due {
+            id,
+            generation,
+            mut watcher,
+            crawl,
+        } = due;
+        let _permit = self
+            .acquire(watcher.url().host_str().unwrap_or_default())
+            .await;
+
+        let rule = if crawl {
+            rules.find(watcher.url())
+        } else {
+            None
+        };
+        if crawl && rule.is_none() {
+            log::warn!("No crawl rule found for feed {}", watcher.url());
+        }
+        let res = match rule {
+            Some(rule) => {
+                log::info!("Crawl feed {} with rule {}", watcher.url(), rule.name);
+                let hosts = Some(&self.hosts);
+                crawler_loop(&db, &watcher, false, rule.stop.max_pages, rule, hosts).await
+            }
+            None => match watcher.load().await {
+                Ok(()) => watcher.save(&db, false).await.map(|_| ()),
+                Err(err) => Err(err),
+            },
+        };
+        if let Err(err) = watcher.apply_move(&db).await {
+            log::warn!("Failed to move feed {}: {}", watcher.url(), err);
+        }
+        if let (Ok(()), Some(websub)) = (&res, &websub) {
+            websub.ensure(&id, &watcher).await;
+        }
+
+        let interval = watcher.check_interval();
+        let mut inner = self.inner.lock().await;
+        let entry = match inner.entries.get_mut(&id) {
+            Some(entry) if entry.generation == generation => entry,
+            // The feed was changed or deleted in the meantime.
+            _ => return,
+        };
+        entry.info.last_fetch = Some(Utc::now());
+        entry.info.check_interval = interval.as_secs();
+        match res {
+            Ok(()) => entry.info.last_error = None,
+            Err(err) => {
+                log::warn!("Failed to fetch feed {}: {}", watcher.url(), err);
+                entry.info.last_error = Some(err.to_string());
+            }
+        }
+        entry.watcher = Some(watcher);
+        let jitter = rand::thread_rng().gen_range(-JITTER..JITTER);
+        let next = Instant::now() + interval.mul_f64(1.0 + jitter);
+        inner.schedule(&id, next);
+        self.notify.notify_one();
+    }
+
+    /// Acquire a fetch permit and a request slot for a host.
+    ///
+    /// The permit is not held while waiting for the host, so that the feeds of one host do
+    /// not keep the feeds of other hosts from being fetched.
+    async fn acquire(&self, host: &str) -> Result<SemaphorePermit<'_>, AcquireError> {
+        loop {
+            self.hosts.ready(host).await;
+            let permit = self.semaphore.acquire().await?;
+            // Another fetch may have taken the slot while waiting for the permit.
+            if self.hosts.try_claim(host).await.is_ok() {
+                return Ok(permit);
+            }
+        }
+    }
+}
+
+/// Limits the request rate per host.
+#[derive(Debug, Clone)]
+pub struct HostLimiter {
+    /// The time from which the next request to a host is allowed.
+    hosts: Arc<Mutex<HashMap<String, Instant>>>,
+    delay: Duration,
+}
+
+impl HostLimiter {
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            hosts: Default::default(),
+            delay,
+        }
+    }
+
+    /// Wait until a request to a host is allowed and claim it.
+    pub async fn wait(&self, host: &str) {
+        while let Err(at) = self.try_claim(host).await {
+            tokio::time::sleep_until(at).await;
+        }
+    }
+
+    /// Wait until a request to a host is allowed, without claiming it.
+    async fn ready(&self, host: &str) {
+        let at = self.hosts.lock().await.get(host).copied();
+        if let Some(at) = at {
+            tokio::time::sleep_until(at).await;
+        }
+    }
+
+    /// Claim a request to a host if it is allowed now, or return when it will be allowed.
+    async fn try_claim(&self, host: &str) -> Result<(), Instant> {
+        let mut hosts = self.hosts.lock().await;
+        let now = Instant::now();
+        match hosts.get(host) {
+            Some(at) if *at > now => Err(*at),
+            _ => {
+                hosts.insert(host.to_string(), now + self.delay);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Compare the parts of two feeds that are relevant for a watcher.
+fn feed_eq(a: &TypedRecord<Feed>, b: &TypedRecord<Feed>) -> bool {
+    serde_json::to_value(&a.value).ok() == serde_json::to_value(&b.value).ok()
+}
@@ -0,0 +1,135 @@
+//! Map the channel of a feed to a [Show].
+//!
+//! The show of a feed is saved together with the items of the feed and is updated whenever
+//! the channel metadata changes (see [update](super::update)).
+
+use oas_common::types::Show;
+use oas_common::util::id_from_hashed_string;
+use oas_common::{Record, Reference, TypedValue};
+use rss::Channel;
+
+use super::{parse_explicit, FeedWatcher, RssError};
+
+impl FeedWatcher {
+    /// The ID of the show of the feed.
+    ///
+    /// Derived from the original URL of the feed, so that the show is kept when the feed moves.
+    pub fn show_id(&self) -> String {
+        id_from_hashed_string(self.identity_scope())
+    }
+
+    /// The GUID of the show of the feed.
+    pub fn show_guid(&self) -> String {
+        Show::guid(&self.show_id())
+    }
+
+    /// Map the channel of the loaded feed to a show.
+    pub fn to_show(&self) -> Result<Record<Show>, RssError> {
+        let channel = self.channel.as_ref().ok_or(RssError::NoChannel)?;
+        let mut show = channel_into_show(channel);
+        if let Some(feed) = &self.feed_record {
+            show.feeds = vec![Reference::Id(feed.guid().to_string())];
+        }
+        Ok(Record::from_id_and_value(self.show_id(), show))
+    }
+}
+
+fn channel_into_show(channel: &Channel) -> Show {
+    let itunes_ext = channel.itunes_ext();
+    let mut categories: Vec<String> = vec![];
+    let itunes_categories = itunes_ext
+        .map(|ext| ext.categories())
+        .unwrap_or_default()
+        .iter()
+        .flat_map(|category| {
+            std::iter::once(category.text()).chain(category.subcategory().map(|sub| sub.text()))
+        });
+    let rss_categories = channel.categories().iter().map(|category| category.name());
+    for category in rss_categories.chain(itunes_categories) {
+        let category = category.trim();
+        if !category.is_empty() && !categories.iter().any(|c| c == category) {
+            categories.push(category.to_string());
+        }
+    }
+
+    Show {
+        title: non_empty(channel.title()),
+        description: non_empty(channel.description())
+            .or_else(|| itunes_ext.and_then(|ext| ext.summary()).and_then(non_empty)),
+        image: itunes_ext
+            .and_then(|ext| ext.image())
+            .or_else(|| channel.image().map(|image| image.url()))
+            .and_then(non_empty),
+        in_language: channel.language().and_then(non_empty),
+        author: itunes_ext
+            .and_then(|ext| ext.author())
+            .or_else(|| channel.managing_editor())
+            .and_then(non_empty),
+        url: non_empty(channel.link()),
+        categories,
+        explicit: itunes_ext
+            .and_then(|ext| ext.explicit())
+            .and_then(parse_explicit),
+        feeds: vec![],
+    }
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    let value = value.trim();
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rss::mapping::FieldMap;
+
+    const FEED: &str = r#"<?xml version="1.0"?>
+<rss version="2.0" xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd">
+  <channel>
+    <title>Radio Show</title>
+    <link>https://example.org/show</link>
+    <description> </description>
+    <language>de</language>
+    <category>Culture</category>
+    <itunes:summary>All about radio</itunes:summary>
+    <itunes:author>Jane</itunes:author>
+    <itunes:explicit>clean</itunes:explicit>
+    <itunes:image href="https://example.org/show.jpg"/>
+    <itunes:category text="Arts">
+      <itunes:category text="Culture"/>
+    </itunes:category>
+    <item>
+      <title>One</title>
+      <guid>one</guid>
+    </item>
+  </channel>
+</rss>"#;
+
+    #[test]
+    fn channel_to_show() {
+        let mut watcher =
+            FeedWatcher::new("https://example.org/feed", None, FieldMap::new(), None).unwrap();
+        watcher.load_from_bytes(FEED.as_bytes()).unwrap();
+        let show = watcher.to_show().unwrap();
+        assert_eq!(show.id(), id_from_hashed_string("https://example.org/feed"));
+
+        let show = show.value;
+        assert_eq!(show.title.as_deref(), Some("Radio Show"));
+        assert_eq!(show.description.as_deref(), Some("All about radio"));
+        assert_eq!(show.image.as_deref(), Some("https://example.org/show.jpg"));
+        assert_eq!(show.in_language.as_deref(), Some("de"));
+        assert_eq!(show.author.as_deref(), Some("Jane"));
+        assert_eq!(show.url.as_deref(), Some("https://example.org/show"));
+        assert_eq!(show.categories, vec!["Culture", "Arts"]);
+        assert_eq!(show.explicit, Some(false));
+
+        let posts = watcher.to_post_and_media_records().unwrap();
+        let post: Record<oas_common::types::Post> = posts[0].clone().into_typed().unwrap();
+        assert_eq!(post.value.shows[0].guid(), watcher.show_guid());
+    }
+}
@@ -0,0 +1,219 @@
+//! Detect and apply changes of feed items that were saved before.
+//!
+//! When a post or media is mapped from a feed item, the hash of its value is stored in the
+//! record metadata. If a later fetch of the feed yields a different hash, the publisher
+//! changed the item and the mapped fields are written onto the existing record. Fields that
+//! are set by jobs are kept, as are the job settings in the record metadata. Edits to a
+//! record are not overwritten until the item changes in the feed.
+
+use oas_common::util::{id_from_hashed_string, sorted_json};
+use oas_common::UntypedRecord;
+use serde_json::Value;
+
+/// Fields that are set by jobs and are kept when an item is updated.
+const PRESERVED_FIELDS: &[&str] = &["transcript", "nlp", "chapters"];
+
+/// Reference lists that are merged when an item is updated, so that records that appear
+/// in several feeds keep all references.
+const MERGED_REFS: &[&str] = &["feeds", "posts", "shows"];
+
+/// How a mapped feed item relates to the record that is saved already.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemChange {
+    New,
+    Changed,
+    Unchanged,
+    /// The item is unchanged, but metadata that is derived from it is missing on the saved
+    /// record (e.g. records saved before the metadata was introduced).
+    Backfill,
+}
+
+/// Hash the value of a record. Object keys are sorted, so the hash does not depend on
+/// the order of the fields.
+///
+/// Merged reference lists and preserved fields are not hashed: they differ between the feeds
+/// that contain an item, and preserved fields are also filled by requests that may fail.
+pub fn source_hash(record: &UntypedRecord) -> String {
+    let mut value = record.value().clone();
+    for key in MERGED_REFS.iter().chain(PRESERVED_FIELDS.iter()) {
+        value.remove(*key);
+    }
+    let value = sorted_json(Value::Object(value));
+    id_from_hashed_string(value.to_string())
+}
+
+/// Store the hash of the record value in the record metadata.
+pub fn set_source_hash(record: &mut UntypedRecord) {
+    let hash = source_hash(record);
+    record.meta_mut().set_source_hash(Some(hash));
+}
+
+/// Compare a mapped record (with its source hash set) to the saved record.
+///
+/// A saved record without a source hash is never reported as changed, only its metadata
+/// is backfilled.
+pub fn detect_change(existing: Option<&UntypedRecord>, record: &UntypedRecord) -> ItemChange {
+    match existing {
+        None => ItemChange::New,
+        // Records saved before source hashes were introduced cannot be compared, so their
+        // values are kept.
+        Some(existing) if existing.meta().source_hash().is_none() => ItemChange::Backfill,
+        Some(existing) if existing.meta().source_hash() == record.meta().source_hash() => {
+            if existing.meta().item_keys().is_none() && record.meta().item_keys().is_some() {
+                ItemChange::Backfill
+            } else {
+                ItemChange::Unchanged
+            }
+        }
+        Some(_) => ItemChange::Changed,
+    }
+}
+
+/// Write the fields of a mapped record onto the saved record.
+///
+/// Preserved fields keep their saved value unless it is empty, reference lists are merged
+/// and the metadata (including job settings) of the saved record is kept.
+pub fn merge(existing: UntypedRecord, record: UntypedRecord) -> UntypedRecord {
+    let mut merged = backfill(existing, &record);
+    for (key, value) in record.value().iter() {
+        let saved = merged.value_mut().get_mut(key);
+        match saved {
+            Some(saved) if PRESERVED_FIELDS.contains(&key.as_str()) && !saved.is_null() => {}
+            Some(Value::Array(saved)) if MERGED_REFS.contains(&key.as_str()) => {
+                if let Value::Array(refs) = value {
+                    for reference in refs {
+                        if !saved.contains(reference) {
+                            saved.push(reference.clone());
+                        }
+                    }
+                }
+            }
+            _ => {
+                merged.value_mut().insert(key.clone(), value.clone());
+            }
+        }
+    }
+    merged
+}
+
+/// Copy the metadata that is derived from the feed item onto the saved record, keeping its
+/// value.
+pub fn backfill(existing: UntypedRecord, record: &UntypedRecord) -> UntypedRecord {
+    let mut existing = existing;
+    let meta = existing.meta_mut();
+    meta.set_source_hash(record.meta().source_hash().map(|hash| hash.to_string()));
+    meta.set_item_keys(record.meta().item_keys().cloned());
+    existing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oas_common::types::{ItemKeys, Post};
+    use oas_common::{Record, Reference};
+
+    fn post(headline: &str) -> UntypedRecord {
+        let post = Post {
+            headline: Some(headline.into()),
+            feeds: vec![Reference::Id("oas.Feed_a".into())],
+            ..Default::default()
+        };
+        let mut record = Record::from_id_and_value("1", post).into_untyped().unwrap();
+        set_source_hash(&mut record);
+        record.meta_mut().set_item_keys(Some(ItemKeys {
+            guid: Some(headline.into()),
+            ..Default::default()
+        }));
+        record
+    }
+
+    #[test]
+    fn update_changed_item() {
+        let mut existing = post("Old");
+        existing
+            .value_mut()
+            .insert("nlp".into(), serde_json::json!({ "keywords": ["radio"] }));
+        existing
+            .value_mut()
+            .insert("feeds".into(), serde_json::json!(["oas.Feed_b"]));
+        existing
+            .meta_mut()
+            .jobs_mut()
+            .settings_mut()
+            .insert("nlp".into(), None);
+
+        assert_eq!(detect_change(None, &post("Old")), ItemChange::New);
+        assert_eq!(
+            detect_change(Some(&existing), &post("Old")),
+            ItemChange::Unchanged
+        );
+        let record = post("New");
+        assert_eq!(detect_change(Some(&existing), &record), ItemChange::Changed);
+
+        let merged = merge(existing, record.clone());
+        assert_eq!(merged.meta().source_hash(), record.meta().source_hash());
+        assert_eq!(merged.meta().item_keys(), record.meta().item_keys());
+        assert!(merged.meta().jobs().settings().contains_key("nlp"));
+        let merged: Record<Post> = merged.into_typed().unwrap();
+        assert_eq!(merged.value.headline.as_deref(), Some("New"));
+        assert_eq!(merged.value.nlp["keywords"][0], "radio");
+        assert_eq!(merged.value.feeds.len(), 2);
+    }
+
+    #[test]
+    fn backfill_item_keys() {
+        let mut existing = post("Old");
+        existing.meta_mut().set_item_keys(None);
+        existing
+            .value_mut()
+            .insert("nlp".into(), serde_json::json!({ "keywords": ["radio"] }));
+        let record = post("Old");
+        assert_eq!(
+            detect_change(Some(&existing), &record),
+            ItemChange::Backfill
+        );
+
+        let backfilled = backfill(existing.clone(), &record);
+        assert_eq!(backfilled.meta().item_keys(), record.meta().item_keys());
+        assert_eq!(backfilled.value(), existing.value());
+    }
+
+    #[test]
+    fn backfill_source_hash() {
+        let mut existing = post("Old");
+        existing.meta_mut().set_source_hash(None);
+        let record = post("New");
+        assert_eq!(
+            detect_change(Some(&existing), &record),
+            ItemChange::Backfill
+        );
+
+        let backfilled = backfill(existing.clone(), &record);
+        assert_eq!(backfilled.meta().source_hash(), record.meta().source_hash());
+        assert_eq!(backfilled.value(), existing.value());
+    }
+
+    #[test]
+    fn hash_ignores_feeds() {
+        let a = post("A");
+        let mut b = post("A");
+        b.value_mut()
+            .insert("feeds".into(), serde_json::json!(["oas.Feed_b"]));
+        b.value_mut()
+            .insert("transcript".into(), serde_json::json!({ "text": "hello" }));
+        assert_eq!(source_hash(&a), source_hash(&b));
+        set_source_hash(&mut b);
+        assert_eq!(detect_change(Some(&a), &b), ItemChange::Unchanged);
+    }
+
+    #[test]
+    fn hash_ignores_key_order() {
+        let mut a = post("A");
+        let mut b = post("A");
+        a.value_mut()
+            .insert("x".into(), serde_json::json!({"a": 1, "b": 2}));
+        b.value_mut()
+            .insert("x".into(), serde_json::json!({"b": 2, "a": 1}));
+        assert_eq!(source_hash(&a), source_hash(&b));
+    }
+}
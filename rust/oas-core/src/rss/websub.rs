@@ -0,0 +1,602 @@
+//! WebSub (formerly PubSubHubbub) push subscriptions for feeds.
+//!
+//! Feeds that advertise a hub with `<atom:link rel="hub">` are subscribed at the hub after
+//! they were fetched. The hub verifies the subscription with a challenge at the callback
+//! route of the server (`/api/v1/websub/<feed_id>`) and then pushes new content to the same
+//! route, signed with the secret of the subscription. Pushed content is saved like fetched
+//! content. Polling continues as a fallback, e.g. if the hub fails to deliver.
+//!
+//! Subscriptions are kept in memory, so the feed watchers and the server have to run in the
+//! same process (`oas run`). After a restart, feeds are subscribed again on their next fetch.
+//! Leases are renewed by [FeedManager](super::FeedManager) before they expire.
+
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac, NewMac};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Sha256, Sha384, Sha512};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use super::FeedWatcher;
+
+/// Lease that is requested from hubs (in seconds).
+pub const DEFAULT_LEASE_SECONDS: u64 = 7 * 24 * 3600;
+/// Leases are renewed this long before they expire (in seconds).
+const RENEW_MARGIN: i64 = 24 * 3600;
+/// Subscriptions that are not verified within this time are requested again (in seconds).
+const VERIFY_TIMEOUT: i64 = 3600;
+/// Interval to check for leases to renew.
+pub const RENEW_INTERVAL: std::time::Duration = std::time::Duration::from_secs(600);
+
+/// The state of a subscription.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum SubscriptionState {
+    /// The subscription was requested and awaits verification by the hub.
+    Pending,
+    /// The subscription was verified.
+    Active,
+    /// The subscription was denied by the hub.
+    Denied,
+    /// The unsubscription was requested and awaits verification by the hub.
+    Unsubscribing,
+}
+
+/// A subscription of a feed at a hub.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Subscription {
+    pub feed_id: String,
+    pub hub: String,
+    pub topic: String,
+    pub state: SubscriptionState,
+    pub requested_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    secret: String,
+    /// The secret of the active subscription while it is renewed.
+    #[serde(skip)]
+    active_secret: Option<String>,
+}
+
+/// The result of checking the signature of pushed content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushCheck {
+    /// The content was signed with the secret of the subscription.
+    Valid,
+    /// The signature is missing or invalid. The content must be ignored.
+    Invalid,
+    /// There is no active subscription for the feed.
+    Unknown,
+}
+
+/// WebSub subscriber. Cloning is cheap and shares the subscriptions.
+#[derive(Debug, Clone)]
+pub struct WebSub {
+    callback_url: String,
+    client: reqwest::Client,
+    subscriptions: Arc<Mutex<HashMap<String, Subscription>>>,
+}
+
+impl WebSub {
+    /// Create a subscriber. `callback_url` is the public URL of the API (e.g.
+    /// `https://example.org/api/v1`).
+    pub fn new(client: reqwest::Client, callback_url: &str) -> Self {
+        Self {
+            callback_url: callback_url.trim_end_matches('/').to_string(),
+            client,
+            subscriptions: Default::default(),
+        }
+    }
+
+    /// The callback URL for a feed.
+    pub fn callback(&self, feed_id: &str) -> String {
+        format!("{}/websub/{}", self.callback_url, feed_id)
+    }
+
+    /// Get all subscriptions.
+    pub async fn subscriptions(&self) -> Vec<Subscription> {
+        self.subscriptions.lock().await.values().cloned().collect()
+    }
+
+    /// Subscribe a feed at the hub advertised by the loaded feed, unless it is subscribed
+    /// already. Feeds that no longer advertise a hub are unsubscribed.
+    pub async fn ensure(&self, feed_id: &str, watcher: &FeedWatcher) {
+        let hub = match watcher.channel_link("hub") {
+            Some(hub) => hub,
+            None => {
+                self.drop_subscription(feed_id).await;
+                return;
+            }
+        };
+        let topic = watcher
+            .channel_link("self")
+            .unwrap_or_else(|| watcher.url().to_string());
+        let subscribed = match self.subscriptions.lock().await.get(feed_id) {
+            Some(sub) if sub.hub == hub && sub.topic == topic => match sub.state {
+                SubscriptionState::Active | SubscriptionState::Denied => true,
+                SubscriptionState::Pending => !verify_timed_out(sub),
+                SubscriptionState::Unsubscribing => false,
+            },
+            _ => false,
+        };
+        if subscribed {
+            return;
+        }
+        if let Err(err) = self.subscribe(feed_id, &hub, &topic).await {
+            log::warn!(
+                "Failed to subscribe feed {} at hub {}: {}",
+                feed_id,
+                hub,
+                err
+            );
+        }
+    }
+
+    /// Unsubscribe a feed at its hub. The subscription is dropped right away if it was not
+    /// verified or if the hub cannot be reached.
+    async fn drop_subscription(&self, feed_id: &str) {
+        let active = match self.subscriptions.lock().await.get(feed_id) {
+            None => return,
+            // Wait for the hub to verify the unsubscription.
+            Some(sub)
+                if sub.state == SubscriptionState::Unsubscribing && !verify_timed_out(sub) =>
+            {
+                return
+            }
+            Some(sub) => is_active(sub),
+        };
+        if active {
+            match self.unsubscribe(feed_id).await {
+                Ok(()) => return,
+                Err(err) => log::warn!("Failed to unsubscribe feed {}: {}", feed_id, err),
+            }
+        }
+        self.subscriptions.lock().await.remove(feed_id);
+    }
+
+    /// Request a subscription at a hub.
+    ///
+    /// If the feed has an active subscription at the same hub (i.e. the subscription is
+    /// renewed), content signed with its secret is accepted until the hub verifies the new
+    /// subscription.
+    pub async fn subscribe(&self, feed_id: &str, hub: &str, topic: &str) -> anyhow::Result<()> {
+        let secret: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+        let lease = DEFAULT_LEASE_SECONDS.to_string();
+        let callback = self.callback(feed_id);
+        let params = [
+            ("hub.mode", "subscribe"),
+            ("hub.topic", topic),
+            ("hub.callback", callback.as_str()),
+            ("hub.secret", secret.as_str()),
+            ("hub.lease_seconds", lease.as_str()),
+        ];
+        // Store the subscription first, the hub may verify it before it responds.
+        let previous = {
+            let mut subscriptions = self.subscriptions.lock().await;
+            let previous = subscriptions.get(feed_id).cloned();
+            let (active_secret, expires_at) = match &previous {
+                Some(sub) if sub.hub == hub && sub.topic == topic && is_active(sub) => {
+                    let secret = match sub.state {
+                        SubscriptionState::Active => Some(sub.secret.clone()),
+                        _ => sub.active_secret.clone(),
+                    };
+                    (secret, sub.expires_at)
+                }
+                _ => (None, None),
+            };
+            let sub = Subscription {
+                feed_id: feed_id.to_string(),
+                hub: hub.to_string(),
+                topic: topic.to_string(),
+                state: SubscriptionState::Pending,
+                requested_at: Utc::now(),
+                expires_at,
+                secret: secret.clone(),
+                active_secret,
+            };
+            subscriptions.insert(feed_id.to_string(), sub);
+            previous
+        };
+        log::debug!("Subscribe feed {} at hub {}", feed_id, hub);
+        let res = self.client.post(hub).form(&params).send().await;
+        let err = match res {
+            Ok(res) if res.status().is_success() => return Ok(()),
+            Ok(res) => anyhow::anyhow!("Hub responded with {}", res.status()),
+            Err(err) => err.into(),
+        };
+        // Keep the previous subscription if it is still active.
+        let mut subscriptions = self.subscriptions.lock().await;
+        match previous.filter(is_active) {
+            Some(previous) => subscriptions.insert(feed_id.to_string(), previous),
+            None => subscriptions.remove(feed_id),
+        };
+        Err(err)
+    }
+
+    /// Request to remove the subscription of a feed.
+    pub async fn unsubscribe(&self, feed_id: &str) -> anyhow::Result<()> {
+        let (hub, topic) = {
+            let mut subscriptions = self.subscriptions.lock().await;
+            match subscriptions.get_mut(feed_id) {
+                Some(sub) => {
+                    sub.state = SubscriptionState::Unsubscribing;
+                    sub.requested_at = Utc::now();
+                    (sub.hub.clone(), sub.topic.clone())
+                }
+                None => return Ok(()),
+            }
+        };
+        let callback = self.callback(feed_id);
+        let params = [
+            ("hub.mode", "unsubscribe"),
+            ("hub.topic", topic.as_str()),
+            ("hub.callback", callback.as_str()),
+        ];
+        let res = self.client.post(&hub).form(&params).send().await?;
+        if !res.status().is_success() {
+            anyhow::bail!("Hub responded with {}", res.status());
+        }
+        Ok(())
+    }
+
+    /// Handle a verification request of a hub.
+    ///
+    /// Returns the response body for the hub, or `None` if the request does not match a
+    /// requested subscription (or unsubscription).
+    pub async fn verify(
+        &self,
+        feed_id: &str,
+        mode: &str,
+        topic: &str,
+        challenge: Option<&str>,
+        lease_seconds: Option<u64>,
+    ) -> Option<String> {
+        let mut subscriptions = self.subscriptions.lock().await;
+        let sub = subscriptions
+            .get_mut(feed_id)
+            .filter(|sub| sub.topic == topic)?;
+        match (mode, sub.state) {
+            ("subscribe", SubscriptionState::Pending)
+            | ("subscribe", SubscriptionState::Active) => {
+                let lease = lease_seconds.unwrap_or(DEFAULT_LEASE_SECONDS);
+                sub.state = SubscriptionState::Active;
+                sub.active_secret = None;
+                sub.expires_at = Some(Utc::now() + Duration::seconds(lease as i64));
+                log::info!("Subscribed feed {} at hub {}", feed_id, sub.hub);
+                challenge.map(|c| c.to_string())
+            }
+            ("unsubscribe", SubscriptionState::Unsubscribing) => {
+                subscriptions.remove(feed_id);
+                challenge.map(|c| c.to_string())
+            }
+            ("denied", _) => {
+                log::warn!("Hub {} denied subscription of feed {}", sub.hub, feed_id);
+                sub.state = SubscriptionState::Denied;
+                Some(String::new())
+            }
+            _ => None,
+        }
+    }
+
+    /// Check the signature (the `X-Hub-Signature` header) of content pushed for a feed.
+    pub async fn check_signature(
+        &self,
+        feed_id: &str,
+        signature: Option<&str>,
+        body: &[u8],
+    ) -> PushCheck {
+        let subscriptions = self.subscriptions.lock().await;
+        let secrets = match subscriptions.get(feed_id) {
+            Some(sub) if is_active(sub) => {
+                let mut secrets = vec![sub.secret.as_str()];
+                // The hub signs with the previous secret until it verified the renewal.
+                secrets.extend(sub.active_secret.as_deref());
+                secrets
+            }
+            _ => return PushCheck::Unknown,
+        };
+        let (method, hex) = match signature.and_then(|s| s.split_once('=')) {
+            Some(parts) => parts,
+            None => return PushCheck::Invalid,
+        };
+        let valid = secrets
+            .iter()
+            .any(|secret| verify_signature(method, secret.as_bytes(), body, hex));
+        if valid {
+            PushCheck::Valid
+        } else {
+            PushCheck::Invalid
+        }
+    }
+
+    /// Renew subscriptions whose lease expires soon and subscriptions that were not verified.
+    pub async fn renew(&self) {
+        let now = Utc::now();
+        let due: Vec<Subscription> = self
+            .subscriptions
+            .lock()
+            .await
+            .values()
+            .filter(|sub| match (sub.state, sub.expires_at) {
+                (SubscriptionState::Active, Some(expires)) => {
+                    expires - now < Duration::seconds(RENEW_MARGIN)
+                }
+                (SubscriptionState::Pending, _) => verify_timed_out(sub),
+                _ => false,
+            })
+            .cloned()
+            .collect();
+        for sub in due {
+            if let Err(err) = self.subscribe(&sub.feed_id, &sub.hub, &sub.topic).await {
+                log::warn!(
+                    "Failed to renew subscription of feed {} at hub {}: {}",
+                    sub.feed_id,
+                    sub.hub,
+                    err
+                );
+            }
+        }
+    }
+
+    /// Renew leases periodically. This runs forever.
+    pub async fn run_renewal(self) {
+        loop {
+            tokio::time::sleep(RENEW_INTERVAL).await;
+            self.renew().await;
+        }
+    }
+}
+
+fn verify_timed_out(sub: &Subscription) -> bool {
+    Utc::now() - sub.requested_at > Duration::seconds(VERIFY_TIMEOUT)
+}
+
+/// Whether a subscription is active, or renewed while its lease has not expired yet.
+fn is_active(sub: &Subscription) -> bool {
+    match sub.state {
+        SubscriptionState::Active => true,
+        SubscriptionState::Pending => {
+            sub.active_secret.is_some() && sub.expires_at.map_or(false, |at| at > Utc::now())
+        }
+        _ => false,
+    }
+}
+
+/// Check the hex encoded HMAC of a message with one of the WebSub signature methods.
+///
+/// The HMAC is compared in constant time.
+fn verify_signature(method: &str, key: &[u8], message: &[u8], signature: &str) -> bool {
+    let signature = match decode_hex(signature) {
+        Some(signature) => signature,
+        None => return false,
+    };
+    match method {
+        "sha1" => verify_mac::<Hmac<Sha1>>(key, message, &signature),
+        "sha256" => verify_mac::<Hmac<Sha256>>(key, message, &signature),
+        "sha384" => verify_mac::<Hmac<Sha384>>(key, message, &signature),
+        "sha512" => verify_mac::<Hmac<Sha512>>(key, message, &signature),
+        _ => false,
+    }
+}
+
+fn verify_mac<M: Mac + NewMac>(key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    match M::new_from_slice(key) {
+        Ok(mut mac) => {
+            mac.update(message);
+            mac.verify(signature).is_ok()
+        }
+        Err(_) => false,
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    /// A stand-in hub that accepts a number of subscription requests and sends their form
+    /// bodies.
+    fn stand_in_hub(requests: usize) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hub", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            for _ in 0..requests {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim().to_lowercase();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                stream
+                    .write_all(
+                        b"HTTP/1.1 202 Accepted\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    )
+                    .unwrap();
+                tx.send(String::from_utf8(body).unwrap()).unwrap();
+            }
+        });
+        (url, rx)
+    }
+
+    /// Compute the hex encoded HMAC-SHA256 of a message, like a hub does.
+    fn sign(key: &[u8], message: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+        mac.update(message);
+        let bytes = mac.finalize().into_bytes();
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn hmac_vectors() {
+        // Test case 2 of RFC 2202 and RFC 4231.
+        let key = b"Jefe";
+        let data = b"what do ya want for nothing?";
+        assert!(verify_signature(
+            "sha1",
+            key,
+            data,
+            "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79"
+        ));
+        let sha256 = "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843";
+        assert_eq!(sign(key, data), sha256);
+        assert!(verify_signature("sha256", key, data, sha256));
+        assert!(!verify_signature("sha256", key, b"something else", sha256));
+        assert!(!verify_signature("sha256", key, data, "5bdc"));
+        assert!(!verify_signature("sha256", key, data, "not hex"));
+        assert!(!verify_signature("md5", key, data, sha256));
+    }
+
+    #[tokio::test]
+    async fn subscribe_verify_push() {
+        let (hub, requests) = stand_in_hub(1);
+        let websub = WebSub::new(reqwest::Client::new(), "https://oas.example/api/v1/");
+        let topic = "https://example.org/feed";
+        websub.subscribe("feed1", &hub, topic).await.unwrap();
+
+        let form: HashMap<String, String> =
+            url::form_urlencoded::parse(requests.recv().unwrap().as_bytes())
+                .into_owned()
+                .collect();
+        assert_eq!(form["hub.mode"], "subscribe");
+        assert_eq!(form["hub.topic"], topic);
+        assert_eq!(
+            form["hub.callback"],
+            "https://oas.example/api/v1/websub/feed1"
+        );
+        let secret = &form["hub.secret"];
+
+        let body = b"<rss></rss>";
+        let signature = format!("sha256={}", sign(secret.as_bytes(), body));
+        // Content is not accepted before the subscription is verified.
+        assert_eq!(
+            websub
+                .check_signature("feed1", Some(&signature), body)
+                .await,
+            PushCheck::Unknown
+        );
+
+        let challenge = websub
+            .verify(
+                "feed1",
+                "subscribe",
+                "https://other.example",
+                Some("c"),
+                None,
+            )
+            .await;
+        assert!(challenge.is_none());
+        let challenge = websub
+            .verify("feed1", "subscribe", topic, Some("c"), Some(60))
+            .await;
+        assert_eq!(challenge.as_deref(), Some("c"));
+        assert_eq!(
+            websub.subscriptions().await[0].state,
+            SubscriptionState::Active
+        );
+
+        assert_eq!(
+            websub
+                .check_signature("feed1", Some(&signature), body)
+                .await,
+            PushCheck::Valid
+        );
+        assert_eq!(
+            websub
+                .check_signature("feed1", Some(&signature), b"<rss>x</rss>")
+                .await,
+            PushCheck::Invalid
+        );
+        assert_eq!(
+            websub.check_signature("feed1", None, body).await,
+            PushCheck::Invalid
+        );
+        assert_eq!(
+            websub
+                .check_signature("feed2", Some(&signature), body)
+                .await,
+            PushCheck::Unknown
+        );
+    }
+
+    #[tokio::test]
+    async fn renew_keeps_active_secret() {
+        let (hub, requests) = stand_in_hub(2);
+        let websub = WebSub::new(reqwest::Client::new(), "https://oas.example/api/v1/");
+        let topic = "https://example.org/feed";
+        let secret = |requests: &mpsc::Receiver<String>| {
+            let form: HashMap<String, String> =
+                url::form_urlencoded::parse(requests.recv().unwrap().as_bytes())
+                    .into_owned()
+                    .collect();
+            form["hub.secret"].clone()
+        };
+        let body = b"<rss></rss>";
+
+        websub.subscribe("feed1", &hub, topic).await.unwrap();
+        let old = format!("sha256={}", sign(secret(&requests).as_bytes(), body));
+        websub
+            .verify("feed1", "subscribe", topic, Some("c"), Some(60))
+            .await;
+
+        websub.subscribe("feed1", &hub, topic).await.unwrap();
+        let new = format!("sha256={}", sign(secret(&requests).as_bytes(), body));
+        assert_eq!(
+            websub.subscriptions().await[0].state,
+            SubscriptionState::Pending
+        );
+        // Until the renewal is verified, the hub may sign with either secret.
+        assert_eq!(
+            websub.check_signature("feed1", Some(&old), body).await,
+            PushCheck::Valid
+        );
+        assert_eq!(
+            websub.check_signature("feed1", Some(&new), body).await,
+            PushCheck::Valid
+        );
+
+        websub
+            .verify("feed1", "subscribe", topic, Some("c"), Some(60))
+            .await;
+        assert_eq!(
+            websub.check_signature("feed1", Some(&old), body).await,
+            PushCheck::Invalid
+        );
+        assert_eq!(
+            websub.check_signature("feed1", Some(&new), body).await,
+            PushCheck::Valid
+        );
+    }
+}
@@ -1,9 +1,15 @@
 use oas_common::{types, util, Record, TypedValue};
+use rocket::http::Status;
+use rocket::response::content;
 use rocket::serde::json::Json;
 use rocket::{delete, get, post, put};
 use rocket_okapi::openapi;
 
 use crate::couch::types::PutResponse;
+use crate::rss::moves::{self, MoveReport};
+use crate::rss::opml::{self, ImportReport};
+use crate::rss::preview::{FeedPreview, PreviewRequest};
+use crate::rss::scheduler::WatcherInfo;
 use crate::server::auth::AdminUser;
 use crate::server::error::AppError;
 use crate::State;
@@ -39,6 +45,7 @@ pub async fn put_feed(
 ) -> Result<Json<PutResponse>, AppError> {
     let feed = body.into_inner();
     let feed = Record::from_id_and_value(id, feed);
+    feed.validate().map_err(AppError::ValidationError)?;
     let result = state.db.put_record(feed).await?;
 
     Ok(Json(result))
@@ -78,3 +85,107 @@ pub async fn get_feeds(
     let feeds = state.db.get_all_records().await?;
     Ok(Json(feeds))
 }
+
+/// Get the watcher state of all feeds
+#[openapi(tag = "Feed")]
+#[get("/feed/watchers")]
+pub async fn get_feed_watchers(
+    _user: AdminUser,
+    state: &rocket::State<State>,
+) -> Result<Json<Vec<WatcherInfo>>, AppError> {
+    let infos = state.feed_manager.watcher_infos().await;
+    Ok(Json(infos))
+}
+
+/// Get the watcher state of a feed by its id
+#[openapi(tag = "Feed")]
+#[get("/feed/<id>/watcher")]
+pub async fn get_feed_watcher(
+    _user: AdminUser,
+    state: &rocket::State<State>,
+    id: String,
+) -> Result<Json<WatcherInfo>, AppError> {
+    match state.feed_manager.watcher_info(&id).await {
+        Some(info) => Ok(Json(info)),
+        None => Err(AppError::Http(
+            Status::NotFound,
+            format!("No watcher running for feed {}", id),
+        )),
+    }
+}
+
+/// Move a feed to a new URL
+///
+/// Without `url`, the feed is moved to the URL it announced (`movedTo`). Posts and medias
+/// stay attached to the feed and the old URL is kept as an alias.
+#[openapi(tag = "Feed")]
+#[post("/feed/<id>/move?<url>")]
+pub async fn move_feed(
+    _user: AdminUser,
+    state: &rocket::State<State>,
+    id: String,
+    url: Option<String>,
+) -> Result<Json<MoveReport>, AppError> {
+    let feed: Record<types::Feed> = state.db.get_record(&types::Feed::guid(&id)).await?;
+    let url = match url.or_else(|| feed.value.moved_to.clone()) {
+        Some(url) => url,
+        None => {
+            return Err(AppError::Http(
+                Status::BadRequest,
+                format!("Feed {} did not announce a new URL", id),
+            ))
+        }
+    };
+    let report = moves::move_feed(&state.db, feed, &url).await?;
+    Ok(Json(report))
+}
+
+/// Import feeds from an OPML document
+///
+/// Feeds that exist already are not changed. With `dry_run`, only report which feeds would
+/// be created.
+#[openapi(tag = "Feed")]
+#[post("/feed/import?<dry_run>", data = "<body>")]
+pub async fn import_feeds(
+    _user: AdminUser,
+    state: &rocket::State<State>,
+    dry_run: Option<bool>,
+    body: String,
+) -> Result<Json<ImportReport>, AppError> {
+    let report = opml::import(&state.db, &body, dry_run.unwrap_or_default())
+        .await
+        .map_err(|err| match err.downcast::<opml::InvalidOpml>() {
+            Ok(err) => AppError::Http(Status::BadRequest, err.to_string()),
+            Err(err) => err.into(),
+        })?;
+    Ok(Json(report))
+}
+
+/// Export all feeds as an OPML document
+#[openapi(tag = "Feed")]
+#[get("/feed/export")]
+pub async fn export_feeds(
+    _user: AdminUser,
+    state: &rocket::State<State>,
+) -> Result<content::Xml<String>, AppError> {
+    let xml = opml::export(&state.db).await?;
+    Ok(content::Xml(xml))
+}
+
+/// Preview the posts and medias of a feed
+///
+/// Fetches the feed and applies the selected mapping profile without saving anything.
+#[openapi(tag = "Feed")]
+#[post("/feed/preview", data = "<body>")]
+pub async fn preview_feed(
+    _user: AdminUser,
+    state: &rocket::State<State>,
+    body: Json<PreviewRequest>,
+) -> Result<Json<FeedPreview>, AppError> {
+    let request = body.into_inner();
+    let preview = state
+        .feed_manager
+        .preview(&state.db, &request.url, request.settings)
+        .await?;
+    Ok(Json(preview))
+}
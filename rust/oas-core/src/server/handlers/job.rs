@@ -1,16 +1,23 @@
-use rocket::http::Status;
+use futures::stream::{BoxStream, StreamExt};
+use rocket::http::{ContentType, Status};
 use rocket::response::status::Custom;
+use rocket::response::stream::{stream, Event, EventStream, TextStream};
+use rocket::response::Responder;
 use rocket::serde::json::Json;
-use rocket::FromForm;
 use rocket::{delete, get, post, put};
+use rocket::{FromForm, Shutdown};
 use rocket_okapi::openapi;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::oneshot;
 
 use crate::server::auth::AdminUser;
 use crate::server::error::{AppError, Result};
 
 use crate::jobs::{
-    JobCompletedRequest, JobCreateRequest, JobFailedRequest, JobFilter, JobId, JobInfo,
-    JobProgressRequest, JobRequest, JobStatus,
+    bulk, BulkCreateRequest, BulkCreateResult, JobCompletedRequest, JobCreateRequest, JobEvent,
+    JobEventFilter, JobFailedRequest, JobFilter, JobId, JobInfo, JobManager, JobProgressRequest,
+    JobRequest, JobStatus, JobTakeTimeout, Queue, QueueInfo, QueueSettings,
 };
 
 #[derive(FromForm)]
@@ -37,6 +44,73 @@ pub async fn get_all_jobs(
     Ok(Json(jobs))
 }
 
+#[derive(FromForm)]
+pub struct JobEventQuery {
+    pub job: Vec<u64>,
+    pub queue: Vec<String>,
+    pub subject: Vec<String>,
+}
+
+impl From<JobEventQuery> for JobEventFilter {
+    fn from(query: JobEventQuery) -> Self {
+        Self {
+            job: query.job,
+            queue: query.queue,
+            subject: query.subject,
+        }
+    }
+}
+
+/// Interval in which heartbeats are sent on job event streams.
+const EVENTS_HEARTBEAT: Duration = Duration::from_secs(15);
+
+/// Stream changes of jobs as Server-Sent Events.
+///
+/// Filter by job ID, queue or subject GUID, e.g. `/jobs/events?queue=asr&subject=oas.Media_1`.
+/// Events have the type `job` and a [JobEvent] as data. The current state of the jobs passed
+/// as `job` is sent first. If the client falls behind, a `lagged` event with the number of
+/// skipped events is sent.
+#[openapi(skip)]
+#[get("/jobs/events?<filter..>")]
+pub async fn get_job_events(
+    _user: AdminUser,
+    state: &rocket::State<crate::State>,
+    filter: Option<JobEventQuery>,
+    mut shutdown: Shutdown,
+) -> EventStream![] {
+    let filter: JobEventFilter = filter.map(Into::into).unwrap_or_default();
+    // Subscribe before loading the current states to not miss any changes.
+    let mut events = state.jobs.events().subscribe();
+    let mut current = vec![];
+    for id in filter.job.iter() {
+        if let Ok(job) = state.jobs.job(*id).await {
+            current.push(JobEvent::from(job));
+        }
+    }
+    EventStream! {
+        for event in current {
+            yield Event::json(&event).event("job");
+        }
+        loop {
+            let event = tokio::select! {
+                event = events.recv() => match event {
+                    Ok(event) => event,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(skipped)) => {
+                        yield Event::data(skipped.to_string()).event("lagged");
+                        continue;
+                    }
+                },
+                _ = &mut shutdown => break,
+            };
+            if filter.matches(&event) {
+                yield Event::json(&event).event("job");
+            }
+        }
+    }
+    .heartbeat(EVENTS_HEARTBEAT)
+}
+
 /// Get a job by ID.
 #[openapi(skip)]
 #[get("/job/<id>")]
@@ -61,6 +135,18 @@ pub async fn post_job(
     Ok(Json(res))
 }
 
+/// Create jobs for many posts or medias.
+#[openapi(skip)]
+#[post("/jobs/bulk", data = "<value>")]
+pub async fn post_jobs_bulk(
+    _user: AdminUser,
+    state: &rocket::State<crate::State>,
+    value: Json<BulkCreateRequest>,
+) -> Result<BulkCreateResult> {
+    let res = bulk::create_jobs(state.inner(), value.into_inner()).await?;
+    Ok(Json(res))
+}
+
 /// Delete a job by ID
 #[openapi(skip)]
 #[delete("/job/<id>")]
@@ -73,35 +159,146 @@ pub async fn delete_job(
     Ok(Json(()))
 }
 
+/// Default time to wait for a job in seconds.
+const DEFAULT_WAIT_SECS: u64 = 60;
+/// Max time to wait for a job in seconds.
+const MAX_WAIT_SECS: u64 = 300;
+/// Interval in which whitespace is sent while waiting for a job.
+const WAIT_KEEPALIVE: Duration = Duration::from_secs(2);
+
+pub enum WorkResponse {
+    Job(Custom<Json<Option<JobRequest>>>),
+    Wait((ContentType, TextStream<BoxStream<'static, String>>)),
+}
+
+impl<'r> Responder<'r, 'r> for WorkResponse {
+    fn respond_to(self, req: &'r rocket::Request<'_>) -> rocket::response::Result<'r> {
+        match self {
+            Self::Job(res) => res.respond_to(req),
+            Self::Wait(res) => res.respond_to(req),
+        }
+    }
+}
+
 /// Pull a job to work it.
+///
+/// With `?wait=<secs>` (default 60, max 300), the request is held until a job is available or
+/// the time is up. The response then always has status 200: Whitespace is sent while
+/// waiting, followed by the job, `null` if no job became available, or `{"error": ".."}`.
 // #[openapi(tag = "Job")]
 #[openapi(skip)]
-#[post("/work/<typ>?<wait>")]
+#[post("/work/<typ>?<wait>&<worker>")]
 pub async fn work_job(
     _user: AdminUser,
     state: &rocket::State<crate::State>,
     typ: String,
     wait: Option<String>,
-) -> std::result::Result<Custom<rocket::serde::json::Json<Option<JobRequest>>>, AppError> {
-    let wait = wait.map(|_any| true).unwrap_or(false);
-    let job = match wait {
-        false => state.jobs.take_job(&typ).await?,
-        true => {
-            // TODO: This is not aborted in rocket when the request ends.
-            // Enable after switching to axum
-            return Err(AppError::Other("wait opt is not yet supported.".into()));
-            // let timeout = std::time::Duration::from_secs(60);
-            // state
-            //     .jobs
-            //     .take_job_timeout(&typ, JobTakeTimeout::Timeout(timeout))
-            //     .await?
+    worker: Option<String>,
+) -> std::result::Result<WorkResponse, AppError> {
+    if let Some(wait) = wait {
+        let secs = wait
+            .parse::<u64>()
+            .unwrap_or(DEFAULT_WAIT_SECS)
+            .min(MAX_WAIT_SECS);
+        let timeout = Duration::from_secs(secs);
+        let stream = wait_for_job(state.jobs.clone(), typ, worker, timeout);
+        return Ok(WorkResponse::Wait((ContentType::JSON, TextStream(stream))));
+    }
+    let job = state.jobs.take_job(&typ, worker.as_deref()).await?;
+    let status = match job {
+        Some(_) => Status::Ok,
+        None => Status::NoContent,
+    };
+    Ok(WorkResponse::Job(Custom(status, Json(job))))
+}
+
+/// Wait for a job while sending whitespace to detect closed connections.
+///
+/// Rocket does not cancel request handlers when the client disconnects, but it drops the
+/// response stream once writing to the connection fails. The job is taken in a task of its
+/// own, which stops waiting once the stream is dropped. A job that was taken but not written
+/// to the connection is put back in its queue.
+fn wait_for_job(
+    jobs: JobManager,
+    typ: String,
+    worker: Option<String>,
+    timeout: Duration,
+) -> BoxStream<'static, String> {
+    let (tx, rx) = oneshot::channel();
+    tokio::spawn(async move {
+        let res = jobs
+            .take_job_until(
+                &typ,
+                worker.as_deref(),
+                JobTakeTimeout::Timeout(timeout),
+                || tx.is_closed(),
+            )
+            .await;
+        let taken = match &res {
+            Ok(Some(job)) => Some(TakenJob::new(jobs.clone(), job.id)),
+            _ => None,
+        };
+        // If the stream was dropped, the taken job is dropped with the message.
+        let _ = tx.send((res, taken));
+    });
+    let stream = stream! {
+        tokio::pin!(rx);
+        let mut keepalive = tokio::time::interval_at(
+            tokio::time::Instant::now() + WAIT_KEEPALIVE,
+            WAIT_KEEPALIVE,
+        );
+        let res = loop {
+            tokio::select! {
+                res = &mut rx => break res,
+                _ = keepalive.tick() => yield " ".to_string(),
+            }
+        };
+        let (res, taken) =
+            res.unwrap_or_else(|_| (Err(anyhow::anyhow!("Failed to wait for a job")), None));
+        let body = match res {
+            Ok(job) => serde_json::to_string(&job),
+            Err(err) => {
+                log::error!("Failed to take job: {:?}", err);
+                serde_json::to_string(&serde_json::json!({ "error": err.to_string() }))
+            }
+        };
+        yield body.unwrap_or_else(|_| "null".to_string());
+        // The stream is only polled again once the body was written.
+        if let Some(taken) = taken {
+            taken.handed_out();
         }
     };
-    // let job = state.jobs.take_job(&typ).await?;
-    if let Some(job) = job {
-        Ok(Custom(Status::Ok, Json(Some(job))))
-    } else {
-        Ok(Custom(Status::NoContent, Json(None)))
+    stream.boxed()
+}
+
+/// A job that was taken for a waiting client. Unless it is marked as handed out, the job is
+/// put back in its queue when this is dropped.
+struct TakenJob {
+    jobs: JobManager,
+    id: Option<JobId>,
+}
+
+impl TakenJob {
+    fn new(jobs: JobManager, id: JobId) -> Self {
+        Self { jobs, id: Some(id) }
+    }
+
+    fn handed_out(mut self) {
+        self.id = None;
+    }
+}
+
+impl Drop for TakenJob {
+    fn drop(&mut self) {
+        if let Some(id) = self.id.take() {
+            let jobs = self.jobs.clone();
+            tokio::spawn(async move {
+                log::debug!("Client disconnected before receiving job {}, requeue", id);
+                if let Err(err) = jobs.requeue_job(id).await {
+                    log::warn!("Failed to requeue job {}: {}", id, err);
+                }
+            });
+        }
     }
 }
 
@@ -131,6 +328,18 @@ pub async fn put_job_progress(
     Ok(Json(()))
 }
 
+/// Send a heartbeat for a running job.
+#[openapi(skip)]
+#[put("/job/<id>/heartbeat")]
+pub async fn put_job_heartbeat(
+    _user: AdminUser,
+    state: &rocket::State<crate::State>,
+    id: u64,
+) -> Result<()> {
+    state.jobs.heartbeat(id).await?;
+    Ok(Json(()))
+}
+
 /// Fail a job.
 #[openapi(skip)]
 #[put("/job/<id>/failed", data = "<value>")]
@@ -143,3 +352,47 @@ pub async fn put_job_failed(
     state.jobs.set_failed(id, value.into_inner()).await?;
     Ok(Json(()))
 }
+
+/// Get all queues with their settings.
+#[openapi(skip)]
+#[get("/queue")]
+pub async fn get_queues(
+    _user: AdminUser,
+    state: &rocket::State<crate::State>,
+) -> Result<Vec<QueueInfo>> {
+    let queues = state.jobs.queues().await?;
+    Ok(Json(queues))
+}
+
+/// Get the settings of a queue.
+#[openapi(skip)]
+#[get("/queue/<name>")]
+pub async fn get_queue(
+    _user: AdminUser,
+    state: &rocket::State<crate::State>,
+    name: String,
+) -> Result<Queue> {
+    match state.jobs.queue(&name).await? {
+        Some(queue) => Ok(Json(queue)),
+        None => Err(AppError::Http(
+            Status::NotFound,
+            format!("Queue not found: {}", name),
+        )),
+    }
+}
+
+/// Update the settings of a queue.
+///
+/// Settings that are not set are left unchanged. Queues that do not exist yet are created with
+/// the configured settings first.
+#[openapi(skip)]
+#[put("/queue/<name>", data = "<value>")]
+pub async fn put_queue(
+    _user: AdminUser,
+    state: &rocket::State<crate::State>,
+    name: String,
+    value: Json<QueueSettings>,
+) -> Result<Queue> {
+    let queue = state.jobs.update_queue(&name, value.into_inner()).await?;
+    Ok(Json(queue))
+}
@@ -0,0 +1,81 @@
+use oas_common::types::FeedMapping;
+use oas_common::{Record, TypedValue};
+use rocket::serde::json::Json;
+use rocket::{delete, get, put};
+use rocket_okapi::openapi;
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::couch::types::PutResponse;
+use crate::rss::mapping::{self, check_mapping, MappingProfiles};
+use crate::server::auth::AdminUser;
+use crate::server::error::AppError;
+use crate::State;
+
+#[derive(Serialize, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PutMappingResponse {
+    #[serde(flatten)]
+    pub response: PutResponse,
+    /// Target fields that are not part of the post or media schema
+    pub warnings: Vec<String>,
+}
+
+/// Get all mapping profiles
+#[openapi(tag = "Mapping")]
+#[get("/mapping")]
+pub async fn get_mappings(
+    _user: AdminUser,
+    state: &rocket::State<State>,
+) -> Result<Json<Vec<Record<FeedMapping>>>, AppError> {
+    let mappings = state.db.get_all_records().await?;
+    Ok(Json(mappings))
+}
+
+/// Get a mapping profile by its name
+#[openapi(tag = "Mapping")]
+#[get("/mapping/<name>")]
+pub async fn get_mapping(
+    _user: AdminUser,
+    state: &rocket::State<State>,
+    name: String,
+) -> Result<Json<Record<FeedMapping>>, AppError> {
+    let mapping = state.db.get_record(&FeedMapping::guid(&name)).await?;
+    Ok(Json(mapping))
+}
+
+/// Create or update a mapping profile
+///
+/// Running feed watchers pick up the changed profile without a restart.
+#[openapi(tag = "Mapping")]
+#[put("/mapping/<name>", data = "<body>")]
+pub async fn put_mapping(
+    _user: AdminUser,
+    state: &rocket::State<State>,
+    name: String,
+    body: Json<FeedMapping>,
+) -> Result<Json<PutMappingResponse>, AppError> {
+    let mapping = body.into_inner();
+    let profiles = MappingProfiles::from_records(state.db.get_all_records().await?);
+    let check = check_mapping(&profiles, &name, &mapping).map_err(AppError::ValidationError)?;
+    let record = Record::from_id_and_value(name, mapping);
+    let response = state.db.put_record(record).await?;
+    Ok(Json(PutMappingResponse {
+        response,
+        warnings: check.warnings,
+    }))
+}
+
+/// Delete a mapping profile
+///
+/// Profiles that are extended by other profiles cannot be deleted.
+#[openapi(tag = "Mapping")]
+#[delete("/mapping/<name>")]
+pub async fn delete_mapping(
+    _user: AdminUser,
+    state: &rocket::State<State>,
+    name: String,
+) -> Result<Json<PutResponse>, AppError> {
+    let result = mapping::delete_profile(&state.db, &name).await?;
+    Ok(Json(result))
+}
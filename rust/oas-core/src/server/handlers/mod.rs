@@ -1,7 +1,11 @@
 pub mod changes;
 pub mod feed;
 pub mod job;
+pub mod mapping;
 pub mod media;
+pub mod oai;
 pub mod post;
 pub mod record;
 pub mod search;
+pub mod show;
+pub mod websub;
@@ -0,0 +1,67 @@
+use oas_common::{types, Record, TypedValue};
+use rocket::serde::json::Json;
+use rocket::{get, post};
+use rocket_okapi::openapi;
+
+use crate::rss::oai::{self, HarvestOpts, HarvestReport};
+use crate::server::auth::AdminUser;
+use crate::server::error::AppError;
+use crate::State;
+
+/// Create a new OAI-PMH source
+///
+/// Returns the existing source if the repository (and set) was added before.
+#[openapi(tag = "OAI")]
+#[post("/oai", data = "<body>")]
+pub async fn post_oai_source(
+    _user: AdminUser,
+    state: &rocket::State<State>,
+    body: Json<types::OaiSource>,
+) -> Result<Json<Record<types::OaiSource>>, AppError> {
+    let source = body.into_inner();
+    source.validate().map_err(AppError::ValidationError)?;
+    let record = oai::add_source(&state.db, source).await?;
+    Ok(Json(record))
+}
+
+/// Get an OAI-PMH source by its id
+#[openapi(tag = "OAI")]
+#[get("/oai/<id>")]
+pub async fn get_oai_source(
+    _user: AdminUser,
+    state: &rocket::State<State>,
+    id: String,
+) -> Result<Json<Record<types::OaiSource>>, AppError> {
+    let source = state.db.get_record(&types::OaiSource::guid(&id)).await?;
+    Ok(Json(source))
+}
+
+/// Get all OAI-PMH sources
+#[openapi(tag = "OAI")]
+#[get("/oai")]
+pub async fn get_oai_sources(
+    _user: AdminUser,
+    state: &rocket::State<State>,
+) -> Result<Json<Vec<Record<types::OaiSource>>>, AppError> {
+    let sources = state.db.get_all_records().await?;
+    Ok(Json(sources))
+}
+
+/// Harvest an OAI-PMH source
+///
+/// Only records that changed since the last complete harvest are harvested, unless `full`
+/// is set.
+#[openapi(tag = "OAI")]
+#[post("/oai/<id>/harvest", data = "<body>")]
+pub async fn harvest_oai_source(
+    _user: AdminUser,
+    state: &rocket::State<State>,
+    id: String,
+    body: Json<HarvestOpts>,
+) -> Result<Json<HarvestReport>, AppError> {
+    let report = state
+        .feed_manager
+        .harvest(&state.db, &id, &body.into_inner())
+        .await?;
+    Ok(Json(report))
+}
@@ -1,4 +1,4 @@
-use oas_common::types::{Feed, Media, Post};
+use oas_common::types::{Feed, Media, Post, Show};
 use oas_common::{TypedValue, UntypedRecord};
 use rocket::serde::json::Json;
 use rocket::{get, post};
@@ -50,6 +50,10 @@ pub async fn post_record(
             let record = record.into_typed_record::<Feed>()?;
             records.push(record.into_untyped()?);
         }
+        Show::NAME => {
+            let record = record.into_typed_record::<Show>()?;
+            records.push(record.into_untyped()?);
+        }
         _ => return Err(AppError::Other("Unknown type".to_string())),
     }
 
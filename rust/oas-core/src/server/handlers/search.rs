@@ -13,12 +13,16 @@ pub async fn search(
     search_method: String,
     body: String,
 ) -> Result<String, AppError> {
-    if &index_name != "oas" {
-        return Err(AppError::Http(
-            Status::BadRequest,
-            "Invalid index name".into(),
-        ));
-    }
+    let index = match index_name.as_str() {
+        "oas" => state.index_manager.post_index().index(),
+        "shows" => state.index_manager.show_index().index(),
+        _ => {
+            return Err(AppError::Http(
+                Status::BadRequest,
+                "Invalid index name".into(),
+            ))
+        }
+    };
 
     if !SEARCH_METHODS.contains(&search_method.as_str()) {
         return Err(AppError::Http(
@@ -27,7 +31,6 @@ pub async fn search(
         ));
     }
 
-    let index = &state.index_manager.post_index();
     let client = &index.client();
 
     let path = format!("{}/{}", index.name(), search_method);
@@ -0,0 +1,23 @@
+use oas_common::types::Show;
+use oas_common::{Record, TypedValue};
+use rocket::get;
+use rocket::serde::json::Json;
+use rocket_okapi::openapi;
+
+use crate::server::error::Result;
+
+/// Get a show record by id.
+#[openapi(tag = "Show")]
+#[get("/show/<id>")]
+pub async fn get_show(state: &rocket::State<crate::State>, id: String) -> Result<Record<Show>> {
+    let record = state.db.get_record(&Show::guid(&id)).await?;
+    Ok(Json(record))
+}
+
+/// Get all show records.
+#[openapi(tag = "Show")]
+#[get("/show")]
+pub async fn get_shows(state: &rocket::State<crate::State>) -> Result<Vec<Record<Show>>> {
+    let records = state.db.get_all_records().await?;
+    Ok(Json(records))
+}
@@ -0,0 +1,86 @@
+use rocket::data::{Data, ToByteUnit};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::{get, post, FromForm};
+
+use crate::rss::websub::PushCheck;
+use crate::State;
+
+/// Max size of content pushed by a hub.
+const PUSH_LIMIT_MIB: usize = 16;
+
+/// The `hub.*` query parameters of a verification request.
+#[derive(FromForm, Debug)]
+pub struct HubVerification {
+    mode: String,
+    topic: String,
+    challenge: Option<String>,
+    lease_seconds: Option<u64>,
+}
+
+/// The `X-Hub-Signature` header of pushed content.
+pub struct HubSignature(Option<String>);
+
+#[async_trait::async_trait]
+impl<'r> FromRequest<'r> for HubSignature {
+    type Error = ();
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let signature = request.headers().get_one("X-Hub-Signature");
+        Outcome::Success(Self(signature.map(|s| s.to_string())))
+    }
+}
+
+/// Verify a WebSub subscription
+#[get("/websub/<id>?<hub>")]
+pub async fn verify_subscription(
+    state: &rocket::State<State>,
+    id: String,
+    hub: HubVerification,
+) -> Result<String, Status> {
+    let websub = state.feed_manager.websub().ok_or(Status::NotFound)?;
+    websub
+        .verify(
+            &id,
+            &hub.mode,
+            &hub.topic,
+            hub.challenge.as_deref(),
+            hub.lease_seconds,
+        )
+        .await
+        .ok_or(Status::NotFound)
+}
+
+/// Receive content pushed by a WebSub hub
+#[post("/websub/<id>", data = "<data>")]
+pub async fn push_content(
+    state: &rocket::State<State>,
+    id: String,
+    signature: HubSignature,
+    data: Data<'_>,
+) -> Status {
+    let body = match data.open(PUSH_LIMIT_MIB.mebibytes()).into_bytes().await {
+        Ok(body) if body.is_complete() => body.into_inner(),
+        _ => return Status::PayloadTooLarge,
+    };
+    let result = state
+        .feed_manager
+        .ingest_push(&state.db, &id, signature.0.as_deref(), &body)
+        .await;
+    match result {
+        Ok(PushCheck::Valid) => Status::NoContent,
+        // Content with an invalid signature is acknowledged but ignored, as required by
+        // the WebSub specification.
+        Ok(PushCheck::Invalid) => {
+            log::warn!(
+                "Ignoring WebSub content with invalid signature for feed {}",
+                id
+            );
+            Status::NoContent
+        }
+        Ok(PushCheck::Unknown) => Status::NotFound,
+        Err(err) => {
+            log::warn!("Failed to save WebSub content for feed {}: {}", id, err);
+            Status::InternalServerError
+        }
+    }
+}
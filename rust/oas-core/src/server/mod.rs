@@ -2,7 +2,7 @@ use crate::State;
 use clap::Parser;
 use rocket::fairing::{Fairing, Info, Kind};
 use rocket::http::Header;
-use rocket::{catchers, Orbit, Request, Rocket};
+use rocket::{catchers, routes, Orbit, Request, Rocket};
 use rocket_okapi::routes_with_openapi;
 use rocket_okapi::swagger_ui::{make_swagger_ui, SwaggerUIConfig};
 
@@ -72,6 +72,25 @@ pub async fn run_server(mut state: State, opts: ServerOpts) -> anyhow::Result<()
                 handlers::feed::post_feed,
                 handlers::feed::get_feeds,
                 handlers::feed::delete_feed,
+                handlers::feed::get_feed_watchers,
+                handlers::feed::get_feed_watcher,
+                handlers::feed::import_feeds,
+                handlers::feed::export_feeds,
+                handlers::feed::preview_feed,
+                handlers::feed::move_feed,
+                // /oai routes
+                handlers::oai::post_oai_source,
+                handlers::oai::get_oai_source,
+                handlers::oai::get_oai_sources,
+                handlers::oai::harvest_oai_source,
+                // /show routes
+                handlers::show::get_show,
+                handlers::show::get_shows,
+                // /mapping routes
+                handlers::mapping::get_mappings,
+                handlers::mapping::get_mapping,
+                handlers::mapping::put_mapping,
+                handlers::mapping::delete_mapping,
                 // /search routes
                 handlers::search::search,
                 // login routes
@@ -83,16 +102,30 @@ pub async fn run_server(mut state: State, opts: ServerOpts) -> anyhow::Result<()
                 // job routes
                 handlers::job::get_all_jobs,
                 handlers::job::get_job,
+                handlers::job::get_job_events,
                 handlers::job::post_job,
+                handlers::job::post_jobs_bulk,
                 handlers::job::delete_job,
                 handlers::job::work_job,
                 handlers::job::put_job_completed,
                 handlers::job::put_job_failed,
                 handlers::job::put_job_progress,
+                handlers::job::put_job_heartbeat,
+                handlers::job::get_queues,
+                handlers::job::get_queue,
+                handlers::job::put_queue,
                 // changes routes
                 handlers::changes::durable_changes,
             ],
         )
+        // WebSub callbacks are called by hubs and are not part of the API docs.
+        .mount(
+            "/api/v1",
+            routes![
+                handlers::websub::verify_subscription,
+                handlers::websub::push_content,
+            ],
+        )
         .mount(
             "/swagger-ui/",
             make_swagger_ui(&SwaggerUIConfig {